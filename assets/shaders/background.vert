@@ -0,0 +1,28 @@
+#version 450
+
+// position in the window, from (0, 0) at the top left corner to (1, 1) at the bottom right one
+layout(location = 0) out vec2 tex_coords;
+
+// Data passed by the Graphics Handler, after the colors of the gradient
+layout(push_constant) uniform OutputData {
+    layout(offset = 32) uint quarter_turns;
+} output_data;
+
+
+// quarter turns (clockwise) of the whole output, for displays mounted rotated
+vec2 rotate_output(vec2 position) {
+    switch (output_data.quarter_turns) {
+        case 1u: return vec2(-position.y, position.x);
+        case 2u: return -position;
+        case 3u: return vec2(position.y, -position.x);
+        default: return position;
+    }
+}
+
+void main() {
+    // the corners of the window are generated from the vertex index, drawn as a triangle strip
+    vec2 corner = vec2(gl_VertexIndex & 1, gl_VertexIndex >> 1);
+    tex_coords = corner;
+
+    gl_Position = vec4(rotate_output(corner * 2.0 - 1.0), 0.0, 1.0);
+}
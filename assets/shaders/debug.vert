@@ -0,0 +1,45 @@
+#version 450
+
+// world position and color of the line's end
+layout(location = 0) in vec2 position;
+layout(location = 1) in vec4 color;
+
+layout(location = 0) out vec4 frag_color;
+
+// Data passed by the Graphics Handler
+layout(set = 0, binding = 0) uniform readonly GlobalData {
+    uvec4 window_size;
+    vec4 camera_position;
+    vec4 camera_scale;
+} global_data;
+
+
+// quarter turns (clockwise) of the whole output, for displays mounted rotated (stored in window_size.w)
+vec2 rotate_output(vec2 position) {
+    switch (global_data.window_size.w) {
+        case 1u: return vec2(-position.y, position.x);
+        case 2u: return -position;
+        case 3u: return vec2(position.y, -position.x);
+        default: return position;
+    }
+}
+
+void main() {
+    frag_color = color;
+
+    // world units are half pixels from the center of the window, or pixels from its top left corner (window_size.z == 1)
+    bool pixel_top_left = global_data.window_size.z == 1u;
+
+    // rotate the view around the camera (the angle is stored in camera_position.z)
+    float camera_angle = -global_data.camera_position.z;
+    mat2 camera_rotation = mat2(cos(camera_angle), sin(camera_angle), -sin(camera_angle), cos(camera_angle));
+
+    vec2 view_position = camera_rotation * (position - global_data.camera_position.xy);
+
+    vec2 rel_position = view_position / (global_data.window_size.xy * global_data.camera_scale.xy);
+    if (pixel_top_left) {
+        rel_position = rel_position * 2.0 - 1.0;
+    }
+
+    gl_Position = vec4(rotate_output(rel_position), 0.0, 1.0);
+}
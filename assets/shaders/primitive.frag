@@ -4,7 +4,28 @@ layout(location = 0) in vec4 color;
 
 layout(location = 0) out vec4 f_color;
 
+// set when the swapchain has no sRGB format, to encode the (linear) output in the shader instead
+layout(constant_id = 0) const bool encode_srgb = false;
+
+// set by the pipelines writing masks to the stencil, which keep the opaque half of the shape only
+layout(constant_id = 1) const bool alpha_test = false;
+
+
+// same conversion an sRGB attachment applies on write
+vec3 linear_to_srgb(vec3 value) {
+    vec3 low = value * 12.92;
+    vec3 high = 1.055 * pow(max(value, 0.0), vec3(1.0 / 2.4)) - 0.055;
+    return mix(high, low, lessThanEqual(value, vec3(0.0031308)));
+}
 
 void main() {
     f_color = color;
-}
\ No newline at end of file
+
+    if (alpha_test && f_color.a < 0.5) {
+        discard;
+    }
+
+    if (encode_srgb) {
+        f_color.rgb = linear_to_srgb(f_color.rgb);
+    }
+}
@@ -5,12 +5,17 @@ layout(location = 0) in vec2 vert_pos;
 
 // color and texture coordinates for the fragment shader
 layout(location = 0) out vec4 frag_color;
+// position relative to the center of the shape and half its size (used by SDF shapes)
+layout(location = 1) out vec2 local_position;
+layout(location = 2) flat out vec2 half_size;
+layout(location = 3) flat out vec4 corner_radii;
 
 // Data passed by the Primitive object
 layout(set = 0, binding = 0) uniform readonly PrimitiveData {
     vec4 color;
     vec4 global_position;
     vec4 scale;
+    vec4 corner_radii;
 } primitive_data;
 
 // Data passed by the Graphics Handler
@@ -21,12 +26,42 @@ layout(set = 0, binding = 1) uniform readonly GlobalData {
 } global_data;
 
 
+// quarter turns (clockwise) of the whole output, for displays mounted rotated (stored in window_size.w)
+vec2 rotate_output(vec2 position) {
+    switch (global_data.window_size.w) {
+        case 1u: return vec2(-position.y, position.x);
+        case 2u: return -position;
+        case 3u: return vec2(position.y, -position.x);
+        default: return position;
+    }
+}
+
 void main() {
     frag_color = primitive_data.color; // pass the sprite color to the fragment shader
+    local_position = vert_pos * primitive_data.scale.xy;
+    half_size = primitive_data.scale.xy;
+    corner_radii = primitive_data.corner_radii;
+
+    // rotate the scaled vertex around the primitive's origin (the angle is stored in global_position.z)
+    float angle = primitive_data.global_position.z;
+    mat2 rotation = mat2(cos(angle), sin(angle), -sin(angle), cos(angle));
+
+    // world units are half pixels from the center of the window, or pixels from its top left corner (window_size.z == 1)
+    bool pixel_top_left = global_data.window_size.z == 1u;
+    float extent = pixel_top_left ? 0.5 : 1.0;
+
+    vec2 vertex_global_position = primitive_data.global_position.xy + (rotation * (local_position * extent));
+
+    // rotate the view around the camera (the angle is stored in camera_position.z)
+    float camera_angle = -global_data.camera_position.z;
+    mat2 camera_rotation = mat2(cos(camera_angle), sin(camera_angle), -sin(camera_angle), cos(camera_angle));
 
-    vec4 vertex_global_position = primitive_data.global_position + (vec4(vert_pos, 0.0, 0.0) * primitive_data.scale);
+    vec2 view_position = camera_rotation * (vertex_global_position - global_data.camera_position.xy);
 
-    vec2 rel_position = (vertex_global_position.xy - global_data.camera_position.xy) / (global_data.window_size.xy * global_data.camera_scale.xy);
+    vec2 rel_position = view_position / (global_data.window_size.xy * global_data.camera_scale.xy);
+    if (pixel_top_left) {
+        rel_position = rel_position * 2.0 - 1.0;
+    }
 
-    gl_Position = vec4(rel_position, 0.0, 1.0);
+    gl_Position = vec4(rotate_output(rel_position), 0.0, 1.0);
 }
@@ -0,0 +1,52 @@
+#version 450
+
+layout(location = 0) in vec4 color;
+layout(location = 1) in vec2 local_position;
+layout(location = 2) flat in vec2 half_size;
+// top left, top right, bottom right, bottom left (y grows downwards on screen)
+layout(location = 3) flat in vec4 corner_radii;
+
+layout(location = 0) out vec4 f_color;
+
+// set when the swapchain has no sRGB format, to encode the (linear) output in the shader instead
+layout(constant_id = 0) const bool encode_srgb = false;
+
+// set by the pipelines writing masks to the stencil, which keep the opaque half of the shape only
+layout(constant_id = 1) const bool alpha_test = false;
+
+
+// same conversion an sRGB attachment applies on write
+vec3 linear_to_srgb(vec3 value) {
+    vec3 low = value * 12.92;
+    vec3 high = 1.055 * pow(max(value, 0.0), vec3(1.0 / 2.4)) - 0.055;
+    return mix(high, low, lessThanEqual(value, vec3(0.0031308)));
+}
+
+// signed distance from a box centered on the origin, with a different radius for each corner
+float rounded_box_distance(vec2 position, vec2 half_size, vec4 radii) {
+    float radius = position.x > 0.0
+        ? (position.y > 0.0 ? radii.z : radii.y)
+        : (position.y > 0.0 ? radii.w : radii.x);
+    radius = min(radius, min(half_size.x, half_size.y));
+
+    vec2 q = abs(position) - half_size + radius;
+    return min(max(q.x, q.y), 0.0) + length(max(q, 0.0)) - radius;
+}
+
+void main() {
+    float distance = rounded_box_distance(local_position, half_size, corner_radii);
+
+    // blend over about a pixel for smooth edges at any size
+    float edge = fwidth(distance);
+    float coverage = 1.0 - smoothstep(-edge, edge, distance);
+
+    f_color = vec4(color.rgb, color.a * coverage);
+
+    if (alpha_test && f_color.a < 0.5) {
+        discard;
+    }
+
+    if (encode_srgb) {
+        f_color.rgb = linear_to_srgb(f_color.rgb);
+    }
+}
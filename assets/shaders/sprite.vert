@@ -13,6 +13,7 @@ layout(set = 0, binding = 1) uniform readonly SpriteData {
     vec4 global_position;
     vec4 scale;
     uvec4 image_dimensions;
+    uvec4 source_rect;
 } sprite_data;
 
 // Data passed by the Graphics Handler
@@ -23,13 +24,64 @@ layout(set = 0, binding = 2) uniform readonly GlobalData {
 } global_data;
 
 
+// world units are half pixels from the center of the window, or pixels from its top left corner (window_size.z == 1)
+bool pixel_top_left() {
+    return global_data.window_size.z == 1u;
+}
+
+// position of a world point on the window, in normalized device coordinates
+vec2 world_to_screen(vec2 world_position) {
+    // rotate the view around the camera (the angle is stored in camera_position.z)
+    float camera_angle = -global_data.camera_position.z;
+    mat2 camera_rotation = mat2(cos(camera_angle), sin(camera_angle), -sin(camera_angle), cos(camera_angle));
+
+    vec2 view_position = camera_rotation * (world_position - global_data.camera_position.xy);
+
+    vec2 rel_position = view_position / (global_data.window_size.xy * global_data.camera_scale.xy);
+    if (pixel_top_left()) {
+        rel_position = rel_position * 2.0 - 1.0;
+    }
+
+    return rel_position;
+}
+
+// quarter turns (clockwise) of the whole output, for displays mounted rotated (stored in window_size.w)
+vec2 rotate_output(vec2 position) {
+    switch (global_data.window_size.w) {
+        case 1u: return vec2(-position.y, position.x);
+        case 2u: return -position;
+        case 3u: return vec2(position.y, -position.x);
+        default: return position;
+    }
+}
+
 void main() {
     frag_color = sprite_data.color; // pass the sprite color to the fragment shader
-    tex_coords = clamp(vert_pos, 0.0, 1.0); // texture coordinates can't be negative
+    vec2 quad_coords = clamp(vert_pos, 0.0, 1.0); // texture coordinates can't be negative
+
+    // mirrored sprites (negative scale.z and scale.w) read the source rectangle from the opposite side
+    if (sprite_data.scale.z < 0.0) {
+        quad_coords.x = 1.0 - quad_coords.x;
+    }
+    if (sprite_data.scale.w < 0.0) {
+        quad_coords.y = 1.0 - quad_coords.y;
+    }
+
+    // only the source rectangle of the texture is mapped on the quad
+    tex_coords = (sprite_data.source_rect.xy + quad_coords * sprite_data.source_rect.zw) / vec2(sprite_data.image_dimensions.xy);
+
+    float extent = pixel_top_left() ? 0.5 : 1.0;
+    vec2 half_size = vec2(sprite_data.source_rect.zw) * sprite_data.scale.xy * extent;
 
-    vec4 vertex_global_position = sprite_data.global_position + (sprite_data.image_dimensions * vec4(vert_pos, 0.0, 0.0) * sprite_data.scale);
+    vec2 position = world_to_screen(sprite_data.global_position.xy + half_size * vert_pos);
 
-    vec2 rel_position = (vertex_global_position.xy - global_data.camera_position.xy) / (global_data.window_size.xy * global_data.camera_scale.xy);
+    // pixel snapping (image_dimensions.z == 1) moves the whole quad so its first corner lands on a pixel,
+    // measured after the projection so it follows the camera zoom and keeps the quad's size
+    if (sprite_data.image_dimensions.z == 1u) {
+        vec2 corner = world_to_screen(sprite_data.global_position.xy - half_size) + 1.0;
+        vec2 pixel_size = 2.0 / vec2(global_data.window_size.xy);
+        position += round(corner / pixel_size) * pixel_size - corner;
+    }
 
-    gl_Position = vec4(rel_position, 0.0, 1.0);
+    gl_Position = vec4(rotate_output(position), 0.0, 1.0);
 }
@@ -0,0 +1,84 @@
+#version 450
+
+// positions of the vertices
+layout(location = 0) in vec2 vert_pos;
+
+// Data of each sprite of the batch, one instance per sprite
+layout(location = 1) in vec4 color;
+layout(location = 2) in vec4 transform; // global position in xy, scale in zw
+layout(location = 3) in uvec4 image_dimensions;
+layout(location = 4) in uvec4 source_rect;
+
+// color and texture coordinates for the fragment shader
+layout(location = 0) out vec4 frag_color;
+layout(location = 1) out vec2 tex_coords;
+
+// Data passed by the Graphics Handler
+layout(set = 0, binding = 1) uniform readonly GlobalData {
+    uvec4 window_size;
+    vec4 camera_position;
+    vec4 camera_scale;
+} global_data;
+
+
+// world units are half pixels from the center of the window, or pixels from its top left corner (window_size.z == 1)
+bool pixel_top_left() {
+    return global_data.window_size.z == 1u;
+}
+
+// position of a world point on the window, in normalized device coordinates
+vec2 world_to_screen(vec2 world_position) {
+    // rotate the view around the camera (the angle is stored in camera_position.z)
+    float camera_angle = -global_data.camera_position.z;
+    mat2 camera_rotation = mat2(cos(camera_angle), sin(camera_angle), -sin(camera_angle), cos(camera_angle));
+
+    vec2 view_position = camera_rotation * (world_position - global_data.camera_position.xy);
+
+    vec2 rel_position = view_position / (global_data.window_size.xy * global_data.camera_scale.xy);
+    if (pixel_top_left()) {
+        rel_position = rel_position * 2.0 - 1.0;
+    }
+
+    return rel_position;
+}
+
+// quarter turns (clockwise) of the whole output, for displays mounted rotated (stored in window_size.w)
+vec2 rotate_output(vec2 position) {
+    switch (global_data.window_size.w) {
+        case 1u: return vec2(-position.y, position.x);
+        case 2u: return -position;
+        case 3u: return vec2(position.y, -position.x);
+        default: return position;
+    }
+}
+
+void main() {
+    frag_color = color; // pass the sprite color to the fragment shader
+    vec2 quad_coords = clamp(vert_pos, 0.0, 1.0); // texture coordinates can't be negative
+
+    // mirrored sprites (bits 0 and 1 of image_dimensions.w) read the source rectangle from the opposite side
+    if ((image_dimensions.w & 1u) != 0u) {
+        quad_coords.x = 1.0 - quad_coords.x;
+    }
+    if ((image_dimensions.w & 2u) != 0u) {
+        quad_coords.y = 1.0 - quad_coords.y;
+    }
+
+    // only the source rectangle of the texture is mapped on the quad
+    tex_coords = (source_rect.xy + quad_coords * source_rect.zw) / vec2(image_dimensions.xy);
+
+    float extent = pixel_top_left() ? 0.5 : 1.0;
+    vec2 half_size = vec2(source_rect.zw) * transform.zw * extent;
+
+    vec2 position = world_to_screen(transform.xy + half_size * vert_pos);
+
+    // pixel snapping (image_dimensions.z == 1) moves the whole quad so its first corner lands on a pixel,
+    // measured after the projection so it follows the camera zoom and keeps the quad's size
+    if (image_dimensions.z == 1u) {
+        vec2 corner = world_to_screen(transform.xy - half_size) + 1.0;
+        vec2 pixel_size = 2.0 / vec2(global_data.window_size.xy);
+        position += round(corner / pixel_size) * pixel_size - corner;
+    }
+
+    gl_Position = vec4(rotate_output(position), 0.0, 1.0);
+}
@@ -0,0 +1,401 @@
+//! Registry owning textures and sounds behind small `Copy` handles
+
+// standard imports
+use std::borrow::Borrow;
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+// imports from the module
+use super::audio::{AudioHandler, SoundEffect};
+use super::render::texture_loader::{DecodedTexture, TextureLoader};
+use super::vulkan::{GraphicsHandler, Texture, TextureError};
+
+// other imports
+use cgmath::Vector2;
+
+/// Slot index and generation of an asset
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+struct Handle {
+    index: u32,
+    generation: u32,
+}
+
+/// Cheap id of a texture owned by `Assets`
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct TextureHandle(Handle);
+
+/// Cheap id of a sound effect owned by `Assets`
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct SoundHandle(Handle);
+
+/// Time an asset stays loaded once nothing uses it, in case the next scene needs it again
+const DEFAULT_GRACE_PERIOD: Duration = Duration::from_secs(10);
+
+struct Slot<K, T> {
+    generation: u32,
+    asset: Option<(K, T)>,
+    /// When `collect` first found the asset unused, cleared every time it's used
+    unused_since: Cell<Option<Instant>>,
+}
+
+/// Assets of one kind, stored in reusable slots
+/// A handle is the index of its slot with the generation the slot had when the asset was stored:
+/// unloading bumps the generation, so stale handles to a reused slot find nothing instead of another asset
+struct Slots<K, T> {
+    slots: Vec<Slot<K, T>>,
+    /// Indices of the empty slots, reused before growing
+    free: Vec<u32>,
+    /// Handle of each loaded path, to load every file once
+    by_path: HashMap<K, Handle>,
+}
+
+impl<K: Clone + Eq + Hash, T> Slots<K, T> {
+    fn new() -> Self {
+        Self {
+            slots: Vec::new(),
+            free: Vec::new(),
+            by_path: HashMap::new(),
+        }
+    }
+
+    fn find<Q: ?Sized + Eq + Hash>(&self, path: &Q) -> Option<Handle>
+    where
+        K: Borrow<Q>,
+    {
+        self.by_path.get(path).copied()
+    }
+
+    fn insert(&mut self, path: K, asset: T) -> Handle {
+        let index = match self.free.pop() {
+            Some(index) => index,
+            None => {
+                self.slots.push(Slot {
+                    generation: 0,
+                    asset: None,
+                    unused_since: Cell::new(None),
+                });
+                (self.slots.len() - 1) as u32
+            }
+        };
+
+        let slot = &mut self.slots[index as usize];
+        slot.asset = Some((path.clone(), asset));
+        slot.unused_since.set(None);
+
+        let handle = Handle {
+            index,
+            generation: slot.generation,
+        };
+        self.by_path.insert(path, handle);
+
+        handle
+    }
+
+    fn get(&self, handle: Handle) -> Option<&T> {
+        self.slots
+            .get(handle.index as usize)
+            .filter(|slot| slot.generation == handle.generation)
+            .and_then(|slot| {
+                slot.unused_since.set(None);
+                slot.asset.as_ref()
+            })
+            .map(|(_, asset)| asset)
+    }
+
+    fn remove(&mut self, handle: Handle) -> Option<T> {
+        let slot = self
+            .slots
+            .get_mut(handle.index as usize)
+            .filter(|slot| slot.generation == handle.generation)?;
+        let (path, asset) = slot.asset.take()?;
+
+        slot.generation += 1;
+        self.free.push(handle.index);
+        self.by_path.remove(&path);
+
+        Some(asset)
+    }
+
+    /// Remove the assets `in_use` rejected on every call for at least `grace_period`
+    fn collect(&mut self, grace_period: Duration, mut in_use: impl FnMut(&K, &T) -> bool) -> Vec<(K, T)> {
+        let now = Instant::now();
+        let mut unused = Vec::new();
+
+        for (index, slot) in self.slots.iter().enumerate() {
+            let (path, asset) = match &slot.asset {
+                Some(asset) => asset,
+                None => continue,
+            };
+
+            if in_use(path, asset) {
+                slot.unused_since.set(None);
+                continue;
+            }
+
+            let since = slot.unused_since.get().unwrap_or(now);
+            slot.unused_since.set(Some(since));
+            if now.duration_since(since) >= grace_period {
+                unused.push(Handle {
+                    index: index as u32,
+                    generation: slot.generation,
+                });
+            }
+        }
+
+        unused
+            .into_iter()
+            .filter_map(|handle| {
+                let path = self.slots[handle.index as usize].asset.as_ref()?.0.clone();
+                self.remove(handle).map(|asset| (path, asset))
+            })
+            .collect()
+    }
+}
+
+/// Whether something else than its `owners` (the registry and caches) holds an asset
+fn is_shared<T: ?Sized>(asset: &Arc<T>, owners: usize) -> bool {
+    Arc::strong_count(asset) > owners
+}
+
+/// Central registry of the loaded textures and sounds, handing out `Copy` handles to them
+/// Game code can pass handles around instead of paths, and each file is only decoded once
+pub struct Assets {
+    textures: Slots<String, (Texture, Vector2<u32>)>,
+    sounds: Slots<PathBuf, SoundEffect>,
+    /// Textures decoded in the background by `preload_textures`
+    loader: TextureLoader,
+    /// Time an unused asset is kept by `collect_unused`
+    grace_period: Duration,
+}
+
+impl Default for Assets {
+    fn default() -> Self {
+        Self {
+            textures: Slots::new(),
+            sounds: Slots::new(),
+            loader: TextureLoader::new(),
+            grace_period: DEFAULT_GRACE_PERIOD,
+        }
+    }
+}
+
+impl Assets {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Load a PNG texture (through the texture cache), or get the handle of the one already loaded from `path`
+    pub fn load_texture(&mut self, gl_handler: &GraphicsHandler, path: &str) -> Result<TextureHandle, TextureError> {
+        if let Some(handle) = self.textures.find(path) {
+            return Ok(TextureHandle(handle));
+        }
+
+        let texture = gl_handler.try_get_texture(path)?;
+        Ok(TextureHandle(self.textures.insert(path.to_string(), texture)))
+    }
+
+    /// Start decoding a batch of PNG textures on background threads, e.g. behind a loading screen
+    /// They are uploaded by `update`, after which `texture_handle` finds them and the sprites created from
+    /// their paths hit the texture cache
+    pub fn preload_textures(&mut self, paths: &[&str]) {
+        let paths = paths
+            .iter()
+            .filter(|path| self.textures.find(**path).is_none())
+            .map(|path| path.to_string())
+            .collect();
+
+        self.loader.queue(paths);
+    }
+
+    /// Fraction (from 0 to 1) of the preloaded textures already uploaded, for a loading bar
+    /// Counts from the first `preload_textures` call made while nothing was loading
+    pub fn progress(&self) -> f32 {
+        self.loader.progress()
+    }
+
+    /// Whether textures queued by `preload_textures` are still being decoded
+    pub fn is_preloading(&self) -> bool {
+        !self.loader.is_idle()
+    }
+
+    /// Upload the textures decoded in the background since the last call, to be called every frame
+    pub fn update(&mut self, gl_handler: &GraphicsHandler) {
+        let decoded = self.loader.receive();
+
+        for (path, e) in self.upload(gl_handler, decoded) {
+            eprintln!("Couldn't preload texture \'{}\': {}", path, e);
+        }
+    }
+
+    /// Block until every texture queued by `preload_textures` is decoded and uploaded, e.g. during a scene transition
+    /// Uploads wait for the GPU to complete, so the next frame never samples a partial texture
+    /// Sounds are always loaded on the spot, so there are none to wait for
+    /// Returns the paths that couldn't be loaded, with the reason
+    pub fn wait_all(&mut self, gl_handler: &GraphicsHandler) -> Vec<(String, TextureError)> {
+        let decoded = self.loader.receive().into_iter().chain(self.loader.wait()).collect();
+
+        self.upload(gl_handler, decoded)
+    }
+
+    /// Upload decoded textures and give them a handle, returning the ones that failed
+    fn upload(&mut self, gl_handler: &GraphicsHandler, decoded: Vec<DecodedTexture>) -> Vec<(String, TextureError)> {
+        let mut errors = Vec::new();
+
+        for (path, decoded) in decoded {
+            let texture = decoded.and_then(|(data, dimensions)| gl_handler.cache_texture_data(&path, data, dimensions));
+
+            match texture {
+                Ok(texture) => {
+                    if self.textures.find(path.as_str()).is_none() {
+                        self.textures.insert(path, texture);
+                    }
+                }
+                Err(e) => errors.push((path, e)),
+            }
+        }
+
+        errors
+    }
+
+    /// Texture and its dimensions, `None` if it was unloaded
+    pub fn texture(&self, handle: TextureHandle) -> Option<(Texture, Vector2<u32>)> {
+        self.textures.get(handle.0).cloned()
+    }
+
+    /// Handle of a texture already loaded from `path`
+    pub fn texture_handle(&self, path: &str) -> Option<TextureHandle> {
+        self.textures.find(path).map(TextureHandle)
+    }
+
+    /// Release a texture, objects still using it keep it alive until they are dropped
+    pub fn unload_texture(&mut self, handle: TextureHandle) {
+        self.textures.remove(handle.0);
+    }
+
+    /// Load a sound effect, or get the handle of the one already loaded from `path`
+    pub fn load_sound(&mut self, audio: &mut AudioHandler, path: &Path) -> SoundHandle {
+        if let Some(handle) = self.sounds.find(path) {
+            return SoundHandle(handle);
+        }
+
+        let sound = audio.sfx_from_file(path);
+        SoundHandle(self.sounds.insert(path.to_path_buf(), sound))
+    }
+
+    /// Sound effect to play with `AudioHandler::sfx_play`, `None` if it was unloaded
+    pub fn sound(&self, handle: SoundHandle) -> Option<&SoundEffect> {
+        self.sounds.get(handle.0)
+    }
+
+    pub fn sound_handle(&self, path: &Path) -> Option<SoundHandle> {
+        self.sounds.find(path).map(SoundHandle)
+    }
+
+    pub fn unload_sound(&mut self, handle: SoundHandle) {
+        self.sounds.remove(handle.0);
+    }
+
+    pub fn get_grace_period(&self) -> Duration {
+        self.grace_period
+    }
+
+    /// Time an asset must stay unused before `collect_unused` unloads it, zero to unload it on the first call
+    pub fn set_grace_period(&mut self, grace_period: Duration) {
+        self.grace_period = grace_period;
+    }
+
+    /// Unload the assets nobody used for the grace period, to call periodically or on a scene change
+    /// so memory doesn't grow level after level
+    ///
+    /// Handles are `Copy` and can't be counted, so liveness is tracked on the assets themselves:
+    /// - a texture is used while its reference count is higher than the references held by the registry
+    ///   and the texture cache, i.e. while the descriptor set of an object (or a clone given by `texture`) holds it
+    /// - a sound is only borrowed to be played, so it's used while `sound` is called for it between two collections
+    ///
+    /// An asset is unloaded once it was found unused by every call for the whole grace period,
+    /// its stale handles then find nothing
+    pub fn collect_unused(&mut self, gl_handler: &GraphicsHandler) {
+        let textures = self.textures.collect(self.grace_period, |path, (texture, _)| {
+            is_shared(texture, 1 + gl_handler.is_texture_cached(path, texture) as usize)
+        });
+        for (path, (texture, _)) in textures {
+            if gl_handler.is_texture_cached(&path, &texture) {
+                gl_handler.evict_texture(&path);
+            }
+        }
+
+        self.sounds.collect(self.grace_period, |_, _| false);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    /// Registry of stand-in assets, collected like textures held only by the registry
+    fn collect(slots: &mut Slots<String, Arc<u32>>, grace_period: Duration) -> Vec<String> {
+        slots
+            .collect(grace_period, |_, asset| is_shared(asset, 1))
+            .into_iter()
+            .map(|(path, _)| path)
+            .collect()
+    }
+
+    #[test]
+    fn unused_asset_is_freed() {
+        let mut slots = Slots::new();
+        let handle = slots.insert(String::from("a.png"), Arc::new(1));
+        let user = slots.get(handle).cloned().unwrap();
+
+        assert!(collect(&mut slots, Duration::from_secs(0)).is_empty());
+
+        drop(user);
+        assert_eq!(collect(&mut slots, Duration::from_secs(0)), vec![String::from("a.png")]);
+        assert!(slots.get(handle).is_none());
+        assert!(slots.find("a.png").is_none());
+    }
+
+    #[test]
+    fn unused_asset_is_kept_for_the_grace_period() {
+        let grace_period = Duration::from_millis(20);
+        let mut slots = Slots::new();
+        let handle = slots.insert(String::from("a.png"), Arc::new(1));
+
+        // first found unused now, freed once the grace period is over
+        assert!(collect(&mut slots, grace_period).is_empty());
+        thread::sleep(grace_period * 2);
+        assert_eq!(collect(&mut slots, grace_period), vec![String::from("a.png")]);
+        assert!(slots.get(handle).is_none());
+    }
+
+    #[test]
+    fn using_an_asset_restarts_its_grace_period() {
+        let grace_period = Duration::from_millis(20);
+        let mut slots = Slots::new();
+        let handle = slots.insert(String::from("a.png"), Arc::new(1));
+
+        assert!(collect(&mut slots, grace_period).is_empty());
+        thread::sleep(grace_period * 2);
+        assert!(slots.get(handle).is_some());
+
+        assert!(collect(&mut slots, grace_period).is_empty());
+        assert!(slots.get(handle).is_some());
+    }
+
+    #[test]
+    fn stale_handle_finds_nothing_in_a_reused_slot() {
+        let mut slots = Slots::new();
+        let old = slots.insert(String::from("a.png"), Arc::new(1));
+        collect(&mut slots, Duration::from_secs(0));
+
+        let new = slots.insert(String::from("b.png"), Arc::new(2));
+        assert_eq!(new.index, old.index);
+        assert!(slots.get(old).is_none());
+        assert_eq!(slots.get(new).map(|asset| **asset), Some(2));
+    }
+}
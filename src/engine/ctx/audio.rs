@@ -1,34 +1,164 @@
 // std imports
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 // SDL2 imports
-use sdl2::mixer::{self, Channel, Chunk, Music};
+use sdl2::mixer::{self, Channel, Chunk, LoaderRWops, Music};
+use sdl2::rwops::RWops;
 
+// other imports
+use cgmath::Vector2;
+use rand::Rng;
+
+/// Horizontal distance (in world units) at which a positioned sound only plays on one speaker
+pub const FULL_PAN_DISTANCE: f32 = 500.0;
+
+/// Options used to open the audio device
+#[derive(Copy, Clone, Debug)]
+pub struct AudioConfig {
+    /// Sample rate (in Hz)
+    pub frequency: i32,
+    /// Sample format, one of the `sdl2::mixer::AUDIO_*` constants
+    pub format: mixer::AudioFormat,
+    /// Output channels, 1 for mono and 2 for stereo
+    pub channels: i32,
+    /// Samples per mixing chunk, lower values reduce latency but may crackle
+    pub chunk_size: i32,
+    /// Number of sound effects that can play at the same time
+    pub mixing_channels: i32,
+}
+
+impl Default for AudioConfig {
+    fn default() -> Self {
+        Self {
+            frequency: 44100,
+            format: mixer::AUDIO_U16,
+            channels: 2,
+            chunk_size: 1024,
+            mixing_channels: 5,
+        }
+    }
+}
 
 /// Component of the CtxHandler to handle all calls to SDL_Mixer's API
 pub struct AudioHandler {
     mix_context: mixer::Sdl2MixerContext,
     music: Option<Box<Music<'static>>>,
+    /// Track waiting for the current one to fade out, with its fade in duration (in milliseconds)
+    /// SDL_mixer only plays one music at a time, so a crossfade is a fade out followed by a fade in
+    pending_music: Option<(Box<Music<'static>>, i32)>,
     general_channel: Channel,
+
+    playlist: Option<MusicPlaylist>,
+    music_ramp: Option<VolumeRamp>,
+
+    /// Pause the audio while the window doesn't have the focus
+    mute_on_focus_loss: bool,
+    /// Set while paused by a focus loss, with whether the music was already paused before
+    focus_paused: Option<bool>,
+}
+
+/// Gradual change of the music volume, advanced by `AudioHandler::update`
+struct VolumeRamp {
+    from: i32,
+    to: i32,
+    duration: f32,
+    elapsed: f32,
+}
+
+impl VolumeRamp {
+    fn current(&self) -> i32 {
+        let t = (self.elapsed / self.duration).min(1.0);
+        self.from + ((self.to - self.from) as f32 * t).round() as i32
+    }
 }
 
 impl AudioHandler {
-    pub fn new() -> AudioHandler{
+    pub fn new() -> AudioHandler {
+        Self::with_config(&AudioConfig::default())
+    }
+
+    pub fn with_config(config: &AudioConfig) -> AudioHandler {
         let mut init_flags = mixer::InitFlag::empty();
         init_flags.set(mixer::InitFlag::OGG, true);
 
         let mix_context = mixer::init(init_flags).expect("Couldn't init SDL2 Mixer context");
 
-        mixer::allocate_channels(5);
+        mixer::allocate_channels(config.mixing_channels);
 
-        mixer::open_audio(44100, mixer::AUDIO_U16, 2, 1024).expect("Couldn't open audio on SDL2 Mixer Context");
+        mixer::open_audio(config.frequency, config.format, config.channels, config.chunk_size).expect("Couldn't open audio on SDL2 Mixer Context");
 
         let general_channel = Channel::all();
 
         AudioHandler {
             mix_context,
             music: None,
+            pending_music: None,
             general_channel,
+
+            playlist: None,
+            music_ramp: None,
+
+            mute_on_focus_loss: false,
+            focus_paused: None,
+        }
+    }
+
+    /// Pause the music and the sound effects while the window is in the background, off by default
+    pub fn set_mute_on_focus_loss(&mut self, mute: bool) {
+        self.mute_on_focus_loss = mute;
+
+        if !mute {
+            self.focus_changed(true);
+        }
+    }
+
+    pub fn get_mute_on_focus_loss(&self) -> bool {
+        self.mute_on_focus_loss
+    }
+
+    /// Called by `CtxHandler::check_events` when the window gains or loses the focus
+    /// Sound effects paused by hand are resumed with the others when the focus comes back
+    pub fn focus_changed(&mut self, focused: bool) {
+        if focused {
+            if let Some(music_was_paused) = self.focus_paused.take() {
+                if !music_was_paused {
+                    Music::resume();
+                }
+                Channel::all().resume();
+            }
+        } else if self.mute_on_focus_loss && self.focus_paused.is_none() {
+            self.focus_paused = Some(Music::is_paused());
+            Music::pause();
+            Channel::all().pause();
+        }
+    }
+
+    /// Frame-by-frame update of the audio state (auto-advances the playlist when a track ends,
+    /// starts the track of a crossfade once the previous one faded out, and moves the volume ramp forward by `dt` seconds)
+    pub fn update(&mut self, dt: f32) {
+        if self.pending_music.is_some() && !Music::is_playing() {
+            if let Some((music, ms)) = self.pending_music.take() {
+                self.music_fade_in(music, ms);
+            }
+        }
+
+        if let Some(ramp) = &mut self.music_ramp {
+            ramp.elapsed += dt;
+            Music::set_volume(ramp.current());
+
+            if ramp.elapsed >= ramp.duration {
+                self.music_ramp = None;
+            }
+        }
+
+        let track_ended = match &self.playlist {
+            Some(playlist) => playlist.playing && !Music::is_playing(),
+            None => false,
+        };
+
+        if track_ended {
+            let next = self.playlist.as_mut().and_then(|p| p.advance(true));
+            self.playlist_play_index(next);
         }
     }
 
@@ -49,11 +179,31 @@ impl AudioHandler {
         SoundEffect {data: new_chunk, volume: 30,}
     }
 
+    /// Load a SFX from a file embedded in the executable (e.g. with `include_bytes!`)
+    pub fn sfx_from_bytes(&mut self, bytes: &'static [u8]) -> SoundEffect {
+        // the chunk gets decoded while loading, so the bytes are only needed here
+        let new_chunk = match RWops::from_bytes(bytes).and_then(|rwops| rwops.load_wav()) {
+            Ok(chunk) => {
+                Some(Box::new(chunk))
+            },
+            Err(e) => {
+                eprintln!("Couldn't load SFX from memory: {}", e);
+                None
+            },
+        };
+
+        SoundEffect {data: new_chunk, volume: 30,}
+    }
+
     pub fn sfx_play(&self, sfx: &SoundEffect) -> Option<Channel> {
         if let Some(chunk_box) = &sfx.data {
             match self.general_channel.play(chunk_box.as_ref(), 0) {
                 Ok(c) => {
                     c.set_volume(30);
+                    // the channel may have been panned by a previous sound
+                    if let Err(e) = c.unset_panning() {
+                        eprintln!("Couldn't reset SFX panning: {}", e);
+                    }
                     Some(c)
                 },
                 Err(e) => {
@@ -68,6 +218,31 @@ impl AudioHandler {
         }
     }
 
+    /// Play a SFX with the volume of each speaker (255 is full volume, 0 is silent)
+    /// Returns the channel it plays on, to stop it later
+    pub fn sfx_play_panned(&self, sfx: &SoundEffect, left: u8, right: u8) -> Option<Channel> {
+        let channel = self.sfx_play(sfx)?;
+
+        if let Err(e) = channel.set_panning(left, right) {
+            eprintln!("Couldn't pan SFX: {}", e);
+        }
+
+        Some(channel)
+    }
+
+    /// Play a SFX panned by where its source is, horizontally, from the listener (usually the camera or the player)
+    /// Sources `FULL_PAN_DISTANCE` world units away or more only play on one speaker
+    pub fn sfx_play_positioned(&self, sfx: &SoundEffect, source: Vector2<f32>, listener: Vector2<f32>) -> Option<Channel> {
+        // -1 is all the way to the left, 1 all the way to the right
+        let pan = ((source.x - listener.x) / FULL_PAN_DISTANCE).clamp(-1.0, 1.0);
+
+        // the speaker on the side of the source keeps the full volume, so centered sounds don't get quieter
+        let left = (255.0 * (1.0 - pan).min(1.0)).round() as u8;
+        let right = (255.0 * (1.0 + pan).min(1.0)).round() as u8;
+
+        self.sfx_play_panned(sfx, left, right)
+    }
+
     //--------
     // MUSIC
     //--------
@@ -75,7 +250,7 @@ impl AudioHandler {
         match Music::from_file(path) {
             Ok(music) => {
                 self.music = Some(Box::new(music));
-                self.music_set_volume(30);
+                self.apply_loaded_music_volume();
                 Ok(())
             },
             Err(e) => {
@@ -85,6 +260,62 @@ impl AudioHandler {
         }
     }
 
+    /// Load the music from a file embedded in the executable (e.g. with `include_bytes!`)
+    /// Music is streamed while playing, which is why the bytes must live for the whole program
+    pub fn music_from_bytes(&mut self, bytes: &'static [u8]) -> Result<(), ()> {
+        match Music::from_static_bytes(bytes) {
+            Ok(music) => {
+                self.music = Some(Box::new(music));
+                self.apply_loaded_music_volume();
+                Ok(())
+            },
+            Err(e) => {
+                eprintln!("Couldn't load music from memory: {}", e);
+                Err(())
+            },
+        }
+    }
+
+    /// Fade the current music out over `ms` milliseconds, then fade in the one loaded from `path` over the same time
+    /// The new track loops forever and starts from the `update` following the end of the fade out,
+    /// stopping the playlist if one was playing
+    pub fn music_crossfade(&mut self, path: &Path, ms: i32) -> Result<(), String> {
+        let music = Music::from_file(path)
+            .map_err(|e| format!("Couldn't load music from file \'{}\': {}", path.display(), e))?;
+
+        if let Some(playlist) = &mut self.playlist {
+            playlist.playing = false;
+        }
+        // the fades change the volume on their own
+        self.music_ramp = None;
+
+        // paused music would never finish fading out
+        if ms <= 0 || !Music::is_playing() || Music::is_paused() {
+            Music::halt();
+            self.pending_music = None;
+            self.music_fade_in(Box::new(music), ms);
+            return Ok(());
+        }
+
+        Music::fade_out(ms)?;
+        // the old track is kept in `music` until it stops, dropping it would cut the fade out
+        self.pending_music = Some((Box::new(music), ms));
+
+        Ok(())
+    }
+
+    /// Whether a crossfade is still waiting for the previous track to fade out
+    pub fn is_crossfading(&self) -> bool {
+        self.pending_music.is_some()
+    }
+
+    fn music_fade_in(&mut self, music: Box<Music<'static>>, ms: i32) {
+        if let Err(e) = music.fade_in(-1, ms.max(0)) {
+            eprintln!("Couldn't fade in music: {}", e);
+        }
+        self.music = Some(music);
+    }
+
     pub fn music_play(&self, loops: i32) -> Result<(), String> {
         if let Some(m) = &self.music {
             m.play(loops)?;
@@ -105,17 +336,217 @@ impl AudioHandler {
         Music::rewind();
     }
 
-    pub fn music_stop(&self) {
+    pub fn music_stop(&mut self) {
+        // stopping by hand shouldn't make the playlist jump to the next track
+        if let Some(playlist) = &mut self.playlist {
+            playlist.playing = false;
+        }
+        self.pending_music = None;
+
         Music::halt();
     }
 
+    /// Stop the music and every sound effect still playing
+    pub fn halt_all(&mut self) {
+        self.music_stop();
+        Channel::all().halt();
+    }
+
     pub fn music_get_volume(&self) -> i32 {
         Music::get_volume()
     }
 
-    pub fn music_set_volume(&self, volume: i32) {
+    /// Set the music volume at once (from 0 to 128), cancelling any volume ramp
+    pub fn music_set_volume(&mut self, volume: i32) {
+        self.music_ramp = None;
+        Music::set_volume(volume);
+    }
+
+    // a freshly loaded track starts at the default volume, unless a ramp is running (e.g. a playlist fading)
+    fn apply_loaded_music_volume(&self) {
+        let volume = self.music_ramp.as_ref().map_or(30, VolumeRamp::current);
         Music::set_volume(volume);
     }
+
+    /// Move the music volume to `target` (clamped from 0 to 128) over `over_secs` seconds, without audible pops
+    /// The ramp is driven by `update`, and replaces the one in progress starting from the current volume
+    pub fn music_ramp_volume(&mut self, target: i32, over_secs: f32) {
+        let target = target.max(0).min(mixer::MAX_VOLUME);
+
+        if over_secs <= 0.0 {
+            self.music_set_volume(target);
+            return;
+        }
+
+        self.music_ramp = Some(VolumeRamp {
+            from: Music::get_volume(),
+            to: target,
+            duration: over_secs,
+            elapsed: 0.0,
+        });
+    }
+
+    /// Whether a volume ramp is still in progress
+    pub fn is_music_ramping(&self) -> bool {
+        self.music_ramp.is_some()
+    }
+
+    //-----------
+    // PLAYLIST
+    //-----------
+    /// Replace the current playlist and start playing its first track
+    pub fn set_playlist(&mut self, tracks: Vec<PathBuf>, mode: PlaylistMode) {
+        Music::halt();
+
+        let mut playlist = MusicPlaylist::new(tracks, mode);
+        let first = playlist.advance(false);
+        self.playlist = Some(playlist);
+
+        self.playlist_play_index(first);
+    }
+
+    /// Skip to the next track of the playlist (RepeatOne moves on as well when skipping by hand)
+    pub fn next_track(&mut self) {
+        let next = self.playlist.as_mut().and_then(|p| p.advance(false));
+        self.playlist_play_index(next);
+    }
+
+    /// Go back to the previously played track of the playlist
+    pub fn prev_track(&mut self) {
+        let prev = self.playlist.as_mut().and_then(|p| p.go_back());
+        self.playlist_play_index(prev);
+    }
+
+    pub fn get_playlist(&self) -> Option<&MusicPlaylist> {
+        self.playlist.as_ref()
+    }
+
+    /// Load and play a track of the playlist, skipping the ones that fail to load
+    fn playlist_play_index(&mut self, mut index: Option<usize>) {
+        let tracks_count = match &self.playlist {
+            Some(playlist) => playlist.tracks.len(),
+            None => return,
+        };
+
+        for _ in 0..tracks_count {
+            let i = match index {
+                Some(i) => i,
+                None => break,
+            };
+
+            let path = self.playlist.as_ref().unwrap().tracks[i].clone();
+
+            if self.music_from_file(&path).is_ok() && self.music_play(1).is_ok() {
+                self.playlist.as_mut().unwrap().playing = true;
+                return;
+            }
+
+            index = self.playlist.as_mut().unwrap().advance(false);
+        }
+
+        self.playlist.as_mut().unwrap().playing = false;
+    }
+}
+
+/// Order in which a MusicPlaylist moves through its tracks
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PlaylistMode {
+    /// Play the tracks in order, starting again from the first one after the last
+    Sequential,
+    /// Pick a random track (different from the current one) every time
+    Shuffle,
+    /// Keep playing the current track until skipped by hand
+    RepeatOne,
+}
+
+/// Ordered list of music tracks, driven by the AudioHandler
+pub struct MusicPlaylist {
+    tracks: Vec<PathBuf>,
+    mode: PlaylistMode,
+    current: Option<usize>,
+    history: Vec<usize>,
+    playing: bool,
+}
+
+impl MusicPlaylist {
+    fn new(tracks: Vec<PathBuf>, mode: PlaylistMode) -> Self {
+        Self {
+            tracks,
+            mode,
+            current: None,
+            history: Vec::new(),
+            playing: false,
+        }
+    }
+
+    pub fn get_tracks(&self) -> &[PathBuf] {
+        &self.tracks
+    }
+
+    pub fn get_mode(&self) -> PlaylistMode {
+        self.mode
+    }
+
+    pub fn set_mode(&mut self, mode: PlaylistMode) {
+        self.mode = mode;
+    }
+
+    /// Index of the track currently loaded
+    pub fn get_current(&self) -> Option<usize> {
+        self.current
+    }
+
+    /// Move to the next track, `automatic` is true when the previous track just ended on its own
+    fn advance(&mut self, automatic: bool) -> Option<usize> {
+        if self.tracks.is_empty() {
+            return None;
+        }
+
+        let next = match (self.current, self.mode) {
+            (Some(current), PlaylistMode::RepeatOne) if automatic => current,
+            (Some(current), PlaylistMode::Shuffle) if self.tracks.len() > 1 => {
+                // pick among the other tracks, then shift past the current one
+                let pick = rand::thread_rng().gen_range(0..self.tracks.len() - 1);
+                if pick >= current {
+                    pick + 1
+                } else {
+                    pick
+                }
+            }
+            (None, PlaylistMode::Shuffle) => rand::thread_rng().gen_range(0..self.tracks.len()),
+            (Some(current), _) => (current + 1) % self.tracks.len(),
+            (None, _) => 0,
+        };
+
+        if let Some(current) = self.current {
+            if current != next {
+                self.history.push(current);
+            }
+        }
+        self.current = Some(next);
+
+        Some(next)
+    }
+
+    /// Move back to the last track played before the current one
+    fn go_back(&mut self) -> Option<usize> {
+        if self.tracks.is_empty() {
+            return None;
+        }
+
+        let prev = match self.history.pop() {
+            Some(prev) => prev,
+            None => match self.current {
+                Some(current) if self.mode != PlaylistMode::Shuffle => {
+                    (current + self.tracks.len() - 1) % self.tracks.len()
+                }
+                current => current.unwrap_or(0),
+            },
+        };
+        self.current = Some(prev);
+
+        Some(prev)
+    }
 }
 
 pub struct SoundEffect {
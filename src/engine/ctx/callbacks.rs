@@ -0,0 +1,112 @@
+//! Callbacks run by `CtxHandler::check_events`, as an alternative to polling for one-off UI wiring
+
+// standard imports
+use std::collections::HashMap;
+use std::path::Path;
+
+// SDL2 imports
+use sdl2::keyboard::Keycode;
+
+// imports from the module
+use super::QuitReason;
+
+/// Id of a registered callback, to remove it later
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct CallbackId(u32);
+
+/// Callbacks by event, each list in registration order
+/// They can't access the CtxHandler while it's reading events: share state with them through `Rc<RefCell<_>>`
+/// (or `Rc<Cell<_>>`) and act on it in the game loop
+#[derive(Default)]
+pub struct EventCallbacks {
+    next_id: u32,
+
+    key_down: HashMap<Keycode, Vec<(CallbackId, Box<dyn FnMut()>)>>,
+    key_up: HashMap<Keycode, Vec<(CallbackId, Box<dyn FnMut()>)>>,
+    quit: Vec<(CallbackId, Box<dyn FnMut(QuitReason)>)>,
+    resize: Vec<(CallbackId, Box<dyn FnMut(u32, u32)>)>,
+    file_dropped: Vec<(CallbackId, Box<dyn FnMut(&Path)>)>,
+}
+
+impl EventCallbacks {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn new_id(&mut self) -> CallbackId {
+        self.next_id += 1;
+        CallbackId(self.next_id)
+    }
+
+    pub fn on_key_down(&mut self, key: Keycode, callback: Box<dyn FnMut()>) -> CallbackId {
+        let id = self.new_id();
+        self.key_down.entry(key).or_default().push((id, callback));
+        id
+    }
+
+    pub fn on_key_up(&mut self, key: Keycode, callback: Box<dyn FnMut()>) -> CallbackId {
+        let id = self.new_id();
+        self.key_up.entry(key).or_default().push((id, callback));
+        id
+    }
+
+    pub fn on_quit(&mut self, callback: Box<dyn FnMut(QuitReason)>) -> CallbackId {
+        let id = self.new_id();
+        self.quit.push((id, callback));
+        id
+    }
+
+    pub fn on_resize(&mut self, callback: Box<dyn FnMut(u32, u32)>) -> CallbackId {
+        let id = self.new_id();
+        self.resize.push((id, callback));
+        id
+    }
+
+    pub fn on_file_dropped(&mut self, callback: Box<dyn FnMut(&Path)>) -> CallbackId {
+        let id = self.new_id();
+        self.file_dropped.push((id, callback));
+        id
+    }
+
+    /// Unregister a callback, does nothing if it was already removed
+    pub fn remove(&mut self, id: CallbackId) {
+        for callbacks in self.key_down.values_mut().chain(self.key_up.values_mut()) {
+            callbacks.retain(|(i, _)| *i != id);
+        }
+        self.quit.retain(|(i, _)| *i != id);
+        self.resize.retain(|(i, _)| *i != id);
+        self.file_dropped.retain(|(i, _)| *i != id);
+    }
+
+    pub fn clear(&mut self) {
+        self.key_down.clear();
+        self.key_up.clear();
+        self.quit.clear();
+        self.resize.clear();
+        self.file_dropped.clear();
+    }
+
+    pub fn key_down(&mut self, key: Keycode) {
+        if let Some(callbacks) = self.key_down.get_mut(&key) {
+            callbacks.iter_mut().for_each(|(_, callback)| callback());
+        }
+    }
+
+    pub fn key_up(&mut self, key: Keycode) {
+        if let Some(callbacks) = self.key_up.get_mut(&key) {
+            callbacks.iter_mut().for_each(|(_, callback)| callback());
+        }
+    }
+
+    pub fn quit(&mut self, reason: QuitReason) {
+        self.quit.iter_mut().for_each(|(_, callback)| callback(reason));
+    }
+
+    pub fn resize(&mut self, width: u32, height: u32) {
+        self.resize.iter_mut().for_each(|(_, callback)| callback(width, height));
+    }
+
+    pub fn file_dropped(&mut self, path: &Path) {
+        self.file_dropped.iter_mut().for_each(|(_, callback)| callback(path));
+    }
+}
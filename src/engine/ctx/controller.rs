@@ -0,0 +1,222 @@
+// standard imports
+use std::collections::{HashMap, HashSet};
+
+// SDL2 imports
+use sdl2::controller::{Axis, Button, GameController};
+use sdl2::{GameControllerSubsystem, Sdl};
+
+/// Region around the center of an axis where its value is read as 0
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Deadzone {
+    /// Each axis is cut off on its own, simple but makes diagonals snap to the axes
+    Axial(f32),
+    /// The stick is cut off by its distance from the center, keeping the direction intact
+    /// Triggers have no other axis to pair with, so they fall back to the axial cut off
+    Radial(f32),
+}
+
+impl Default for Deadzone {
+    fn default() -> Self {
+        Deadzone::Radial(0.15)
+    }
+}
+
+/// Component of the CtxHandler to handle the game controllers
+pub struct ControllerHandler {
+    controller_subsystem: GameControllerSubsystem,
+    /// Open controllers by instance id
+    controllers: HashMap<u32, GameController>,
+
+    default_deadzone: Deadzone,
+    deadzones: HashMap<Axis, Deadzone>,
+
+    /// Buttons held down by instance id, as of the last `CtxHandler::check_events` and the one before it
+    down: HashSet<(u32, Button)>,
+    previous: HashSet<(u32, Button)>,
+    /// Buttons pressed and released again between two frames
+    tapped: HashSet<(u32, Button)>,
+}
+
+impl ControllerHandler {
+    pub fn new(ctx: &Sdl) -> ControllerHandler {
+        let controller_subsystem = ctx
+            .game_controller()
+            .expect("Couldn't obtain Game Controller Subsystem from SDL2 context");
+
+        let mut controllers = HashMap::new();
+        let joysticks = controller_subsystem.num_joysticks().unwrap_or(0);
+        for index in (0..joysticks).filter(|&i| controller_subsystem.is_game_controller(i)) {
+            match controller_subsystem.open(index) {
+                Ok(controller) => {
+                    controllers.insert(controller.instance_id(), controller);
+                }
+                Err(e) => eprintln!("Couldn't open game controller {}: {}", index, e),
+            }
+        }
+
+        ControllerHandler {
+            controller_subsystem,
+            controllers,
+
+            default_deadzone: Deadzone::default(),
+            deadzones: HashMap::new(),
+
+            down: HashSet::new(),
+            previous: HashSet::new(),
+            tapped: HashSet::new(),
+        }
+    }
+
+    /// Open a controller plugged in while the game runs, from its joystick index
+    /// SDL also reports the controllers connected at startup, which are already open
+    pub fn device_added(&mut self, index: u32) {
+        match self.controller_subsystem.open(index) {
+            Ok(controller) => {
+                self.controllers.entry(controller.instance_id()).or_insert(controller);
+            }
+            Err(e) => eprintln!("Couldn't open game controller {}: {}", index, e),
+        }
+    }
+
+    /// Close a disconnected controller, releasing its buttons
+    pub fn device_removed(&mut self, which: u32) {
+        self.controllers.remove(&which);
+        self.down.retain(|(id, _)| *id != which);
+    }
+
+    /// Keep the buttons down as the previous frame's, before reading the new events
+    pub fn next_frame(&mut self) {
+        self.previous.clone_from(&self.down);
+        self.tapped.clear();
+    }
+
+    pub fn button_down(&mut self, which: u32, button: Button) {
+        self.down.insert((which, button));
+    }
+
+    pub fn button_up(&mut self, which: u32, button: Button) {
+        if self.down.remove(&(which, button)) && !self.previous.contains(&(which, button)) {
+            self.tapped.insert((which, button));
+        }
+    }
+
+    /// Whether the button went down since the previous frame
+    pub fn button_just_pressed(&self, which: u32, button: Button) -> bool {
+        let key = (which, button);
+        (self.down.contains(&key) && !self.previous.contains(&key)) || self.tapped.contains(&key)
+    }
+
+    /// Whether the button went up since the previous frame
+    pub fn button_just_released(&self, which: u32, button: Button) -> bool {
+        let key = (which, button);
+        (self.previous.contains(&key) && !self.down.contains(&key)) || self.tapped.contains(&key)
+    }
+
+    /// Instance ids of the open controllers
+    pub fn get_controller_ids(&self) -> Vec<u32> {
+        self.controllers.keys().copied().collect()
+    }
+
+    pub fn get_deadzone(&self, axis: Axis) -> Deadzone {
+        *self.deadzones.get(&axis).unwrap_or(&self.default_deadzone)
+    }
+
+    /// Set the deadzone of a single axis
+    pub fn set_deadzone(&mut self, axis: Axis, deadzone: Deadzone) {
+        self.deadzones.insert(axis, deadzone);
+    }
+
+    /// Set the deadzone used by every axis without a specific one
+    pub fn set_default_deadzone(&mut self, deadzone: Deadzone) {
+        self.default_deadzone = deadzone;
+    }
+
+    /// Value of an axis from -1.0 to 1.0 (0.0 to 1.0 for triggers), after the deadzone
+    /// Disconnected controllers always return 0.0
+    pub fn axis(&self, which: u32, axis: Axis) -> f32 {
+        let controller = match self.controllers.get(&which) {
+            Some(controller) => controller,
+            None => return 0.0,
+        };
+
+        let value = normalize_axis(controller.axis(axis));
+
+        match (self.get_deadzone(axis), paired_axis(axis)) {
+            (Deadzone::Radial(threshold), Some(other)) => {
+                let other = normalize_axis(controller.axis(other));
+                let length = (value * value + other * other).sqrt();
+                if length <= threshold {
+                    return 0.0;
+                }
+
+                // rescale the length so the output starts from 0 at the edge of the deadzone
+                let scaled = ((length - threshold) / (1.0 - threshold)).min(1.0);
+                value / length * scaled
+            }
+            (Deadzone::Radial(threshold), None) | (Deadzone::Axial(threshold), _) => {
+                if value.abs() <= threshold {
+                    return 0.0;
+                }
+
+                value.signum() * ((value.abs() - threshold) / (1.0 - threshold)).min(1.0)
+            }
+        }
+    }
+
+    /// Whether a button is pressed, always false for disconnected controllers
+    pub fn button(&self, which: u32, button: Button) -> bool {
+        self.controllers
+            .get(&which)
+            .map_or(false, |controller| controller.button(button))
+    }
+
+    /// Rumble a controller, with intensities from 0 to 0xFFFF for the low and high frequency motors
+    /// Does nothing on controllers without rumble support
+    pub fn rumble(&mut self, which: u32, low_freq: u16, high_freq: u16, duration_ms: u32) {
+        if let Some(controller) = self.controllers.get_mut(&which) {
+            // SDL returns an error for controllers without haptics, which isn't worth reporting
+            let _ = controller.set_rumble(low_freq, high_freq, duration_ms);
+        }
+    }
+
+    /// Rumble every open controller
+    pub fn rumble_all(&mut self, low_freq: u16, high_freq: u16, duration_ms: u32) {
+        for controller in self.controllers.values_mut() {
+            let _ = controller.set_rumble(low_freq, high_freq, duration_ms);
+        }
+    }
+
+    /// Read an axis like a D-pad: -1, 0 or 1 depending on which side passes `threshold`
+    pub fn axis_as_dpad(&self, which: u32, axis: Axis, threshold: f32) -> i8 {
+        let value = self.axis(which, axis);
+
+        if value >= threshold {
+            1
+        } else if value <= -threshold {
+            -1
+        } else {
+            0
+        }
+    }
+}
+
+/// Map a raw SDL axis value to -1.0..1.0
+fn normalize_axis(value: i16) -> f32 {
+    // the negative range is one step bigger than the positive one
+    if value < 0 {
+        value as f32 / 32768.0
+    } else {
+        value as f32 / 32767.0
+    }
+}
+
+/// Other axis of the same stick, if any
+fn paired_axis(axis: Axis) -> Option<Axis> {
+    match axis {
+        Axis::LeftX => Some(Axis::LeftY),
+        Axis::LeftY => Some(Axis::LeftX),
+        Axis::RightX => Some(Axis::RightY),
+        Axis::RightY => Some(Axis::RightX),
+        Axis::TriggerLeft | Axis::TriggerRight => None,
+    }
+}
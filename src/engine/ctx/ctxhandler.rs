@@ -1,12 +1,38 @@
+// standard imports
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
 // SDL2 imports
-use sdl2::event::{Event, WindowEvent};
+use sdl2::controller::{Axis, Button};
+use sdl2::event::{Event, EventType, WindowEvent};
+use sdl2::keyboard::{Keycode, Scancode};
+use sdl2::rect::Rect;
 use sdl2::EventPump;
 use sdl2::Sdl;
 
 // imports from the module
-use super::audio::AudioHandler;
-use super::video::VideoHandler;
-use super::FPSHandler;
+use super::assets::{Assets, SoundHandle, TextureHandle};
+use super::audio::{AudioConfig, AudioHandler};
+use super::callbacks::{CallbackId, EventCallbacks};
+use super::controller::ControllerHandler;
+use super::input::{KeyboardState, NavAction, RepeatTimer, TextInput};
+use super::video::{VideoHandler, WindowConfig};
+use super::vulkan::{GraphicsConfig, GraphicsInitError};
+use super::{FPSHandler, SpriteObject, Time};
+
+/// What asked the program to close
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum QuitReason {
+    /// The window manager's close button (or shortcut) was used
+    WindowClosed,
+    /// The OS asked the program to quit, e.g. Ctrl+C, SIGTERM or a logout
+    System,
+    /// The game itself asked to quit with `CtxHandler::quit`
+    Requested,
+}
+
+/// Callback deciding whether a quit request is accepted (true) or ignored (false)
+pub type QuitHook = Box<dyn FnMut(QuitReason) -> bool>;
 
 /// Main handler to manage calls to the SDL2 API
 pub struct CtxHandler {
@@ -15,71 +41,458 @@ pub struct CtxHandler {
     pub video: VideoHandler,
     pub fps_manager: FPSHandler,
     pub audio: AudioHandler,
+    pub controllers: ControllerHandler,
+    /// Textures and sounds shared by handle
+    pub assets: Assets,
 
     must_break: bool,
+    quit_reason: Option<QuitReason>,
+    quit_hook: Option<QuitHook>,
+
+    /// Files dropped on a window during the last `check_events`, with the id of that window
+    dropped_files: Vec<(u32, PathBuf)>,
+
+    /// Keys held during this frame and the previous one
+    keyboard: KeyboardState,
+    nav_timers: HashMap<NavAction, RepeatTimer>,
+    /// Text typed while text input is started
+    text_input: TextInput,
+
+    callbacks: EventCallbacks,
 }
 
 impl CtxHandler {
     /// Generate a new handler with a new context, window, graphics handler, event pump, audio mixer
-    pub fn new() -> CtxHandler {
+    /// Fails if the machine can't run the renderer, see `GraphicsInitError`
+    pub fn new() -> Result<CtxHandler, GraphicsInitError> {
+        Self::with_config(
+            &WindowConfig::default(),
+            GraphicsConfig::default(),
+            &AudioConfig::default(),
+            60,
+        )
+    }
+
+    /// Like `new`, with the options of each component (and the framerate limit) given by the caller
+    pub fn with_config(
+        window_config: &WindowConfig,
+        graphics_config: GraphicsConfig,
+        audio_config: &AudioConfig,
+        target_fps: u16,
+    ) -> Result<CtxHandler, GraphicsInitError> {
         let ctx = sdl2::init().expect("Couldn't init SDL2 context");
 
-        let event_pump = ctx
+        Self::from_sdl_with_config(ctx, window_config, graphics_config, audio_config, target_fps)
+    }
+
+    /// Generate a new handler on an SDL context owned by a host application (editors, launchers)
+    ///
+    /// The engine opens these on the shared context, so the host should avoid conflicting uses:
+    /// - the video subsystem, for its own Vulkan window
+    /// - the event subsystem, taking the only `EventPump` allowed (the host must drop its own first)
+    /// - the game controller subsystem, opening every connected controller
+    /// - SDL_mixer, opening the audio device and setting the global number of mixing channels
+    pub fn from_sdl(ctx: Sdl) -> Result<CtxHandler, GraphicsInitError> {
+        Self::from_sdl_with_config(
+            ctx,
+            &WindowConfig::default(),
+            GraphicsConfig::default(),
+            &AudioConfig::default(),
+            60,
+        )
+    }
+
+    /// Like `from_sdl`, with the options of each component (and the framerate limit) given by the caller
+    pub fn from_sdl_with_config(
+        ctx: Sdl,
+        window_config: &WindowConfig,
+        graphics_config: GraphicsConfig,
+        audio_config: &AudioConfig,
+        target_fps: u16,
+    ) -> Result<CtxHandler, GraphicsInitError> {
+        let mut event_pump = ctx
             .event_pump()
-            .expect("Couldn't obtain Event Pump from SDL2 context");
+            .expect("Couldn't obtain Event Pump from SDL2 context (only one can exist at a time)");
 
-        let video = VideoHandler::new(&ctx);
-        let audio = AudioHandler::new();
+        // drag and drop events are disabled by default on some platforms
+        event_pump.enable_event(EventType::DropFile);
 
-        let fps_manager = FPSHandler::new(60);
+        let video = VideoHandler::with_config(&ctx, window_config, graphics_config)?;
+        let audio = AudioHandler::with_config(audio_config);
+        let controllers = ControllerHandler::new(&ctx);
 
-        CtxHandler {
+        let fps_manager = FPSHandler::new(target_fps);
+
+        Ok(CtxHandler {
             ctx,
             event_pump,
             video,
             fps_manager,
             audio,
+            controllers,
+            assets: Assets::new(),
 
             must_break: false,
-        }
+            quit_reason: None,
+            quit_hook: None,
+
+            dropped_files: Vec::new(),
+
+            keyboard: KeyboardState::new(),
+            nav_timers: HashMap::new(),
+            text_input: TextInput::new(),
+
+            callbacks: EventCallbacks::new(),
+        })
+    }
+
+    /// Set an SDL hint, an escape hatch for platform quirks the engine doesn't wrap (render driver, XInput,
+    /// mouse focus clickthrough, IME behavior...), returning false if SDL refused it
+    ///
+    /// Most hints are only read when SDL or one of its subsystems is initialized, so they must be set
+    /// before creating the CtxHandler: use `EngineBuilder::hint` rather than calling this afterwards
+    pub fn set_hint(name: &str, value: &str) -> bool {
+        sdl2::hint::set(name, value)
     }
 
     /// Check all SDL2 and SDL_Window events
     pub fn check_events(&mut self) {
-        for event in self.event_pump.poll_iter() {
+        // closing the last window also pushes an SDL_Quit, which mustn't be read as a system quit
+        let mut window_closed = false;
+
+        self.dropped_files.clear();
+        self.keyboard.next_frame();
+        self.controllers.next_frame();
+        let events: Vec<Event> = self.event_pump.poll_iter().collect();
+        for event in events {
             match event {
-                Event::Quit { .. } => self.must_break = true,
                 Event::Window {
-                    win_event: WindowEvent::Resized(_, _),
+                    win_event: WindowEvent::Close,
+                    ..
+                } => {
+                    window_closed = true;
+                    self.request_quit(QuitReason::WindowClosed);
+                }
+                Event::Quit { .. } => {
+                    if !window_closed {
+                        self.request_quit(QuitReason::System);
+                    }
+                }
+                Event::Window {
+                    win_event: WindowEvent::Resized(width, height),
                     ..
                 } => {
                     self.video.set_window_resized(true);
+                    self.callbacks.resize(width as u32, height as u32);
                 }
+                Event::Window {
+                    win_event: WindowEvent::FocusGained,
+                    ..
+                } => self.audio.focus_changed(true),
+                Event::Window {
+                    win_event: WindowEvent::FocusLost,
+                    ..
+                } => {
+                    // the key releases go to the newly focused window
+                    self.keyboard.release_all();
+                    self.audio.focus_changed(false);
+                }
+                Event::DropFile {
+                    window_id,
+                    filename,
+                    ..
+                } => {
+                    let path = PathBuf::from(filename);
+                    self.callbacks.file_dropped(&path);
+                    self.dropped_files.push((window_id, path));
+                }
+                Event::TextInput { text, .. } => self.text_input.commit(&text),
+                Event::TextEditing {
+                    text,
+                    start,
+                    length,
+                    ..
+                } => self.text_input.edit(text, start, length),
+                Event::KeyDown {
+                    keycode: Some(key),
+                    repeat: false,
+                    ..
+                } => {
+                    self.keyboard.key_down(key);
+                    self.callbacks.key_down(key);
+                }
+                Event::KeyUp {
+                    keycode: Some(key), ..
+                } => {
+                    self.keyboard.key_up(key);
+                    self.callbacks.key_up(key);
+                }
+                Event::ControllerDeviceAdded { which, .. } => self.controllers.device_added(which),
+                Event::ControllerDeviceRemoved { which, .. } => {
+                    self.controllers.device_removed(which)
+                }
+                Event::ControllerButtonDown { which, button, .. } => {
+                    self.controllers.button_down(which, button)
+                }
+                Event::ControllerButtonUp { which, button, .. } => {
+                    self.controllers.button_up(which, button)
+                }
+                // axes are read from the controller state, which SDL updates with these events
+                Event::ControllerAxisMotion { .. } => {}
                 _ => {}
             }
         }
     }
 
+    /// Create a sprite showing a texture of `assets`, `None` if the texture was unloaded
+    pub fn new_sprite_from_handle(&mut self, texture: TextureHandle, z_index: u8) -> Option<SpriteObject> {
+        let (texture, dimensions) = self.assets.texture(texture)?;
+
+        Some(self.video.new_sprite_from_texture(texture, dimensions, z_index))
+    }
+
+    /// Play a sound effect of `assets`, does nothing if the sound was unloaded
+    pub fn play_sound(&self, sound: SoundHandle) {
+        if let Some(sound) = self.assets.sound(sound) {
+            self.audio.sfx_play(sound);
+        }
+    }
+
+    /// Unload the assets nobody used for their grace period, see `Assets::collect_unused`
+    pub fn collect_unused_assets(&mut self) {
+        self.assets.collect_unused(&self.video.gl_handler);
+    }
+
     /// Fetch the flag to stop the program
     pub fn get_break_signal(&self) -> bool {
         self.must_break
     }
 
+    /// Paths of the files dropped on the window since the last call to `check_events`
+    pub fn dropped_files(&self) -> Vec<PathBuf> {
+        self.dropped_files
+            .iter()
+            .map(|(_, path)| path.clone())
+            .collect()
+    }
+
+    /// Like `dropped_files`, with the id of the window each file was dropped on
+    pub fn dropped_files_with_window(&self) -> &[(u32, PathBuf)] {
+        &self.dropped_files
+    }
+
+    /// Start receiving text (and input method compositions) in `text_input`, e.g. when a text field gets the focus
+    /// Input methods show their candidate list next to `area`, in window pixels: usually the text field
+    pub fn start_text_input(&mut self, area: Option<Rect>) {
+        let text_input = self.video.text_input();
+        if let Some(area) = area {
+            text_input.set_rect(area);
+        }
+        text_input.start();
+    }
+
+    /// Stop receiving text, dropping the composition in progress
+    pub fn stop_text_input(&mut self) {
+        self.video.text_input().stop();
+        self.text_input.edit(String::new(), 0, 0);
+    }
+
+    pub fn is_text_input_active(&self) -> bool {
+        self.video.text_input().is_active()
+    }
+
+    /// Text typed since text input was started, and composition in progress
+    pub fn text_input(&mut self) -> &mut TextInput {
+        &mut self.text_input
+    }
+
+    /// Whether a key is held down, as of the last `check_events`
+    pub fn is_key_pressed(&self, key: Keycode) -> bool {
+        self.keyboard.is_key_pressed(key)
+    }
+
+    /// Whether a key went down during the last `check_events`
+    pub fn key_just_pressed(&self, key: Keycode) -> bool {
+        self.keyboard.key_just_pressed(key)
+    }
+
+    /// Whether a key went up during the last `check_events`
+    pub fn key_just_released(&self, key: Keycode) -> bool {
+        self.keyboard.key_just_released(key)
+    }
+
+    /// Value of a controller axis from -1.0 to 1.0 (0.0 to 1.0 for triggers), after its deadzone
+    /// `which` is an instance id from `controllers.get_controller_ids()`, disconnected controllers return 0.0
+    pub fn get_controller_axis(&self, which: u32, axis: Axis) -> f32 {
+        self.controllers.axis(which, axis)
+    }
+
+    /// Whether a controller button is held down, as of the last `check_events`
+    pub fn is_controller_button_down(&self, which: u32, button: Button) -> bool {
+        self.controllers.button(which, button)
+    }
+
+    /// Whether a controller button went down during the last `check_events`
+    pub fn controller_button_just_pressed(&self, which: u32, button: Button) -> bool {
+        self.controllers.button_just_pressed(which, button)
+    }
+
+    /// Whether a controller button went up during the last `check_events`
+    pub fn controller_button_just_released(&self, which: u32, button: Button) -> bool {
+        self.controllers.button_just_released(which, button)
+    }
+
+    /// Whether a navigation direction is held on the keyboard or on any controller
+    pub fn nav_held(&self, action: NavAction) -> bool {
+        let (scancode, button, axis, direction) = match action {
+            NavAction::Up => (Scancode::Up, Button::DPadUp, Axis::LeftY, -1),
+            NavAction::Down => (Scancode::Down, Button::DPadDown, Axis::LeftY, 1),
+            NavAction::Left => (Scancode::Left, Button::DPadLeft, Axis::LeftX, -1),
+            NavAction::Right => (Scancode::Right, Button::DPadRight, Axis::LeftX, 1),
+        };
+
+        self.event_pump.keyboard_state().is_scancode_pressed(scancode)
+            || self.controllers.get_controller_ids().into_iter().any(|id| {
+                self.controllers.button(id, button)
+                    || self.controllers.axis_as_dpad(id, axis, 0.5) == direction
+            })
+    }
+
+    /// Menu style auto-repeat of a navigation direction: true on press, again after `initial_delay` seconds,
+    /// then `rate` times per second while held
+    /// Must be called every frame for each action in use, with the frame delta as `dt`
+    pub fn nav_repeat(&mut self, action: NavAction, initial_delay: f32, rate: f32, dt: f32) -> bool {
+        let held = self.nav_held(action);
+
+        self.nav_timers
+            .entry(action)
+            .or_insert_with(RepeatTimer::new)
+            .update(held, initial_delay, rate, dt)
+    }
+
+    /// Reason of the accepted quit request, if any
+    pub fn get_quit_reason(&self) -> Option<QuitReason> {
+        self.quit_reason
+    }
+
+    /// Set the callback asked to confirm every quit request (e.g. to show an "are you sure?" prompt)
+    pub fn set_quit_hook(&mut self, hook: QuitHook) {
+        self.quit_hook = Some(hook);
+    }
+
+    pub fn remove_quit_hook(&mut self) {
+        self.quit_hook = None;
+    }
+
+    /// Ask the program to stop at the end of the current frame
+    pub fn quit(&mut self) {
+        self.request_quit(QuitReason::Requested);
+    }
+
+    fn request_quit(&mut self, reason: QuitReason) {
+        let accepted = match &mut self.quit_hook {
+            Some(hook) => hook(reason),
+            None => true,
+        };
+
+        if accepted {
+            self.must_break = true;
+            self.quit_reason = Some(reason);
+            self.callbacks.quit(reason);
+        }
+    }
+
+    /// Run `callback` when a key gets pressed (not on key repeats), during `check_events`
+    ///
+    /// Callbacks suit one-off UI wiring (e.g. a key toggling a debug overlay), game logic should rather poll
+    /// the input each frame. They can't borrow the CtxHandler, so share state with them through
+    /// `Rc<RefCell<_>>` or `Rc<Cell<_>>` and act on it after `check_events`
+    pub fn on_key_down(&mut self, key: Keycode, callback: Box<dyn FnMut()>) -> CallbackId {
+        self.callbacks.on_key_down(key, callback)
+    }
+
+    /// Run `callback` when a key gets released, during `check_events`
+    pub fn on_key_up(&mut self, key: Keycode, callback: Box<dyn FnMut()>) -> CallbackId {
+        self.callbacks.on_key_up(key, callback)
+    }
+
+    /// Run `callback` when a quit request gets accepted (after the quit hook, if any)
+    pub fn on_quit(&mut self, callback: Box<dyn FnMut(QuitReason)>) -> CallbackId {
+        self.callbacks.on_quit(callback)
+    }
+
+    /// Run `callback` with the new window size (in pixels) when the window gets resized
+    pub fn on_resize(&mut self, callback: Box<dyn FnMut(u32, u32)>) -> CallbackId {
+        self.callbacks.on_resize(callback)
+    }
+
+    /// Run `callback` for each file dropped on the window
+    pub fn on_file_dropped(&mut self, callback: Box<dyn FnMut(&Path)>) -> CallbackId {
+        self.callbacks.on_file_dropped(callback)
+    }
+
+    pub fn remove_callback(&mut self, id: CallbackId) {
+        self.callbacks.remove(id);
+    }
+
+    pub fn clear_callbacks(&mut self) {
+        self.callbacks.clear();
+    }
+
+    /// Stop the audio and wait for the GPU to be idle, to close the program without interrupting a frame
+    pub fn shutdown(&mut self) {
+        self.audio.halt_all();
+        self.video.shutdown();
+    }
+
+    /// Rumble all the connected controllers, see `ControllerHandler::rumble` for a single one
+    pub fn rumble(&mut self, low_freq: u16, high_freq: u16, duration_ms: u32) {
+        self.controllers.rumble_all(low_freq, high_freq, duration_ms);
+    }
+
     /// Public function to set the Ctx's framerate
     pub fn set_framerate_limit(&mut self, new_framerate: u16) {
-        self.fps_manager.set_limit(1. / new_framerate as f32);
+        self.set_target_fps(new_framerate);
     }
 
     /// Get the current framerate
     pub fn get_framerate_limit(&self) -> u16 {
-        (1. / self.fps_manager.get_limit()) as u16
+        self.fps_manager.get_target_fps()
+    }
+
+    /// Change the framerate (0 to uncap it), it can be called at any point of the game loop
+    /// and applies from the end of the current frame
+    pub fn set_target_fps(&mut self, fps: u16) {
+        self.fps_manager.set_target_fps(fps);
     }
 
     pub fn get_current_framerate(&self) -> u16 {
         self.fps_manager.get_fps()
     }
 
+    /// Real duration (in seconds) of the last frame, as measured by the last `wait`
+    /// Multiplying movement by it gives the same speed at any framerate, e.g. `x += 200.0 * delta` moves
+    /// 200 units per second; use `time().delta` instead for gameplay affected by the time scale
+    pub fn get_delta(&self) -> f32 {
+        self.fps_manager.get_delta()
+    }
+
+    pub fn get_time_scale(&self) -> f32 {
+        self.fps_manager.get_time_scale()
+    }
+
+    /// Speed of gameplay time (e.g. 0.5 for slow motion, 0 to pause), see `FPSHandler::set_time_scale`
+    pub fn set_time_scale(&mut self, scale: f32) {
+        self.fps_manager.set_time_scale(scale);
+    }
+
+    /// Get delta, elapsed time, framerate and frame count of the current frame at once
+    pub fn time(&self) -> Time {
+        self.fps_manager.time()
+    }
+
     /// Wait for the next frame based on the current framerate
+    /// The delta is updated before returning, so the next frame reads the duration of the one that just ended
     pub fn wait(&mut self) {
         self.fps_manager.wait()
     }
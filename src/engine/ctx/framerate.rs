@@ -2,54 +2,227 @@
 
 // standard imports
 use std::time::{Duration, Instant};
+use std::hint;
 use std::thread;
 
+/// Time left (in seconds) that precise pacing spends spinning instead of sleeping
+const SPIN_MARGIN: f32 = 0.001;
+
+/// Number of frame times kept by default, two seconds at 60 FPS
+const DEFAULT_HISTORY_LENGTH: usize = 120;
+
+/// Timing information of the current frame
+#[derive(Copy, Clone, Debug)]
+pub struct Time {
+    /// Duration (in seconds) of the last frame, multiplied by the time scale: the one to advance gameplay with
+    pub delta: f32,
+    /// Real duration (in seconds) of the last frame, for what mustn't slow down with the game (UI animations, music fades)
+    pub unscaled_delta: f32,
+    /// Multiplier of `delta`, see `FPSHandler::set_time_scale`
+    pub time_scale: f32,
+    /// Time (in seconds) since the start of the program
+    pub elapsed: f32,
+    pub fps: u16,
+    /// Number of frames completed since the start of the program
+    pub frame_count: u64,
+}
 
 /// Basic struct to handle FPS waiting
 pub struct FPSHandler {
+    start: Instant,
     last_loop: Instant,
     delta: f32,
     limit: f32,
+    frame_count: u64,
+    time_scale: f32,
+    /// Spin-wait the end of each frame, more accurate than sleeping alone but burns CPU
+    precise: bool,
+    /// Durations (in seconds) of the last frames, oldest first
+    history: Vec<f32>,
+    history_length: usize,
 }
 
 impl FPSHandler {
     pub fn new(limit: u16) -> Self {
-        let limit = 1. / limit as f32;
+        let limit = frame_time(limit);
 
         Self {
+            start: Instant::now(),
             last_loop: Instant::now(),
             delta: 0.0,
             limit,
+            frame_count: 0,
+            time_scale: 1.0,
+            precise: false,
+            history: Vec::with_capacity(DEFAULT_HISTORY_LENGTH),
+            history_length: DEFAULT_HISTORY_LENGTH,
         }
     }
 
+    /// Minimum duration (in seconds) of a frame
     pub fn get_limit(&self) -> f32 {
         self.limit
     }
+    /// Set the minimum duration (in seconds) of a frame, see `set_target_fps` to use a framerate
     pub fn set_limit(&mut self, new_limit: f32) {
         self.limit = new_limit;
     }
 
+    /// Framerate the handler waits for, 0 when uncapped
+    pub fn get_target_fps(&self) -> u16 {
+        if self.limit > 0. {
+            (1. / self.limit).round() as u16
+        } else {
+            0
+        }
+    }
+
+    /// Set the framerate to wait for (0 to uncap it), used from the next call to `wait`
+    pub fn set_target_fps(&mut self, fps: u16) {
+        self.limit = frame_time(fps);
+    }
+
+    pub fn get_time_scale(&self) -> f32 {
+        self.time_scale
+    }
+
+    /// Multiply the delta given to gameplay, for slow motion (below 1), fast forward (above 1) or hit-stop,
+    /// 0 pauses the game while it keeps rendering at the same framerate
+    /// A fixed timestep loop should feed its accumulator with the scaled `Time::delta`, so it runs fewer steps
+    /// instead of shorter ones
+    pub fn set_time_scale(&mut self, scale: f32) {
+        self.time_scale = scale.max(0.0);
+    }
+
+    pub fn get_precise(&self) -> bool {
+        self.precise
+    }
+
+    /// Enable or disable precise frame pacing, off by default
+    pub fn set_precise(&mut self, precise: bool) {
+        self.precise = precise;
+    }
+
+    /// Durations (in seconds) of the last frames, oldest first, e.g. to plot a frame time graph
+    pub fn frame_time_history(&self) -> &[f32] {
+        &self.history
+    }
+
+    pub fn get_history_length(&self) -> usize {
+        self.history_length
+    }
+
+    /// Set how many frame times are kept (0 to stop recording them), dropping the oldest ones if needed
+    pub fn set_history_length(&mut self, length: usize) {
+        if self.history.len() > length {
+            self.history.drain(..self.history.len() - length);
+        }
+        self.history_length = length;
+    }
+
     pub fn get_fps(&self) -> u16 {
         (1. / self.get_delta()).round() as u16
     }
 
+    /// Duration (in seconds) of the last frame, including the wait, not affected by the time scale
     pub fn get_delta(&self) -> f32 {
         self.delta
     }
 
+    pub fn get_frame_count(&self) -> u64 {
+        self.frame_count
+    }
+
+    pub fn get_elapsed(&self) -> f32 {
+        self.start.elapsed().as_secs_f32()
+    }
+
+    pub fn time(&self) -> Time {
+        Time {
+            delta: self.delta * self.time_scale,
+            unscaled_delta: self.delta,
+            time_scale: self.time_scale,
+            elapsed: self.get_elapsed(),
+            fps: self.get_fps(),
+            frame_count: self.frame_count,
+        }
+    }
+
     pub fn wait(&mut self) {
         let time_elapsed = self.last_loop.elapsed().as_secs_f32();
 
-        let wait_time = self.limit - time_elapsed;
+        let wait_time = wait_time(self.limit, time_elapsed);
 
         // If we are early on the framerate limit, wait for it
-        if wait_time > 0. {
+        if self.precise {
+            // sleeping is coarse on some OSes, so only sleep most of the time and spin the rest
+            if wait_time > SPIN_MARGIN {
+                thread::sleep(Duration::from_secs_f32(wait_time - SPIN_MARGIN));
+            }
+            while self.last_loop.elapsed().as_secs_f32() < self.limit {
+                hint::spin_loop();
+            }
+        } else if wait_time > 0. {
             thread::sleep(Duration::from_secs_f32(wait_time));
         };
 
         self.delta = self.last_loop.elapsed().as_secs_f32();
+        self.frame_count += 1;
+        self.record_frame_time(self.delta);
 
         self.last_loop = Instant::now();
     }
-}
\ No newline at end of file
+
+    fn record_frame_time(&mut self, delta: f32) {
+        if self.history_length == 0 {
+            return;
+        }
+
+        // the history stays contiguous, shifting it is cheap at these lengths
+        if self.history.len() == self.history_length {
+            self.history.remove(0);
+        }
+        self.history.push(delta);
+    }
+}
+
+/// Time (in seconds) left to wait for a frame of `limit` seconds, `time_elapsed` seconds after it started
+fn wait_time(limit: f32, time_elapsed: f32) -> f32 {
+    (limit - time_elapsed).max(0.)
+}
+
+/// Duration (in seconds) of a frame at the given framerate, 0 for an uncapped framerate
+fn frame_time(fps: u16) -> f32 {
+    if fps == 0 {
+        0.
+    } else {
+        1. / fps as f32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn target_fps_changes_the_wait_time() {
+        let mut fps = FPSHandler::new(60);
+        fps.wait();
+
+        fps.set_target_fps(30);
+        assert_eq!(wait_time(fps.get_limit(), 0.), 1. / 30.);
+
+        fps.wait();
+        fps.set_target_fps(144);
+        assert_eq!(wait_time(fps.get_limit(), 0.), 1. / 144.);
+        assert_eq!(fps.get_target_fps(), 144);
+    }
+
+    #[test]
+    fn wait_time_counts_the_time_already_spent() {
+        assert!((wait_time(frame_time(30), 0.02) - (1. / 30. - 0.02)).abs() < 1e-6);
+        // late or uncapped frames don't wait
+        assert_eq!(wait_time(frame_time(30), 0.05), 0.);
+        assert_eq!(wait_time(frame_time(0), 0.), 0.);
+    }
+}
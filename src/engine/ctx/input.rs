@@ -0,0 +1,234 @@
+//! Key polling, menu navigation and text entry on top of the raw keyboard and controller input
+
+// standard imports
+use std::collections::HashSet;
+
+// SDL2 imports
+use sdl2::keyboard::Keycode;
+
+/// Keys held down, as of the last `CtxHandler::check_events` and the one before it
+/// Comparing the two gives the keys pressed or released this frame, even if the press was shorter than a frame
+#[derive(Clone, Debug, Default)]
+pub struct KeyboardState {
+    down: HashSet<Keycode>,
+    previous: HashSet<Keycode>,
+    /// Keys pressed and released again between two frames
+    tapped: HashSet<Keycode>,
+}
+
+impl KeyboardState {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Keep the keys down as the previous frame's, before reading the new events
+    pub fn next_frame(&mut self) {
+        self.previous.clone_from(&self.down);
+        self.tapped.clear();
+    }
+
+    pub fn key_down(&mut self, key: Keycode) {
+        self.down.insert(key);
+    }
+
+    pub fn key_up(&mut self, key: Keycode) {
+        if self.down.remove(&key) && !self.previous.contains(&key) {
+            self.tapped.insert(key);
+        }
+    }
+
+    /// Release every key, e.g. when the window loses the focus and stops receiving the key releases
+    pub fn release_all(&mut self) {
+        self.down.clear();
+    }
+
+    pub fn is_key_pressed(&self, key: Keycode) -> bool {
+        self.down.contains(&key)
+    }
+
+    /// Whether the key went down since the previous frame
+    pub fn key_just_pressed(&self, key: Keycode) -> bool {
+        (self.down.contains(&key) && !self.previous.contains(&key)) || self.tapped.contains(&key)
+    }
+
+    /// Whether the key went up since the previous frame
+    pub fn key_just_released(&self, key: Keycode) -> bool {
+        (self.previous.contains(&key) && !self.down.contains(&key)) || self.tapped.contains(&key)
+    }
+}
+
+/// Direction of menu navigation, from the arrow keys, the D-pad or the left stick
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum NavAction {
+    Up,
+    Down,
+    Left,
+    Right,
+}
+
+/// Auto-repeat of a held input: fires once on press, then after `initial_delay`, then `rate` times per second
+#[derive(Copy, Clone, Debug, Default)]
+pub struct RepeatTimer {
+    /// Time (in seconds) the input has been held, `None` when released
+    held_time: Option<f32>,
+}
+
+impl RepeatTimer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Advance the timer by `dt` seconds, returns whether the action fires this frame
+    pub fn update(&mut self, held: bool, initial_delay: f32, rate: f32, dt: f32) -> bool {
+        if !held {
+            self.held_time = None;
+            return false;
+        }
+
+        let previous = match self.held_time {
+            Some(time) => time,
+            None => {
+                self.held_time = Some(0.0);
+                return true;
+            }
+        };
+
+        let current = previous + dt;
+        self.held_time = Some(current);
+
+        // a long frame may skip some repeats, but fires at most once
+        repeats_until(current, initial_delay, rate) > repeats_until(previous, initial_delay, rate)
+    }
+
+    pub fn reset(&mut self) {
+        self.held_time = None;
+    }
+}
+
+/// Number of repeats fired after holding an input for `time` seconds (the first press excluded)
+fn repeats_until(time: f32, initial_delay: f32, rate: f32) -> u32 {
+    if time < initial_delay {
+        0
+    } else if rate <= 0.0 {
+        // no repeat rate: only the one after the initial delay
+        1
+    } else {
+        ((time - initial_delay) * rate) as u32 + 1
+    }
+}
+
+/// Text being composed with an input method (e.g. kana not converted to kanji yet), not part of the text so far
+/// Draw it at the end of the text field, underlined, with the selection highlighted
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Composition {
+    pub text: String,
+    /// Position of the cursor in the composition (in characters)
+    pub cursor: usize,
+    /// Characters after the cursor selected by the input method, e.g. the part being converted
+    pub selection_length: usize,
+}
+
+/// Text typed while text input is started, keeping the committed text apart from the composition in progress
+/// Keycodes can't be used for text: layouts, dead keys and input methods all produce characters differently
+#[derive(Clone, Debug, Default)]
+pub struct TextInput {
+    committed: String,
+    composition: Option<Composition>,
+}
+
+impl TextInput {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Text committed since the last `take_committed`
+    pub fn committed(&self) -> &str {
+        &self.committed
+    }
+
+    /// Text committed so far, leaving the buffer empty (e.g. to append it to a text field every frame)
+    pub fn take_committed(&mut self) -> String {
+        std::mem::take(&mut self.committed)
+    }
+
+    /// Composition in progress, `None` when the input method isn't composing
+    pub fn composition(&self) -> Option<&Composition> {
+        self.composition.as_ref()
+    }
+
+    /// Text committed by the keyboard or the input method (SDL `TextInput` event)
+    pub fn commit(&mut self, text: &str) {
+        self.committed.push_str(text);
+        self.composition = None;
+    }
+
+    /// Composition changed by the input method (SDL `TextEditing` event), an empty text ends it
+    pub fn edit(&mut self, text: String, start: i32, length: i32) {
+        if text.is_empty() {
+            self.composition = None;
+            return;
+        }
+
+        self.composition = Some(Composition {
+            text,
+            cursor: start.max(0) as usize,
+            selection_length: length.max(0) as usize,
+        });
+    }
+
+    pub fn clear(&mut self) {
+        self.committed.clear();
+        self.composition = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // repeats every 0.25s after a 0.5s delay, all exact in binary floating point
+    const DELAY: f32 = 0.5;
+    const RATE: f32 = 4.0;
+
+    #[test]
+    fn repeat_timer_fires_on_press() {
+        let mut timer = RepeatTimer::new();
+
+        assert!(timer.update(true, DELAY, RATE, 0.125));
+    }
+
+    #[test]
+    fn repeat_timer_waits_for_the_initial_delay() {
+        let mut timer = RepeatTimer::new();
+        timer.update(true, DELAY, RATE, 0.0);
+
+        assert!(!timer.update(true, DELAY, RATE, 0.25));
+        assert!(!timer.update(true, DELAY, RATE, 0.125));
+        // held for exactly the delay
+        assert!(timer.update(true, DELAY, RATE, 0.125));
+    }
+
+    #[test]
+    fn repeat_timer_fires_at_the_rate_after_the_delay() {
+        let mut timer = RepeatTimer::new();
+        timer.update(true, DELAY, RATE, 0.0);
+        assert!(timer.update(true, DELAY, RATE, DELAY));
+
+        for _ in 0..3 {
+            assert!(!timer.update(true, DELAY, RATE, 0.125));
+            assert!(timer.update(true, DELAY, RATE, 0.125));
+        }
+    }
+
+    #[test]
+    fn repeat_timer_restarts_when_released() {
+        let mut timer = RepeatTimer::new();
+        timer.update(true, DELAY, RATE, 0.0);
+        timer.update(true, DELAY, RATE, 1.0);
+
+        assert!(!timer.update(false, DELAY, RATE, 0.125));
+        // a new press fires at once, then waits for the whole delay again
+        assert!(timer.update(true, DELAY, RATE, 0.125));
+        assert!(!timer.update(true, DELAY, RATE, 0.25));
+    }
+}
@@ -1,12 +1,25 @@
+mod assets;
 mod audio;
+mod callbacks;
+mod controller;
+mod input;
+mod physics;
 mod video;
 
 mod render;
 
-pub use render::{vulkan, draw_objects};
+pub use render::{vulkan, draw_objects, text};
 
 pub mod ctxhandler;
 pub mod framerate;
 
-pub use ctxhandler::CtxHandler;
-pub use framerate::FPSHandler;
+pub use assets::{Assets, SoundHandle, TextureHandle};
+pub use audio::{AudioConfig, MusicPlaylist, PlaylistMode};
+pub use callbacks::CallbackId;
+pub use controller::{ControllerHandler, Deadzone};
+pub use input::{Composition, KeyboardState, NavAction, RepeatTimer, TextInput};
+pub use physics::Body;
+pub use ctxhandler::{CtxHandler, QuitHook, QuitReason};
+pub use draw_objects::{AnimatedSpriteObject, Draw, DrawFlags, DrawObject, GraphicObject, PrimitiveObject, SpriteObject, SpriteState, TextObject};
+pub use framerate::{FPSHandler, Time};
+pub use video::{DisplayInfo, VideoHandler, WindowConfig};
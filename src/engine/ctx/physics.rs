@@ -0,0 +1,96 @@
+//! Movement of sprites by velocity, acceleration and gravity, without collision response
+
+// standard imports
+use std::cell::RefCell;
+use std::rc::Weak;
+
+// imports from the module
+use super::draw_objects::{Draw, DrawFlags, Sprite, SpriteObject};
+
+// other imports
+use cgmath::Vector2;
+
+/// Motion of a sprite, moved by the engine every update
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Body {
+    /// World units per second
+    pub velocity: Vector2<f32>,
+    /// World units per second squared, e.g. the thrust of a ship
+    pub acceleration: Vector2<f32>,
+    /// Added to the acceleration, kept separate to switch it off (ladders, water) without touching the rest
+    pub gravity: Vector2<f32>,
+}
+
+impl Default for Body {
+    fn default() -> Self {
+        Self {
+            velocity: Vector2::new(0.0, 0.0),
+            acceleration: Vector2::new(0.0, 0.0),
+            gravity: Vector2::new(0.0, 0.0),
+        }
+    }
+}
+
+impl Body {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Advance a position by `dt` seconds, then the velocity (explicit Euler)
+    pub fn integrate(&mut self, position: &mut Vector2<f32>, dt: f32) {
+        *position += self.velocity * dt;
+        self.velocity += (self.acceleration + self.gravity) * dt;
+    }
+}
+
+/// Bodies attached to sprites, forgotten when their sprite is dropped
+#[derive(Default)]
+pub struct Bodies {
+    bodies: Vec<(Weak<RefCell<Sprite>>, Body)>,
+}
+
+impl Bodies {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Move a sprite with a body, replacing the one it already had
+    pub fn attach(&mut self, sprite: &SpriteObject, body: Body) {
+        match self.get_mut(sprite) {
+            Some(attached) => *attached = body,
+            None => self.bodies.push((sprite.downgrade(), body)),
+        }
+    }
+
+    pub fn detach(&mut self, sprite: &SpriteObject) {
+        let sprite = sprite.downgrade();
+        self.bodies.retain(|(s, _)| !s.ptr_eq(&sprite));
+    }
+
+    /// Body of a sprite, to read or change its velocity
+    pub fn get_mut(&mut self, sprite: &SpriteObject) -> Option<&mut Body> {
+        let sprite = sprite.downgrade();
+        self.bodies
+            .iter_mut()
+            .find(|(s, _)| s.ptr_eq(&sprite))
+            .map(|(_, body)| body)
+    }
+
+    /// Move every sprite by `dt` seconds
+    /// Called by the engine with the frame time, a fixed timestep loop should call it once per step instead
+    pub fn integrate(&mut self, dt: f32) {
+        self.bodies.retain(|(sprite, _)| {
+            sprite
+                .upgrade()
+                .map_or(false, |s| s.borrow().read_flags().contains(DrawFlags::USED))
+        });
+
+        for (sprite, body) in self.bodies.iter_mut() {
+            if let Some(sprite) = sprite.upgrade() {
+                let mut sprite = sprite.borrow_mut();
+                body.integrate(&mut sprite.global_position, dt);
+                sprite.write_flags().insert(DrawFlags::DIRTY);
+            }
+        }
+    }
+}
@@ -0,0 +1,196 @@
+//! Fill of the whole window drawn before every object, ignoring the camera
+
+// standard imports
+use std::sync::Arc;
+
+// vulkan imports
+use vulkano::command_buffer::{AutoCommandBufferBuilder, DynamicState, PrimaryAutoCommandBuffer};
+use vulkano::descriptor::descriptor_set::{
+    PersistentDescriptorSet, PersistentDescriptorSetImg, PersistentDescriptorSetSampler,
+};
+use vulkano::device::Device;
+use vulkano::pipeline::cache::PipelineCache;
+use vulkano::pipeline::vertex::{BufferlessDefinition, BufferlessVertices};
+use vulkano::pipeline::{GraphicsPipeline, GraphicsPipelineAbstract};
+use vulkano::render_pass::{RenderPass, Subpass};
+use vulkano::sampler::Sampler;
+
+// vulkan implementation imports
+use super::vulkan::{GraphicsInitError, Texture};
+
+// other imports
+use cgmath::Vector4;
+
+mod vertex_shader {
+    vulkano_shaders::shader! {
+        ty: "vertex",
+        path: "assets/shaders/background.vert"
+    }
+}
+
+mod gradient_shader {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        path: "assets/shaders/background_gradient.frag"
+    }
+}
+
+mod image_shader {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        path: "assets/shaders/background_image.frag"
+    }
+}
+
+/// What fills the window behind the objects, instead of the clear color
+#[derive(Clone, Debug, PartialEq)]
+pub enum Background {
+    /// Vertical gradient, from the color at the top of the window to the one at the bottom
+    Gradient(Vector4<f32>, Vector4<f32>),
+    /// PNG texture stretched over the whole window
+    Image(String),
+}
+
+pub type BackgroundPipeline = GraphicsPipeline<BufferlessDefinition>;
+
+type ImageDescriptorSet = PersistentDescriptorSet<(
+    (
+        (),
+        PersistentDescriptorSetImg<Texture>,
+    ),
+    PersistentDescriptorSetSampler,
+)>;
+
+/// Colors of a gradient (ignored by images) and rotation of the output, recorded with the draw call
+#[derive(Copy, Clone, Debug)]
+#[repr(C)]
+struct BackgroundPushConstants {
+    top: Vector4<f32>,
+    bottom: Vector4<f32>,
+    quarter_turns: u32,
+}
+
+/// Background ready to be drawn, with the data its pipeline needs
+pub enum BackgroundFill {
+    Gradient(Vector4<f32>, Vector4<f32>),
+    Image(Arc<ImageDescriptorSet>),
+}
+
+/// Pipelines drawing the backgrounds, one for each kind of fill
+pub struct BackgroundPipelines {
+    gradient: Arc<BackgroundPipeline>,
+    image: Arc<BackgroundPipeline>,
+}
+
+impl BackgroundPipelines {
+    pub fn new(
+        device: Arc<Device>,
+        render_pass: Arc<RenderPass>,
+        cache: Arc<PipelineCache>,
+        encode_srgb: bool,
+    ) -> Result<Self, GraphicsInitError> {
+        let vert_shader = vertex_shader::Shader::load(device.clone())
+            .map_err(|e| GraphicsInitError::Pipeline(format!("background Vertex Shader: {}", e)))?;
+        let gradient_shader = gradient_shader::Shader::load(device.clone())
+            .map_err(|e| GraphicsInitError::Pipeline(format!("background gradient Fragment Shader: {}", e)))?;
+        let image_shader = image_shader::Shader::load(device.clone())
+            .map_err(|e| GraphicsInitError::Pipeline(format!("background image Fragment Shader: {}", e)))?;
+
+        let gradient = Arc::new(
+            GraphicsPipeline::start()
+                .vertex_shader(vert_shader.main_entry_point(), ())
+                .triangle_strip()
+                .viewports_dynamic_scissors_irrelevant(1)
+                .fragment_shader(
+                    gradient_shader.main_entry_point(),
+                    gradient_shader::SpecializationConstants {
+                        encode_srgb: encode_srgb as u32,
+                    },
+                )
+                .render_pass(Subpass::from(render_pass.clone(), 0).unwrap())
+                .build_with_cache(cache.clone())
+                .build(device.clone())
+                .map_err(|e| GraphicsInitError::Pipeline(format!("background gradient: {}", e)))?,
+        );
+
+        let image = Arc::new(
+            GraphicsPipeline::start()
+                .vertex_shader(vert_shader.main_entry_point(), ())
+                .triangle_strip()
+                .viewports_dynamic_scissors_irrelevant(1)
+                .fragment_shader(
+                    image_shader.main_entry_point(),
+                    image_shader::SpecializationConstants {
+                        encode_srgb: encode_srgb as u32,
+                    },
+                )
+                .render_pass(Subpass::from(render_pass, 0).unwrap())
+                .build_with_cache(cache)
+                .build(device)
+                .map_err(|e| GraphicsInitError::Pipeline(format!("background image: {}", e)))?,
+        );
+
+        Ok(Self { gradient, image })
+    }
+
+    /// Bind the texture of an image background
+    pub fn image_fill(&self, texture: Texture, sampler: Arc<Sampler>) -> BackgroundFill {
+        let layout = self
+            .image
+            .layout()
+            .descriptor_set_layout(0)
+            .expect("Couldn't use Descriptor Set Layout");
+
+        BackgroundFill::Image(Arc::new(
+            PersistentDescriptorSet::start(layout.clone())
+                .add_sampled_image(texture, sampler)
+                .expect("Couldn't add Sampled Image to the background Descriptor Set")
+                .build()
+                .expect("Couldn't build the background Descriptor Set"),
+        ))
+    }
+
+    /// Record the draw covering the window, to do first in the render pass
+    /// The pipelines don't test the stencil, so `dynamic_state` only needs the viewport
+    pub fn draw(
+        &self,
+        fill: &BackgroundFill,
+        quarter_turns: u32,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        dynamic_state: &DynamicState,
+    ) {
+        let vertices = BufferlessVertices {
+            vertices: 4,
+            instances: 1,
+        };
+        let transparent = Vector4::new(0.0, 0.0, 0.0, 0.0);
+
+        match fill {
+            BackgroundFill::Gradient(top, bottom) => builder.draw(
+                self.gradient.clone(),
+                dynamic_state,
+                vertices,
+                (),
+                BackgroundPushConstants {
+                    top: *top,
+                    bottom: *bottom,
+                    quarter_turns,
+                },
+                vec![],
+            ),
+            BackgroundFill::Image(descriptor_set) => builder.draw(
+                self.image.clone(),
+                dynamic_state,
+                vertices,
+                descriptor_set.clone(),
+                BackgroundPushConstants {
+                    top: transparent,
+                    bottom: transparent,
+                    quarter_turns,
+                },
+                vec![],
+            ),
+        }
+        .expect("Couldn't add the background Draw command to Vulkan Render Pass");
+    }
+}
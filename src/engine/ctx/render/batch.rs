@@ -0,0 +1,167 @@
+//! Sprites sharing a texture, sampler and camera drawn together with instancing
+
+// standard imports
+use std::sync::Arc;
+
+// vulkan imports
+use vulkano::buffer::{BufferUsage, ImmutableBuffer};
+use vulkano::descriptor::descriptor_set::{
+    PersistentDescriptorSet, PersistentDescriptorSetBuf, PersistentDescriptorSetImg,
+    PersistentDescriptorSetSampler,
+};
+use vulkano::device::{Device, Queue};
+use vulkano::pipeline::cache::PipelineCache;
+use vulkano::pipeline::depth_stencil::DepthStencil;
+use vulkano::pipeline::vertex::OneVertexOneInstanceDefinition;
+use vulkano::pipeline::GraphicsPipeline;
+use vulkano::render_pass::{RenderPass, Subpass};
+use vulkano::sync::GpuFuture;
+
+// vulkan implementation imports
+use super::vulkan::{GlobalUniformBuffer, GraphicsInitError, Texture, Vertex};
+
+mod vertex_shader {
+    vulkano_shaders::shader! {
+        ty: "vertex",
+        path: "assets/shaders/sprite_batch.vert"
+    }
+}
+
+mod fragment_shader {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        path: "assets/shaders/sprite.frag"
+    }
+}
+
+/// Most sprites drawn by a single batch, a longer batch is split in many draw calls
+pub const MAX_BATCH_SIZE: usize = 1024;
+
+/// Data of a sprite in a batch, read by the vertex shader once per instance
+#[derive(Default, Copy, Clone)]
+pub struct SpriteInstance {
+    pub color: [f32; 4],
+    /// Global position in `xy`, scale in `zw`
+    pub transform: [f32; 4],
+    /// Texture size in `xy`, pixel snapping flag in `z`, flip flags in `w` (bit 0 horizontal, bit 1 vertical)
+    pub image_dimensions: [u32; 4],
+    pub source_rect: [u32; 4],
+}
+vulkano::impl_vertex!(SpriteInstance, color, transform, image_dimensions, source_rect);
+
+pub type BatchPipeline = GraphicsPipeline<OneVertexOneInstanceDefinition<Vertex, SpriteInstance>>;
+
+/// Texture, sampler and camera of a batch, shared by all the sprites that can be drawn in it
+pub type BatchDescriptorSet = PersistentDescriptorSet<(
+    (
+        (
+            (),
+            PersistentDescriptorSetImg<Texture>,
+        ),
+        PersistentDescriptorSetSampler,
+    ),
+    PersistentDescriptorSetBuf<Arc<GlobalUniformBuffer>>,
+)>;
+
+/// Sprites collected until the next one can't join them, drawn in z order with a single draw call
+pub struct SpriteBatch {
+    descriptor_set: Option<Arc<BatchDescriptorSet>>,
+    /// Stencil bits of the masks of the sprites
+    mask_bits: u32,
+    instances: Vec<SpriteInstance>,
+}
+
+impl SpriteBatch {
+    pub fn new() -> Self {
+        Self {
+            descriptor_set: None,
+            mask_bits: 0,
+            instances: Vec::with_capacity(MAX_BATCH_SIZE),
+        }
+    }
+
+    /// Whether a sprite with this descriptor set and masks can be added without breaking the batch
+    pub fn accepts(&self, descriptor_set: &Arc<BatchDescriptorSet>, mask_bits: u32) -> bool {
+        match &self.descriptor_set {
+            Some(current) => {
+                Arc::ptr_eq(current, descriptor_set)
+                    && self.mask_bits == mask_bits
+                    && self.instances.len() < MAX_BATCH_SIZE
+            }
+            None => true,
+        }
+    }
+
+    pub fn push(&mut self, descriptor_set: Arc<BatchDescriptorSet>, mask_bits: u32, instance: SpriteInstance) {
+        if self.descriptor_set.is_none() {
+            self.descriptor_set = Some(descriptor_set);
+            self.mask_bits = mask_bits;
+        }
+        self.instances.push(instance);
+    }
+
+    /// Content of the batch, leaving it empty for the next sprites
+    pub fn take(&mut self) -> Option<(Arc<BatchDescriptorSet>, u32, Vec<SpriteInstance>)> {
+        let descriptor_set = self.descriptor_set.take()?;
+        let instances = std::mem::replace(&mut self.instances, Vec::with_capacity(MAX_BATCH_SIZE));
+
+        Some((descriptor_set, self.mask_bits, instances))
+    }
+}
+
+impl Default for SpriteBatch {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Pipeline drawing batches of sprites, testing the stencil like the pipeline of single sprites
+pub fn create_batch_pipeline(
+    device: Arc<Device>,
+    render_pass: Arc<RenderPass>,
+    cache: Arc<PipelineCache>,
+    encode_srgb: bool,
+    depth_stencil: DepthStencil,
+) -> Result<Arc<BatchPipeline>, GraphicsInitError> {
+    let vert_shader = vertex_shader::Shader::load(device.clone())
+        .map_err(|e| GraphicsInitError::Pipeline(format!("batch Vertex Shader: {}", e)))?;
+    let frag_shader = fragment_shader::Shader::load(device.clone())
+        .map_err(|e| GraphicsInitError::Pipeline(format!("batch Fragment Shader: {}", e)))?;
+
+    GraphicsPipeline::start()
+        .vertex_input(OneVertexOneInstanceDefinition::<Vertex, SpriteInstance>::new())
+        .vertex_shader(vert_shader.main_entry_point(), ())
+        .triangle_list()
+        .viewports_dynamic_scissors_irrelevant(1)
+        .blend_alpha_blending()
+        .depth_stencil(depth_stencil)
+        .fragment_shader(
+            frag_shader.main_entry_point(),
+            fragment_shader::SpecializationConstants {
+                encode_srgb: encode_srgb as u32,
+                alpha_test: 0,
+            },
+        )
+        .render_pass(Subpass::from(render_pass, 0).unwrap())
+        .build_with_cache(cache)
+        .build(device)
+        .map(Arc::new)
+        .map_err(|e| GraphicsInitError::Pipeline(format!("batch: {}", e)))
+}
+
+/// Two triangles covering the quad of a sprite, shared by every instance
+pub fn create_quad_buffer(queue: Arc<Queue>) -> Arc<ImmutableBuffer<[Vertex]>> {
+    let corners = [[-1.0, -1.0], [-1.0, 1.0], [1.0, 1.0], [1.0, 1.0], [1.0, -1.0], [-1.0, -1.0]];
+
+    let (buffer, future) = ImmutableBuffer::from_iter(
+        corners.iter().map(|&vert_pos| Vertex { vert_pos }),
+        BufferUsage::vertex_buffer(),
+        queue,
+    )
+    .expect("Couldn't create the Vertex Buffer of sprite batches");
+    future
+        .flush()
+        .expect("Couldn't upload the Vertex Buffer of sprite batches");
+
+    buffer
+}
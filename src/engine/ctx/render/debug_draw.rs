@@ -0,0 +1,130 @@
+//! Immediate-mode shapes for development (paths, hitboxes, vectors), drawn on top of everything for a single frame
+
+// standard imports
+use std::f32::consts::PI;
+use std::sync::Arc;
+
+// vulkan imports
+use vulkano::device::Device;
+use vulkano::pipeline::cache::PipelineCache;
+use vulkano::pipeline::vertex::SingleBufferDefinition;
+use vulkano::pipeline::GraphicsPipeline;
+use vulkano::render_pass::{RenderPass, Subpass};
+
+// vulkan implementation imports
+use super::vulkan::GraphicsInitError;
+
+// other imports
+use cgmath::{Vector2, Vector4};
+
+mod vertex_shader {
+    vulkano_shaders::shader! {
+        ty: "vertex",
+        path: "assets/shaders/debug.vert"
+    }
+}
+
+mod fragment_shader {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        path: "assets/shaders/debug.frag"
+    }
+}
+
+/// Segments used to approximate debug circles
+const CIRCLE_SEGMENTS: usize = 32;
+
+/// End of a debug line, in world coordinates
+#[derive(Default, Copy, Clone)]
+pub struct DebugVertex {
+    pub position: [f32; 2],
+    pub color: [f32; 4],
+}
+vulkano::impl_vertex!(DebugVertex, position, color);
+
+pub type DebugPipeline = GraphicsPipeline<SingleBufferDefinition<DebugVertex>>;
+
+/// Lines accumulated during a frame, all drawn with a single draw call
+#[derive(Default)]
+pub struct DebugShapes {
+    vertices: Vec<DebugVertex>,
+}
+
+impl DebugShapes {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn line(&mut self, a: Vector2<f32>, b: Vector2<f32>, color: Vector4<f32>) {
+        let color = color.into();
+        self.vertices.push(DebugVertex { position: a.into(), color });
+        self.vertices.push(DebugVertex { position: b.into(), color });
+    }
+
+    /// Outline of an axis-aligned rectangle
+    pub fn rect(&mut self, center: Vector2<f32>, size: Vector2<f32>, color: Vector4<f32>) {
+        let half = size / 2.0;
+        let corners = [
+            center + Vector2::new(-half.x, -half.y),
+            center + Vector2::new(half.x, -half.y),
+            center + Vector2::new(half.x, half.y),
+            center + Vector2::new(-half.x, half.y),
+        ];
+
+        for i in 0..corners.len() {
+            self.line(corners[i], corners[(i + 1) % corners.len()], color);
+        }
+    }
+
+    /// Outline of a circle
+    pub fn circle(&mut self, center: Vector2<f32>, radius: f32, color: Vector4<f32>) {
+        let point = |i: usize| {
+            let angle = i as f32 / CIRCLE_SEGMENTS as f32 * 2.0 * PI;
+            center + Vector2::new(angle.cos(), angle.sin()) * radius
+        };
+
+        for i in 0..CIRCLE_SEGMENTS {
+            self.line(point(i), point(i + 1), color);
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.vertices.is_empty()
+    }
+
+    /// Vertices of all the lines, leaving the buffer empty for the next frame
+    pub fn take_vertices(&mut self) -> Vec<DebugVertex> {
+        std::mem::take(&mut self.vertices)
+    }
+}
+
+/// Pipeline drawing the debug lines, with the same projection as every other object
+pub fn create_debug_pipeline(
+    device: Arc<Device>,
+    render_pass: Arc<RenderPass>,
+    cache: Arc<PipelineCache>,
+    encode_srgb: bool,
+) -> Result<Arc<DebugPipeline>, GraphicsInitError> {
+    let vert_shader = vertex_shader::Shader::load(device.clone())
+        .map_err(|e| GraphicsInitError::Pipeline(format!("debug Vertex Shader: {}", e)))?;
+    let frag_shader = fragment_shader::Shader::load(device.clone())
+        .map_err(|e| GraphicsInitError::Pipeline(format!("debug Fragment Shader: {}", e)))?;
+
+    GraphicsPipeline::start()
+        .vertex_input_single_buffer::<DebugVertex>()
+        .vertex_shader(vert_shader.main_entry_point(), ())
+        .line_list()
+        .viewports_dynamic_scissors_irrelevant(1)
+        .blend_alpha_blending()
+        .fragment_shader(
+            frag_shader.main_entry_point(),
+            fragment_shader::SpecializationConstants {
+                encode_srgb: encode_srgb as u32,
+            },
+        )
+        .render_pass(Subpass::from(render_pass, 0).unwrap())
+        .build_with_cache(cache)
+        .build(device)
+        .map(Arc::new)
+        .map_err(|e| GraphicsInitError::Pipeline(format!("debug: {}", e)))
+}
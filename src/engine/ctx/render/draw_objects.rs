@@ -2,36 +2,118 @@
 use std::cell::RefCell;
 use std::cell::{Ref, RefMut};
 use std::ops::DerefMut;
-use std::rc::Rc;
+use std::rc::{Rc, Weak};
 use std::sync::Arc;
 
 // vulkan imports
-use vulkano::buffer::{BufferUsage, CpuAccessibleBuffer, ImmutableBuffer, TypedBufferAccess};
+use vulkano::buffer::{BufferUsage, CpuAccessibleBuffer, ImmutableBuffer};
 use vulkano::command_buffer::{AutoCommandBufferBuilder, PrimaryAutoCommandBuffer};
 use vulkano::descriptor::descriptor_set::collection::DescriptorSetsCollection;
 use vulkano::descriptor::descriptor_set::{
     PersistentDescriptorSet, PersistentDescriptorSetBuf, PersistentDescriptorSetImg,
     PersistentDescriptorSetSampler,
 };
-use vulkano::image::view::ImageView;
-use vulkano::image::ImmutableImage;
-use vulkano::pipeline::vertex::SingleBufferDefinition;
-use vulkano::pipeline::GraphicsPipeline;
 
 // vulkan implementation imports
-use super::vulkan::{GlobalUniformData, GraphicsHandler, Vertex, VertexArray, VertexBuffer};
+use super::batch::{BatchDescriptorSet, SpriteInstance};
+use super::text::{layout_text, measure_text, BitmapFont, GlyphMetrics, TextAntialiasing, TextStyle};
+use super::texture;
+use super::vulkan::{
+    GlobalUniformBuffer, GlobalUniformData, GraphicsHandler, IndexBuffer, ProjectionMode,
+    SamplerConfig, Texture, TextureError, TextureFilter, Vertex, VertexArray, VertexBuffer,
+};
 
 // other imports
 use bitflags::bitflags;
+use crate::engine::spatial::Aabb;
 use cgmath::{Vector2, Vector4};
+#[cfg(feature = "serialize")]
+use serde::{Deserialize, Serialize};
 
 bitflags! {
-    pub struct DrawFlags: u8 {
+    pub struct DrawFlags: u16 {
         const USED = 0b00000001;
         const VISIBLE = 0b00000010;
+        /// Changed since the last rendered frame
+        const DIRTY = 0b00000100;
+        /// Drawn in window pixels, ignoring the camera
+        const SCREEN_SPACE = 0b00001000;
+        /// Written to the stencil by `push_mask` instead of being shown
+        const MASK = 0b00010000;
+        /// Z index changed since the objects were last sorted
+        const Z_CHANGED = 0b00100000;
+        /// Round the position on screen to whole pixels, avoiding shimmering of filtered textures while moving
+        /// Off by default, to keep smooth sub-pixel motion (only sprites snap)
+        const PIXEL_SNAP = 0b01000000;
+        /// Mirror the texture horizontally, e.g. for a character facing left (only sprites flip)
+        const FLIP_X = 0b10000000;
+        /// Mirror the texture vertically
+        const FLIP_Y = 0b100000000;
+        /// Flags users can change with `set_flags`, the others are only set by the engine
+        const USER = Self::VISIBLE.bits | Self::PIXEL_SNAP.bits | Self::FLIP_X.bits | Self::FLIP_Y.bits;
     }
 }
 
+/// Object drawn by the GraphicsHandler, sorted by z index and flushed every frame before drawing
+/// It can be implemented outside the engine and added with `add_custom`: `draw` is called inside the engine's
+/// render pass (one color and one stencil attachment) and must record draws with pipelines built for it,
+/// using the dynamic state of `gl_handler.get_swapchain()` (viewport and stencil values)
+///
+/// ```no_run
+/// use std::cell::{Cell, RefCell};
+/// use std::rc::Rc;
+///
+/// use pholidota::engine::{Draw, DrawFlags, Engine, GraphicsHandler, GraphicsInitError};
+/// use vulkano::command_buffer::{AutoCommandBufferBuilder, PrimaryAutoCommandBuffer};
+///
+/// /// Object counting the frames it was drawn in
+/// struct FrameCounter {
+///     frames: Cell<u32>,
+///     flags: DrawFlags,
+/// }
+///
+/// impl Draw for FrameCounter {
+///     fn draw(
+///         &self,
+///         gl_handler: &mut GraphicsHandler,
+///         _command_buffer: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+///     ) {
+///         // draws recorded here must use this viewport and these stencil values
+///         let _dynamic_state = gl_handler.get_swapchain().get_dynamic_state();
+///         self.frames.set(self.frames.get() + 1);
+///     }
+///
+///     fn get_z_index(&self) -> u8 {
+///         0
+///     }
+///
+///     fn flush_data(&self) {}
+///
+///     fn write_flags(&mut self) -> &mut DrawFlags {
+///         &mut self.flags
+///     }
+///
+///     fn read_flags(&self) -> DrawFlags {
+///         self.flags
+///     }
+///
+///     fn set_dead(&mut self) {
+///         self.flags.remove(DrawFlags::USED);
+///     }
+///
+///     fn set_visible(&mut self, visible: bool) {
+///         self.flags.set(DrawFlags::VISIBLE, visible);
+///     }
+/// }
+///
+/// let mut engine = Engine::new()?;
+/// let counter = Rc::new(RefCell::new(FrameCounter {
+///     frames: Cell::new(0),
+///     flags: DrawFlags::VISIBLE,
+/// }));
+/// let _object = engine.get_ctx_handler().video.add_custom(counter);
+/// # Ok::<(), GraphicsInitError>(())
+/// ```
 pub trait Draw {
     fn draw(
         &self,
@@ -43,11 +125,35 @@ pub trait Draw {
 
     fn flush_data(&self);
 
+    /// Raw access for the engine, which relies on the flags it owns (e.g. an object without `USED` is dropped)
+    /// Users should change them with `set_flags`
     fn write_flags(&mut self) -> &mut DrawFlags;
     fn read_flags(&self) -> DrawFlags;
 
+    fn flags(&self) -> DrawFlags {
+        self.read_flags()
+    }
+
+    /// Set all the flags in `DrawFlags::USER` at once, ignoring the others: the engine's flags are kept as they are,
+    /// so a dead object stays dead and a mask stays a mask
+    fn set_flags(&mut self, flags: DrawFlags) {
+        let draw_flags = self.write_flags();
+        draw_flags.remove(DrawFlags::USER);
+        draw_flags.insert((flags & DrawFlags::USER) | DrawFlags::DIRTY);
+    }
+
     fn set_dead(&mut self);
     fn set_visible(&mut self, visible: bool);
+
+    /// State to save the object with, `None` for objects that can't be rebuilt from it
+    fn get_sprite_state(&self) -> Option<SpriteState> {
+        None
+    }
+
+    /// Data to draw the object in a sprite batch instead of calling `draw`, `None` for objects drawn on their own
+    fn batch_instance(&self) -> Option<(Arc<BatchDescriptorSet>, SpriteInstance)> {
+        None
+    }
 }
 
 pub type DrawObject<O> = Rc<RefCell<O>>;
@@ -59,7 +165,7 @@ type SpriteImmutableDescriptorSet = PersistentDescriptorSet<(
         (
             (
                 (),
-                PersistentDescriptorSetImg<Arc<ImageView<Arc<ImmutableImage>>>>,
+                PersistentDescriptorSetImg<Texture>,
             ),
             PersistentDescriptorSetSampler,
         ),
@@ -68,6 +174,17 @@ type SpriteImmutableDescriptorSet = PersistentDescriptorSet<(
     PersistentDescriptorSetBuf<Arc<CpuAccessibleBuffer<GlobalUniformData>>>,
 )>;
 
+type SpritePushDescriptorSet = PersistentDescriptorSet<(
+    (
+        (
+            (),
+            PersistentDescriptorSetImg<Texture>,
+        ),
+        PersistentDescriptorSetSampler,
+    ),
+    PersistentDescriptorSetBuf<Arc<CpuAccessibleBuffer<GlobalUniformData>>>,
+)>;
+
 /// User Accessible DrawObject dependent on the draw type
 pub struct GraphicObject<O: Draw + ?Sized> {
     draw_object: DrawObject<O>,
@@ -82,8 +199,72 @@ impl<O: Draw + ?Sized> GraphicObject<O> {
         self.draw_object.borrow()
     }
 
+    /// Handle to the object that doesn't keep it alive, e.g. to attach data to it
+    pub fn downgrade(&self) -> Weak<RefCell<O>> {
+        Rc::downgrade(&self.draw_object)
+    }
+
+    /// Mutable access to the object, which is then redrawn even in on-demand redraw mode
     pub fn get_mut(&self) -> RefMut<'_, O> {
-        self.draw_object.borrow_mut()
+        let mut object = self.draw_object.borrow_mut();
+        object.write_flags().insert(DrawFlags::DIRTY);
+        object
+    }
+
+    /// Change many fields at once, holding the borrow only for the duration of `f`
+    /// The object is marked as changed and its data gets flushed with the next frame
+    pub fn update<R>(&self, f: impl FnOnce(&mut O) -> R) -> R {
+        f(&mut self.get_mut())
+    }
+
+    pub fn set_visible(&self, visible: bool) {
+        self.get_mut().set_visible(visible);
+    }
+
+    pub fn flags(&self) -> DrawFlags {
+        self.draw_object.borrow().flags()
+    }
+
+    /// Configure several flags at once, see `Draw::set_flags`
+    pub fn set_flags(&self, flags: DrawFlags) {
+        self.get_mut().set_flags(flags);
+    }
+
+    pub fn is_visible(&self) -> bool {
+        self.draw_object
+            .borrow()
+            .read_flags()
+            .contains(DrawFlags::VISIBLE)
+    }
+
+    /// Whether the object was created with `screen_space`, pinned to the window
+    pub fn is_screen_space(&self) -> bool {
+        self.draw_object
+            .borrow()
+            .read_flags()
+            .contains(DrawFlags::SCREEN_SPACE)
+    }
+
+    pub fn show(&self) {
+        self.set_visible(true);
+    }
+
+    pub fn hide(&self) {
+        self.set_visible(false);
+    }
+}
+
+impl<O: Draw + 'static> GraphicObject<O> {
+    /// Shared handle to the object, as stored by the GraphicsHandler
+    pub fn get_draw_object(&self) -> DrawObject<dyn Draw> {
+        self.draw_object.clone()
+    }
+}
+
+impl GraphicObject<dyn Draw> {
+    /// Shared handle to an object added with `add_custom`
+    pub fn get_draw_object(&self) -> DrawObject<dyn Draw> {
+        self.draw_object.clone()
     }
 }
 
@@ -93,27 +274,50 @@ impl<O: Draw + ?Sized> Drop for GraphicObject<O> {
     }
 }
 
-fn draw<DescSet>(
+/// Record an indexed draw with one of the handler's pipelines, in the render pass and dynamic state of the frame
+/// Custom objects drawn with the built-in vertex layout can use it from their `Draw::draw`
+pub fn draw<DescSet, PushConstants>(
     gl_handler: &mut GraphicsHandler,
-    pipeline: Arc<GraphicsPipeline<SingleBufferDefinition<Vertex>>>,
+    pipeline_name: &str,
     cmnd_buf: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
     vertices: Arc<ImmutableBuffer<[Vertex]>>,
-    indices: Arc<dyn TypedBufferAccess<Content = [u16]> + Send + Sync>,
+    indices: IndexBuffer,
     sets: DescSet,
+    constants: PushConstants,
 ) where
     DescSet: DescriptorSetsCollection,
 {
-    cmnd_buf
-        .draw_indexed(
+    let pipeline = match gl_handler.get_draw_pipeline(pipeline_name) {
+        Some(pipeline) => pipeline,
+        None => {
+            gl_handler.warn_missing_pipeline(pipeline_name);
+            return;
+        }
+    };
+
+    let dynamic_state = gl_handler.get_swapchain().get_dynamic_state();
+
+    match indices {
+        IndexBuffer::U16(indices) => cmnd_buf.draw_indexed(
             pipeline,
-            &gl_handler.get_swapchain().get_dynamic_state(),
+            dynamic_state,
             vertices,
             indices,
             sets,
-            (),
+            constants,
             vec![],
-        )
-        .expect("Couldn't add Draw command to Vulkan Render Pass");
+        ),
+        IndexBuffer::U32(indices) => cmnd_buf.draw_indexed(
+            pipeline,
+            dynamic_state,
+            vertices,
+            indices,
+            sets,
+            constants,
+            vec![],
+        ),
+    }
+    .expect("Couldn't add Draw command to Vulkan Render Pass");
 }
 
 /// Struct to hold sprite specific data that both CPU and GPU must access
@@ -121,16 +325,61 @@ fn draw<DescSet>(
 struct SpriteData {
     color: Vector4<f32>,
     global_position: Vector4<f32>,
+    /// Scale in `xy`, -1 in `z` and `w` to mirror the texture horizontally and vertically (1 otherwise)
     scale: Vector4<f32>,
+    /// Texture size in `xy`, pixel snapping flag in `z`
+    image_dimensions: Vector4<u32>,
+    /// Region of the texture drawn (x, y, width, height in pixels)
+    source_rect: Vector4<u32>,
+}
+
+/// Sprite data recorded with each draw call when using `SpriteDataMode::PushConstants`
+#[derive(Copy, Clone, Debug)]
+#[repr(C)]
+struct SpritePushConstants {
+    color: Vector4<f32>,
+    /// Global position in `xy`, scale in `zw`
+    transform: Vector4<f32>,
+    /// Texture size in `xy`, pixel snapping flag in `z`, flip flags in `w` (see `Sprite::flip_bits`)
     image_dimensions: Vector4<u32>,
+    source_rect: Vector4<u32>,
+}
+
+/// How the data of a Sprite reaches the GPU
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+pub enum SpriteDataMode {
+    /// A uniform buffer per sprite, written every frame
+    UniformBuffer,
+    /// Push constants recorded with each draw, lighter for sprites that only move around
+    PushConstants,
+}
+
+impl SpriteDataMode {
+    fn pipeline_name(self) -> &'static str {
+        match self {
+            SpriteDataMode::UniformBuffer => "Sprite",
+            SpriteDataMode::PushConstants => "SpritePush",
+        }
+    }
+}
+
+#[derive(Clone)]
+enum SpriteBinding {
+    UniformBuffer {
+        descriptor_set: Arc<SpriteImmutableDescriptorSet>,
+        cpu_buffer: Arc<CpuAccessibleBuffer<SpriteData>>,
+    },
+    PushConstants {
+        descriptor_set: Arc<SpritePushDescriptorSet>,
+    },
 }
 
 /// Struct to handle sprite entities on screen capable of having transforms
 #[derive(Clone)]
 pub struct Sprite {
     vertex_buffer: VertexBuffer,
-    descriptor_set: Arc<SpriteImmutableDescriptorSet>,
-    cpu_buffer: Arc<CpuAccessibleBuffer<SpriteData>>,
+    binding: SpriteBinding,
 
     // flags and params
     z_index: u8,
@@ -140,10 +389,117 @@ pub struct Sprite {
     pub global_position: Vector2<f32>,
     pub scale: Vector2<f32>,
     image_dimensions: Vector2<u32>,
+    source_rect: Vector4<u32>,
+
+    /// File the texture was loaded from, `None` for textures built in memory
+    texture_path: Option<String>,
+    data_mode: SpriteDataMode,
+
+    // kept to bind the texture again with a different sampler
+    texture: Texture,
+    sampler_config: SamplerConfig,
+    global_buffer: Arc<GlobalUniformBuffer>,
+    /// Shared by the sprites that can be batched with this one
+    batch_set: Arc<BatchDescriptorSet>,
+}
+
+/// Plain copy of the state of a Sprite, to save it and build the Sprite again later
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+pub struct SpriteState {
+    pub texture_path: String,
+    pub data_mode: SpriteDataMode,
+    pub z_index: u8,
+    pub visible: bool,
+    pub color: Vector4<f32>,
+    pub global_position: Vector2<f32>,
+    pub scale: Vector2<f32>,
+    pub source_rect: Vector4<u32>,
+    /// Missing in states saved before pixel snapping existed
+    #[cfg_attr(feature = "serialize", serde(default))]
+    pub pixel_snap: bool,
+    /// Missing in states saved before flipping existed
+    #[cfg_attr(feature = "serialize", serde(default))]
+    pub flip_x: bool,
+    #[cfg_attr(feature = "serialize", serde(default))]
+    pub flip_y: bool,
 }
 
 impl Sprite {
     pub fn new(texture_path: &str, gl_handler: &GraphicsHandler, z_index: u8) -> Self {
+        Self::with_data_mode(
+            texture_path,
+            gl_handler,
+            z_index,
+            SpriteDataMode::UniformBuffer,
+        )
+    }
+
+    pub fn with_data_mode(
+        texture_path: &str,
+        gl_handler: &GraphicsHandler,
+        z_index: u8,
+        data_mode: SpriteDataMode,
+    ) -> Self {
+        let (texture, image_dimensions) = gl_handler.get_texture(texture_path);
+
+        let mut sprite = Self::with_texture(texture, image_dimensions, gl_handler, z_index, data_mode);
+        sprite.texture_path = Some(texture_path.to_string());
+
+        sprite
+    }
+
+    /// Create a Sprite showing an already uploaded texture (e.g. a `RenderTarget`)
+    pub fn from_texture(texture: Texture, image_dimensions: Vector2<u32>, gl_handler: &GraphicsHandler, z_index: u8) -> Self {
+        Self::with_texture(texture, image_dimensions, gl_handler, z_index, SpriteDataMode::UniformBuffer)
+    }
+
+    /// Like `from_texture`, drawn with the camera of `global_buffer` instead of the active one of the handler
+    fn with_global_buffer(
+        texture: Texture,
+        image_dimensions: Vector2<u32>,
+        gl_handler: &GraphicsHandler,
+        z_index: u8,
+        global_buffer: Arc<GlobalUniformBuffer>,
+    ) -> Self {
+        Self::build(texture, image_dimensions, gl_handler, z_index, SpriteDataMode::UniformBuffer, global_buffer)
+    }
+
+    /// Load a Sprite again from a saved state
+    pub fn from_state(state: &SpriteState, gl_handler: &GraphicsHandler) -> Self {
+        let mut sprite = Self::with_data_mode(&state.texture_path, gl_handler, state.z_index, state.data_mode);
+
+        sprite.color = state.color;
+        sprite.global_position = state.global_position;
+        sprite.scale = state.scale;
+        sprite.source_rect = state.source_rect;
+        sprite.set_pixel_snap(state.pixel_snap);
+        sprite.set_flip(state.flip_x, state.flip_y);
+        sprite.set_visible(state.visible);
+
+        sprite
+    }
+
+    /// Build a Sprite showing an uploaded texture, sampled with the current sampler config of the handler
+    fn with_texture(
+        texture: Texture,
+        image_dimensions: Vector2<u32>,
+        gl_handler: &GraphicsHandler,
+        z_index: u8,
+        data_mode: SpriteDataMode,
+    ) -> Self {
+        let global_buffer = gl_handler.get_global_uniform_buffer();
+        Self::build(texture, image_dimensions, gl_handler, z_index, data_mode, global_buffer)
+    }
+
+    fn build(
+        texture: Texture,
+        image_dimensions: Vector2<u32>,
+        gl_handler: &GraphicsHandler,
+        z_index: u8,
+        data_mode: SpriteDataMode,
+        global_buffer: Arc<GlobalUniformBuffer>,
+    ) -> Self {
         let vao = VertexArray::from(vec![
             Vertex {
                 vert_pos: [-1.0, -1.0],
@@ -158,57 +514,285 @@ impl Sprite {
                 vert_pos: [1.0, -1.0],
             },
         ]);
-        let indices = gl_handler.new_index_buffer(&[0, 1, 2, 2, 3, 0]);
-        let vertex_buffer = gl_handler.new_vertex_buffer(vao, indices);
-
-        let persistent_set = gl_handler.create_empty_descriptor_set_builder("Sprite", 0);
-        let sampler = gl_handler.create_texture_sampler();
+        let indices = gl_handler
+            .new_index_buffer(&[0, 1, 2, 2, 3, 0])
+            .expect("Couldn't create Index Buffer for Sprite object");
+        let vertex_buffer = gl_handler
+            .new_vertex_buffer(vao, indices)
+            .expect("Couldn't create Vertex Buffer for Sprite object");
 
         let color = Vector4::new(1.0, 1.0, 1.0, 1.0);
         let global_position = Vector2::new(0.0, 0.0);
         let scale = Vector2::new(1.0, 1.0);
-
-        let (persistent_set, image_dimensions) =
-            gl_handler.create_and_bind_texture(texture_path, persistent_set, sampler);
-
-        let sprite_data = SpriteData {
-            global_position: global_position.extend(0.0).extend(0.0),
-            color,
-            scale: scale.extend(0.0).extend(0.0),
-            image_dimensions: image_dimensions.extend(0).extend(0),
+        let source_rect = Vector4::new(0, 0, image_dimensions.x, image_dimensions.y);
+
+        let cpu_buffer = match data_mode {
+            SpriteDataMode::UniformBuffer => {
+                let sprite_data = SpriteData {
+                    global_position: global_position.extend(0.0).extend(0.0),
+                    color,
+                    scale: scale.extend(1.0).extend(1.0),
+                    image_dimensions: image_dimensions.extend(0).extend(0),
+                    source_rect,
+                };
+
+                let cpu_buffer = CpuAccessibleBuffer::from_data(
+                    gl_handler.get_device(),
+                    BufferUsage::uniform_buffer(),
+                    true,
+                    sprite_data,
+                )
+                .unwrap();
+
+                Some(cpu_buffer)
+            }
+            SpriteDataMode::PushConstants => None,
         };
 
-        let cpu_buffer = CpuAccessibleBuffer::from_data(
-            gl_handler.get_device(),
-            BufferUsage::uniform_buffer(),
-            true,
-            sprite_data,
-        )
-        .unwrap();
-
-        let persistent_set = persistent_set
-            .add_buffer(cpu_buffer.clone())
-            .unwrap()
-            .add_buffer(gl_handler.get_global_uniform_buffer())
-            .unwrap()
-            .build()
-            .expect("Couldn't build Persistent Descriptor Set for Sprite object");
-
-        let descriptor_set = Arc::new(persistent_set);
+        let sampler_config = gl_handler.get_sampler_config();
+        let binding = build_sprite_binding(
+            gl_handler,
+            texture.clone(),
+            sampler_config,
+            global_buffer.clone(),
+            cpu_buffer,
+        );
+        let batch_set = gl_handler.get_batch_descriptor_set(texture.clone(), sampler_config, global_buffer.clone());
 
         let mut draw_flags = DrawFlags::empty();
         draw_flags.insert(DrawFlags::USED | DrawFlags::VISIBLE);
 
         Self {
             vertex_buffer,
-            descriptor_set,
-            cpu_buffer,
+            binding,
             z_index,
             draw_flags,
             color,
             global_position,
             scale,
             image_dimensions,
+            source_rect,
+
+            texture_path: None,
+            data_mode,
+
+            texture,
+            sampler_config,
+            global_buffer,
+            batch_set,
+        }
+    }
+
+    pub fn get_sampler_config(&self) -> SamplerConfig {
+        self.sampler_config
+    }
+
+    /// Sample the texture with different options from now on, binding it again with a shared sampler
+    pub fn set_sampler_config(&mut self, config: SamplerConfig, gl_handler: &GraphicsHandler) {
+        if config == self.sampler_config {
+            return;
+        }
+
+        let cpu_buffer = match &self.binding {
+            SpriteBinding::UniformBuffer { cpu_buffer, .. } => Some(cpu_buffer.clone()),
+            SpriteBinding::PushConstants { .. } => None,
+        };
+
+        self.binding = build_sprite_binding(
+            gl_handler,
+            self.texture.clone(),
+            config,
+            self.global_buffer.clone(),
+            cpu_buffer,
+        );
+        self.batch_set = gl_handler.get_batch_descriptor_set(self.texture.clone(), config, self.global_buffer.clone());
+        self.sampler_config = config;
+    }
+
+    /// Move the sprite in front of or behind other objects, they are sorted again before the next frame
+    pub fn set_z_index(&mut self, z_index: u8) {
+        if z_index != self.z_index {
+            self.z_index = z_index;
+            self.draw_flags.insert(DrawFlags::Z_CHANGED | DrawFlags::DIRTY);
+        }
+    }
+
+    /// Alpha of the color the texture is multiplied by
+    pub fn get_opacity(&self) -> f32 {
+        self.color.w
+    }
+
+    /// Set only the alpha of the color, clamped between 0 (invisible) and 1 (opaque)
+    pub fn set_opacity(&mut self, alpha: f32) {
+        self.color.w = alpha.clamp(0.0, 1.0);
+    }
+
+    /// Region of the texture drawn (x, y, width, height in pixels), the whole image by default
+    pub fn get_source_rect(&self) -> Vector4<u32> {
+        self.source_rect
+    }
+
+    /// Draw only a region of the texture (x, y, width, height in pixels), e.g. a tile of a tileset
+    /// The sprite takes the size of the region, still multiplied by `scale`
+    pub fn set_source_rect(&mut self, rect: Vector4<u32>) {
+        self.source_rect = rect;
+    }
+
+    /// Draw the whole texture again
+    pub fn reset_source_rect(&mut self) {
+        self.source_rect = Vector4::new(0, 0, self.image_dimensions.x, self.image_dimensions.y);
+    }
+
+    /// Whether the position on screen is rounded to whole pixels, see `DrawFlags::PIXEL_SNAP`
+    pub fn get_pixel_snap(&self) -> bool {
+        self.draw_flags.contains(DrawFlags::PIXEL_SNAP)
+    }
+
+    pub fn set_pixel_snap(&mut self, pixel_snap: bool) {
+        self.draw_flags.set(DrawFlags::PIXEL_SNAP, pixel_snap);
+    }
+
+    /// Whether the texture is mirrored horizontally and vertically
+    pub fn get_flip(&self) -> (bool, bool) {
+        (self.draw_flags.contains(DrawFlags::FLIP_X), self.draw_flags.contains(DrawFlags::FLIP_Y))
+    }
+
+    /// Mirror the texture on each axis, it can be changed every frame
+    pub fn set_flip(&mut self, x: bool, y: bool) {
+        self.draw_flags.set(DrawFlags::FLIP_X, x);
+        self.draw_flags.set(DrawFlags::FLIP_Y, y);
+    }
+
+    /// Flips packed for the push constants and batch instances, bit 0 horizontal and bit 1 vertical
+    fn flip_bits(&self) -> u32 {
+        let (x, y) = self.get_flip();
+        x as u32 | ((y as u32) << 1)
+    }
+
+    /// Box covered by the sprite in world units (ignoring the camera), e.g. for collision checks
+    pub fn bounds(&self, gl_handler: &GraphicsHandler) -> Aabb {
+        // world units are half pixels with the centered projection
+        let pixel_size = match gl_handler.get_graphics_config().projection {
+            ProjectionMode::Centered => 2.0,
+            ProjectionMode::PixelTopLeft => 1.0,
+        };
+        let size = Vector2::new(
+            self.source_rect.z as f32 * self.scale.x,
+            self.source_rect.w as f32 * self.scale.y,
+        ) * pixel_size;
+
+        Aabb::from_center(self.global_position, size)
+    }
+
+    /// Move the sprite to the opposite edge of the view once it's completely out of it, like in Asteroids
+    pub fn wrap_to_view(&mut self, gl_handler: &GraphicsHandler) {
+        let view = gl_handler.visible_world_bounds();
+        let bounds = self.bounds(gl_handler);
+        // the sprite reappears just outside the other edge, so it slides back in
+        let distance = (view.max - view.min) + (bounds.max - bounds.min);
+
+        if bounds.min.x > view.max.x {
+            self.global_position.x -= distance.x;
+        } else if bounds.max.x < view.min.x {
+            self.global_position.x += distance.x;
+        }
+
+        if bounds.min.y > view.max.y {
+            self.global_position.y -= distance.y;
+        } else if bounds.max.y < view.min.y {
+            self.global_position.y += distance.y;
+        }
+    }
+
+    /// Keep the whole sprite inside the view, sprites larger than the view stay against its top left corner
+    pub fn clamp_to_view(&mut self, gl_handler: &GraphicsHandler) {
+        let view = gl_handler.visible_world_bounds();
+        let bounds = self.bounds(gl_handler);
+        let half = (bounds.max - bounds.min) / 2.0;
+
+        self.global_position.x = self.global_position.x.min(view.max.x - half.x).max(view.min.x + half.x);
+        self.global_position.y = self.global_position.y.min(view.max.y - half.y).max(view.min.y + half.y);
+    }
+
+    pub fn get_filter(&self) -> TextureFilter {
+        self.sampler_config.filter
+    }
+
+    /// Switch between sharp and smooth sampling, e.g. to snap to the pixel grid while paused
+    pub fn set_filter(&mut self, filter: TextureFilter, gl_handler: &GraphicsHandler) {
+        let config = SamplerConfig {
+            filter,
+            ..self.sampler_config
+        };
+        self.set_sampler_config(config, gl_handler);
+    }
+
+    /// Record the draw with another pipeline sharing the layout of "Sprite", like "SpriteAlphaTest"
+    /// Sprites with their data in push constants are always drawn with their own pipeline
+    fn draw_with_pipeline(
+        &self,
+        pipeline_name: &str,
+        gl_handler: &mut GraphicsHandler,
+        command_buffer: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+    ) {
+        match &self.binding {
+            SpriteBinding::UniformBuffer { descriptor_set, .. } => draw(
+                gl_handler,
+                pipeline_name,
+                command_buffer,
+                self.vertex_buffer.get_vertices(),
+                self.vertex_buffer.get_indices(),
+                descriptor_set.clone(),
+                (),
+            ),
+            SpriteBinding::PushConstants { .. } => Draw::draw(self, gl_handler, command_buffer),
+        }
+    }
+}
+
+/// Descriptor set of a Sprite, with its data in `cpu_buffer` (uniform buffer mode) or in push constants (`None`)
+fn build_sprite_binding(
+    gl_handler: &GraphicsHandler,
+    texture: Texture,
+    sampler_config: SamplerConfig,
+    global_buffer: Arc<GlobalUniformBuffer>,
+    cpu_buffer: Option<Arc<CpuAccessibleBuffer<SpriteData>>>,
+) -> SpriteBinding {
+    let data_mode = match cpu_buffer {
+        Some(_) => SpriteDataMode::UniformBuffer,
+        None => SpriteDataMode::PushConstants,
+    };
+
+    let persistent_set =
+        gl_handler.create_empty_descriptor_set_builder(data_mode.pipeline_name(), 0);
+    let sampler = gl_handler.create_texture_sampler_with_config(sampler_config);
+    let persistent_set = gl_handler.bind_texture(persistent_set, texture, sampler);
+
+    match cpu_buffer {
+        Some(cpu_buffer) => {
+            let persistent_set = persistent_set
+                .add_buffer(cpu_buffer.clone())
+                .unwrap()
+                .add_buffer(global_buffer)
+                .unwrap()
+                .build()
+                .expect("Couldn't build Persistent Descriptor Set for Sprite object");
+
+            SpriteBinding::UniformBuffer {
+                descriptor_set: Arc::new(persistent_set),
+                cpu_buffer,
+            }
+        }
+        None => {
+            let persistent_set = persistent_set
+                .add_buffer(global_buffer)
+                .unwrap()
+                .build()
+                .expect("Couldn't build Persistent Descriptor Set for Sprite object");
+
+            SpriteBinding::PushConstants {
+                descriptor_set: Arc::new(persistent_set),
+            }
         }
     }
 }
@@ -219,14 +803,31 @@ impl Draw for Sprite {
         gl_handler: &mut GraphicsHandler,
         command_buffer: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
     ) {
-        draw(
-            gl_handler,
-            gl_handler.get_pipeline("Sprite"),
-            command_buffer,
-            self.vertex_buffer.get_vertices(),
-            self.vertex_buffer.get_indices(),
-            self.descriptor_set.clone(),
-        )
+        match &self.binding {
+            SpriteBinding::UniformBuffer { descriptor_set, .. } => draw(
+                gl_handler,
+                "Sprite",
+                command_buffer,
+                self.vertex_buffer.get_vertices(),
+                self.vertex_buffer.get_indices(),
+                descriptor_set.clone(),
+                (),
+            ),
+            SpriteBinding::PushConstants { descriptor_set } => draw(
+                gl_handler,
+                "SpritePush",
+                command_buffer,
+                self.vertex_buffer.get_vertices(),
+                self.vertex_buffer.get_indices(),
+                descriptor_set.clone(),
+                SpritePushConstants {
+                    color: self.color,
+                    transform: self.global_position.extend(self.scale.x).extend(self.scale.y),
+                    image_dimensions: self.image_dimensions.extend(self.get_pixel_snap() as u32).extend(self.flip_bits()),
+                    source_rect: self.source_rect,
+                },
+            ),
+        }
     }
 
     fn get_z_index(&self) -> u8 {
@@ -234,12 +835,428 @@ impl Draw for Sprite {
     }
 
     fn flush_data(&self) {
-        let mut write_lock = self.cpu_buffer.write().expect("Couldn't write the buffer");
+        // push constants are recorded at draw time, there's no buffer to update
+        let cpu_buffer = match &self.binding {
+            SpriteBinding::UniformBuffer { cpu_buffer, .. } => cpu_buffer,
+            SpriteBinding::PushConstants { .. } => return,
+        };
+
+        let mut write_lock = cpu_buffer.write().expect("Couldn't write the buffer");
         let sprite_data = write_lock.deref_mut();
 
         sprite_data.color = self.color;
         sprite_data.global_position = self.global_position.extend(0.0).extend(0.0);
-        sprite_data.scale = self.scale.extend(0.0).extend(0.0);
+        let flip = |flipped: bool| if flipped { -1.0 } else { 1.0 };
+        let (flip_x, flip_y) = self.get_flip();
+        sprite_data.scale = self.scale.extend(flip(flip_x)).extend(flip(flip_y));
+        sprite_data.image_dimensions = self.image_dimensions.extend(self.get_pixel_snap() as u32).extend(0);
+        sprite_data.source_rect = self.source_rect;
+    }
+
+    fn write_flags(&mut self) -> &mut DrawFlags {
+        &mut self.draw_flags
+    }
+
+    fn read_flags(&self) -> DrawFlags {
+        self.draw_flags
+    }
+
+    fn set_dead(&mut self) {
+        self.draw_flags.remove(DrawFlags::USED);
+    }
+
+    fn set_visible(&mut self, visible: bool) {
+        self.draw_flags.set(DrawFlags::VISIBLE, visible);
+    }
+
+    fn get_sprite_state(&self) -> Option<SpriteState> {
+        Some(SpriteState {
+            texture_path: self.texture_path.clone()?,
+            data_mode: self.data_mode,
+            z_index: self.z_index,
+            visible: self.draw_flags.contains(DrawFlags::VISIBLE),
+            color: self.color,
+            global_position: self.global_position,
+            scale: self.scale,
+            source_rect: self.source_rect,
+            pixel_snap: self.get_pixel_snap(),
+            flip_x: self.get_flip().0,
+            flip_y: self.get_flip().1,
+        })
+    }
+
+    fn batch_instance(&self) -> Option<(Arc<BatchDescriptorSet>, SpriteInstance)> {
+        let instance = SpriteInstance {
+            color: self.color.into(),
+            transform: self.global_position.extend(self.scale.x).extend(self.scale.y).into(),
+            image_dimensions: self.image_dimensions.extend(self.get_pixel_snap() as u32).extend(self.flip_bits()).into(),
+            source_rect: self.source_rect.into(),
+        };
+
+        Some((self.batch_set.clone(), instance))
+    }
+}
+
+pub type AnimatedSpriteObject = GraphicObject<AnimatedSprite>;
+
+/// Sprite cycling through regions of its texture over time
+#[derive(Clone)]
+pub struct AnimatedSprite {
+    pub sprite: Sprite,
+
+    frames: Vec<Vector4<u32>>,
+    frame_durations: Vec<f32>,
+    current_frame: usize,
+    frame_time: f32,
+    /// Start again from the first frame after the last one, otherwise stop on the last frame
+    pub looping: bool,
+}
+
+impl AnimatedSprite {
+    /// Load all frames of an animated PNG, with the frame delays written in the file
+    pub fn from_file(texture_path: &str, gl_handler: &GraphicsHandler, z_index: u8) -> Result<Self, TextureError> {
+        let atlas = texture::decode_apng(texture_path)?;
+        let texture = gl_handler.upload_texture(atlas.data, atlas.dimensions)?;
+
+        let sprite = Sprite::with_texture(
+            texture,
+            atlas.dimensions,
+            gl_handler,
+            z_index,
+            SpriteDataMode::UniformBuffer,
+        );
+
+        let mut animated = Self {
+            sprite,
+            frames: atlas.frames,
+            frame_durations: atlas.frame_durations,
+            current_frame: 0,
+            frame_time: 0.0,
+            looping: atlas.looping,
+        };
+        animated.set_current_frame(0);
+
+        Ok(animated)
+    }
+
+    /// Animate a sprite through regions of its texture (x, y, width, height in pixels), e.g. the cells of a spritesheet
+    /// Every frame lasts `frame_duration` seconds and the animation loops, until `looping` is turned off
+    pub fn from_frames(sprite: Sprite, frames: Vec<Vector4<u32>>, frame_duration: f32) -> Self {
+        assert!(!frames.is_empty(), "Couldn't create an AnimatedSprite without frames");
+
+        // a frame must last some time, zero durations are shown for 100ms like the zero delays of animated PNGs
+        let frame_duration = if frame_duration > 0.0 { frame_duration } else { 0.1 };
+
+        let mut animated = Self {
+            sprite,
+            frame_durations: vec![frame_duration; frames.len()],
+            frames,
+            current_frame: 0,
+            frame_time: 0.0,
+            looping: true,
+        };
+        animated.set_current_frame(0);
+
+        animated
+    }
+
+    /// Advance the animation by `delta` seconds
+    pub fn update(&mut self, delta: f32) {
+        let (frame, frame_time) = advance_frame(
+            &self.frame_durations,
+            self.current_frame,
+            self.frame_time + delta,
+            self.looping,
+        );
+        self.frame_time = frame_time;
+
+        if frame != self.current_frame {
+            self.set_current_frame(frame);
+        }
+    }
+
+    pub fn get_current_frame(&self) -> usize {
+        self.current_frame
+    }
+
+    /// Jump to a specific frame of the animation
+    pub fn set_current_frame(&mut self, frame: usize) {
+        self.current_frame = frame.min(self.frames.len() - 1);
+        self.sprite.source_rect = self.frames[self.current_frame];
+    }
+
+    pub fn get_frames_count(&self) -> usize {
+        self.frames.len()
+    }
+}
+
+/// Frame shown after `frame_time` seconds spent since `frame` started, with the time already spent in it
+/// Past the last frame the animation starts again from the first one, or stays on the last one if not `looping`
+fn advance_frame(frame_durations: &[f32], mut frame: usize, mut frame_time: f32, looping: bool) -> (usize, f32) {
+    while frame_time >= frame_durations[frame] {
+        if frame + 1 == frame_durations.len() && !looping {
+            // the last frame stays on screen
+            return (frame, 0.0);
+        }
+
+        frame_time -= frame_durations[frame];
+        frame = (frame + 1) % frame_durations.len();
+    }
+
+    (frame, frame_time)
+}
+
+impl Draw for AnimatedSprite {
+    fn draw(
+        &self,
+        gl_handler: &mut GraphicsHandler,
+        command_buffer: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+    ) {
+        self.sprite.draw(gl_handler, command_buffer)
+    }
+
+    fn get_z_index(&self) -> u8 {
+        self.sprite.get_z_index()
+    }
+
+    fn flush_data(&self) {
+        self.sprite.flush_data()
+    }
+
+    fn write_flags(&mut self) -> &mut DrawFlags {
+        self.sprite.write_flags()
+    }
+
+    fn read_flags(&self) -> DrawFlags {
+        self.sprite.read_flags()
+    }
+
+    fn set_dead(&mut self) {
+        self.sprite.set_dead()
+    }
+
+    fn set_visible(&mut self, visible: bool) {
+        self.sprite.set_visible(visible)
+    }
+
+    fn batch_instance(&self) -> Option<(Arc<BatchDescriptorSet>, SpriteInstance)> {
+        self.sprite.batch_instance()
+    }
+}
+
+pub type TextObject = GraphicObject<Text>;
+
+/// Block of text drawn with a bitmap font, one sprite of the font texture for each character
+#[derive(Clone)]
+pub struct Text {
+    font: BitmapFont,
+    content: String,
+    style: TextStyle,
+    /// Glyph sprites with the distance of their centers from the top left corner of the text
+    glyphs: Vec<(Sprite, Vector2<f32>)>,
+    /// Shifted copies of the glyphs drawn under them in the outline color, in the same way
+    outline_glyphs: Vec<(Sprite, Vector2<f32>)>,
+    position: Vector2<f32>,
+    color: Vector4<f32>,
+    /// World units of a font pixel, fixed by the projection the text was created in
+    pixel_size: f32,
+    global_buffer: Arc<GlobalUniformBuffer>,
+    z_index: u8,
+    draw_flags: DrawFlags,
+}
+
+impl Text {
+    pub fn new(content: &str, font: &BitmapFont, position: Vector2<f32>, gl_handler: &GraphicsHandler, z_index: u8) -> Self {
+        // objects created in screen space are placed in window pixels, whatever the projection mode
+        let pixel_size = if gl_handler.is_screen_space() {
+            1.0
+        } else {
+            match gl_handler.get_graphics_config().projection {
+                ProjectionMode::Centered => 2.0,
+                ProjectionMode::PixelTopLeft => 1.0,
+            }
+        };
+
+        let mut draw_flags = DrawFlags::empty();
+        draw_flags.insert(DrawFlags::USED | DrawFlags::VISIBLE);
+
+        let mut text = Self {
+            font: font.clone(),
+            content: content.to_string(),
+            style: TextStyle::default(),
+            glyphs: Vec::new(),
+            outline_glyphs: Vec::new(),
+            position,
+            color: Vector4::new(1.0, 1.0, 1.0, 1.0),
+            pixel_size,
+            global_buffer: gl_handler.get_global_uniform_buffer(),
+            z_index,
+            draw_flags,
+        };
+        text.build_glyphs(gl_handler);
+
+        text
+    }
+
+    pub fn get_content(&self) -> &str {
+        &self.content
+    }
+
+    /// Change the text, rebuilding its glyphs
+    pub fn set_content(&mut self, content: &str, gl_handler: &GraphicsHandler) {
+        if content == self.content {
+            return;
+        }
+
+        self.content = content.to_string();
+        self.build_glyphs(gl_handler);
+    }
+
+    pub fn get_style(&self) -> TextStyle {
+        self.style
+    }
+
+    /// Lay the text out and build its glyphs again with another style
+    pub fn set_style(&mut self, style: TextStyle, gl_handler: &GraphicsHandler) {
+        self.style = style;
+        self.build_glyphs(gl_handler);
+    }
+
+    pub fn get_position(&self) -> Vector2<f32> {
+        self.position
+    }
+
+    /// Move the top left corner of the text
+    pub fn set_position(&mut self, position: Vector2<f32>) {
+        self.position = position;
+
+        for (glyph, offset) in self.glyphs.iter_mut().chain(self.outline_glyphs.iter_mut()) {
+            glyph.global_position = position + *offset;
+        }
+    }
+
+    pub fn get_color(&self) -> Vector4<f32> {
+        self.color
+    }
+
+    /// Tint of the glyphs, multiplied with the colors of the font texture
+    pub fn set_color(&mut self, color: Vector4<f32>) {
+        self.color = color;
+
+        for (glyph, _) in self.glyphs.iter_mut() {
+            glyph.color = color;
+        }
+    }
+
+    /// Move the text in front of or behind other objects, they are sorted again before the next frame
+    pub fn set_z_index(&mut self, z_index: u8) {
+        if z_index != self.z_index {
+            self.z_index = z_index;
+            self.draw_flags.insert(DrawFlags::Z_CHANGED | DrawFlags::DIRTY);
+
+            for (glyph, _) in self.glyphs.iter_mut().chain(self.outline_glyphs.iter_mut()) {
+                glyph.z_index = z_index;
+            }
+        }
+    }
+
+    /// Size (in pixels) of the text once laid out
+    pub fn get_size(&self) -> Vector2<f32> {
+        measure_text(&self.content, &self.style, &self.font)
+    }
+
+    fn build_glyphs(&mut self, gl_handler: &GraphicsHandler) {
+        let glyph_size = self.font.get_glyph_size();
+        let glyph_size = Vector2::new(glyph_size.x as f32, glyph_size.y as f32);
+        let outline = self.style.outline.filter(|outline| outline.width > 0.0);
+        let mut glyphs = Vec::new();
+        let mut outline_glyphs = Vec::new();
+
+        for line in layout_text(&self.content, &self.style, &self.font) {
+            let mut x = line.position.x;
+
+            for c in line.content.chars() {
+                // characters missing from the font (e.g. spaces in a font starting at '!') leave a gap
+                if let Some(rect) = self.font.glyph_rect(c) {
+                    let center = Vector2::new(x, line.position.y) + glyph_size / 2.0;
+
+                    if let Some(outline) = outline {
+                        for direction in OUTLINE_DIRECTIONS.iter() {
+                            let shift = Vector2::new(direction.0, direction.1) * outline.width;
+                            let offset = (center + shift) * self.pixel_size;
+                            outline_glyphs.push((self.new_glyph(rect, outline.color, offset, gl_handler), offset));
+                        }
+                    }
+
+                    let offset = center * self.pixel_size;
+                    glyphs.push((self.new_glyph(rect, self.color, offset, gl_handler), offset));
+                }
+
+                x += self.font.advance(c);
+            }
+        }
+
+        self.glyphs = glyphs;
+        self.outline_glyphs = outline_glyphs;
+    }
+
+    /// Sprite of the font texture showing a glyph, sampled as the antialiasing of the style asks
+    fn new_glyph(&self, rect: Vector4<u32>, color: Vector4<f32>, offset: Vector2<f32>, gl_handler: &GraphicsHandler) -> Sprite {
+        let (texture, dimensions) = self.font.get_texture();
+
+        let mut glyph = Sprite::with_global_buffer(texture, dimensions, gl_handler, self.z_index, self.global_buffer.clone());
+        glyph.set_source_rect(rect);
+        glyph.color = color;
+        glyph.global_position = self.position + offset;
+
+        let filter = match self.style.antialiasing {
+            TextAntialiasing::None => TextureFilter::Nearest,
+            TextAntialiasing::Grayscale => TextureFilter::Linear,
+        };
+        glyph.set_filter(filter, gl_handler);
+
+        glyph
+    }
+}
+
+/// Shifts of the copies of a glyph making its outline, scaled by the outline width
+const OUTLINE_DIRECTIONS: [(f32, f32); 8] = [
+    (-1.0, -1.0),
+    (0.0, -1.0),
+    (1.0, -1.0),
+    (-1.0, 0.0),
+    (1.0, 0.0),
+    (-1.0, 1.0),
+    (0.0, 1.0),
+    (1.0, 1.0),
+];
+
+impl Draw for Text {
+    fn draw(
+        &self,
+        gl_handler: &mut GraphicsHandler,
+        command_buffer: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+    ) {
+        // hard edges discard the half transparent pixels left by the sampling
+        let pipeline_name = match self.style.antialiasing {
+            TextAntialiasing::None => "SpriteAlphaTest",
+            TextAntialiasing::Grayscale => "Sprite",
+        };
+
+        // the outline goes under the text, objects are drawn in order
+        for (glyph, _) in self.outline_glyphs.iter().chain(self.glyphs.iter()) {
+            glyph.draw_with_pipeline(pipeline_name, gl_handler, command_buffer);
+        }
+    }
+
+    fn get_z_index(&self) -> u8 {
+        self.z_index
+    }
+
+    fn flush_data(&self) {
+        for (glyph, _) in self.glyphs.iter().chain(self.outline_glyphs.iter()) {
+            glyph.flush_data();
+        }
     }
 
     fn write_flags(&mut self) -> &mut DrawFlags {
@@ -269,11 +1286,13 @@ type PrimitiveImmutableDescriptorSet = PersistentDescriptorSet<(
 pub type PrimitiveObject = GraphicObject<Primitive>;
 
 /// Struct to hold sprite specific data that both CPU and GPU must access
+/// The `z` component of `global_position` holds the rotation of the shape
 #[derive(Copy, Clone, Debug)]
 struct PrimitiveData {
     color: Vector4<f32>,
     global_position: Vector4<f32>,
     scale: Vector4<f32>,
+    corner_radii: Vector4<f32>,
 }
 
 /// Struct to handle primitive shapes with simple colours
@@ -282,6 +1301,8 @@ pub struct Primitive {
     vertex_buffer: VertexBuffer,
     descriptor_set: Arc<PrimitiveImmutableDescriptorSet>,
     cpu_buffer: Arc<CpuAccessibleBuffer<PrimitiveData>>,
+    /// Pipeline drawing the shape, SDF shapes use their own fragment shader
+    pipeline_name: &'static str,
 
     // general flags and params
     z_index: u8,
@@ -290,23 +1311,40 @@ pub struct Primitive {
     pub color: Vector4<f32>,
     pub global_position: Vector2<f32>,
     pub scale: Vector2<f32>,
+    /// Rotation (in radians) around the `global_position`, clockwise on screen
+    pub rotation: f32,
+    /// Radii of the top left, top right, bottom right and bottom left corners (only used by rounded rectangles)
+    pub corner_radii: Vector4<f32>,
 }
 
 impl Primitive {
     /// Complex function to create custom shapes
     /// Should be avoided in favour of premade shapes
-    pub fn new(vertex_array: VertexArray, index_array: &[u16], scale: Vector2<f32>, color: Vector4<f32>, global_position: Vector2<f32>, gl_handler: &GraphicsHandler, z_index: u8) -> Self {
-        let indices = gl_handler.new_index_buffer(index_array);
+    pub fn new(vertex_array: VertexArray, index_array: &[u32], scale: Vector2<f32>, color: Vector4<f32>, global_position: Vector2<f32>, gl_handler: &GraphicsHandler, z_index: u8) -> Self {
+        Self::with_pipeline("Primitive", vertex_array, index_array, scale, color, global_position, gl_handler, z_index)
+    }
 
-        let vertex_buffer = gl_handler.new_vertex_buffer(vertex_array, indices);
+    #[allow(clippy::too_many_arguments)]
+    fn with_pipeline(pipeline_name: &'static str, vertex_array: VertexArray, index_array: &[u32], scale: Vector2<f32>, color: Vector4<f32>, global_position: Vector2<f32>, gl_handler: &GraphicsHandler, z_index: u8) -> Self {
+        let indices = gl_handler
+            .new_index_buffer(index_array)
+            .expect("Couldn't create Index Buffer for Primitive object");
 
-        let persistent_set = gl_handler.create_empty_descriptor_set_builder("Primitive", 0);
+        let vertex_buffer = gl_handler
+            .new_vertex_buffer(vertex_array, indices)
+            .expect("Couldn't create Vertex Buffer for Primitive object");
 
+        let persistent_set = gl_handler.create_empty_descriptor_set_builder(pipeline_name, 0);
+
+
+        let rotation = 0.0;
+        let corner_radii = Vector4::new(0.0, 0.0, 0.0, 0.0);
 
         let primitive_data = PrimitiveData {
-            global_position: global_position.extend(0.0).extend(0.0),
+            global_position: global_position.extend(rotation).extend(0.0),
             color,
             scale: scale.extend(0.0).extend(0.0),
+            corner_radii,
         };
 
         let cpu_buffer = CpuAccessibleBuffer::from_data(
@@ -334,33 +1372,56 @@ impl Primitive {
             vertex_buffer,
             descriptor_set,
             cpu_buffer,
+            pipeline_name,
             z_index,
             draw_flags,
             color,
             global_position,
             scale,
+            rotation,
+            corner_radii,
         }
     }
 
     /// Create a new Primitive of rectangular shape
     /// Here the `scale` parameter is also the dimensions of a pre-built rectangle (a Vector2(1.0, 1.0) would be a pixel when zoom isn't applied)
     pub fn rectangle(scale: Vector2<f32>, color: Vector4<f32>, global_position: Vector2<f32>, gl_handler: &GraphicsHandler, z_index: u8) -> Self {
-        let vao = VertexArray::from(vec![
-            Vertex {
-                vert_pos: [-1.0, -1.0],
-            },
-            Vertex {
-                vert_pos: [-1.0, 1.0],
-            },
-            Vertex {
-                vert_pos: [1.0, 1.0],
-            },
-            Vertex {
-                vert_pos: [1.0, -1.0],
-            },
-        ]);
-        
-        Self::new(vao, &[0, 1, 2, 2, 3, 0], scale, color, global_position, gl_handler, z_index)
+        Self::new(quad_vertices(), &[0, 1, 2, 2, 3, 0], scale, color, global_position, gl_handler, z_index)
+    }
+
+    /// Create a circle approximated by a polygon of `segments` sides (at least 3)
+    /// `radius` is in the same units as the dimensions of `rectangle`, see `sdf_circle` for smooth edges
+    pub fn circle(radius: f32, segments: u32, color: Vector4<f32>, global_position: Vector2<f32>, gl_handler: &GraphicsHandler, z_index: u8) -> Self {
+        let scale = Vector2::new(radius * 2.0, radius * 2.0);
+
+        let (vertices, indices) = circle_strip(segments);
+
+        Self::new(VertexArray::from(vertices), &indices, scale, color, global_position, gl_handler, z_index)
+    }
+
+    /// Create a circle computed in the fragment shader on a single quad, with antialiased edges at any size
+    /// `radius` is in the same units as the dimensions of `rectangle`
+    pub fn sdf_circle(radius: f32, color: Vector4<f32>, global_position: Vector2<f32>, gl_handler: &GraphicsHandler, z_index: u8) -> Self {
+        let scale = Vector2::new(radius * 2.0, radius * 2.0);
+
+        Self::with_pipeline("SdfCircle", quad_vertices(), &[0, 1, 2, 2, 3, 0], scale, color, global_position, gl_handler, z_index)
+    }
+
+    /// Create a rectangle with rounded corners computed in the fragment shader on a single quad
+    /// Each corner can then get its own radius through `corner_radii`
+    pub fn rounded_rectangle(scale: Vector2<f32>, corner_radius: f32, color: Vector4<f32>, global_position: Vector2<f32>, gl_handler: &GraphicsHandler, z_index: u8) -> Self {
+        let mut primitive = Self::with_pipeline("SdfRoundedRectangle", quad_vertices(), &[0, 1, 2, 2, 3, 0], scale, color, global_position, gl_handler, z_index);
+        primitive.corner_radii = Vector4::new(corner_radius, corner_radius, corner_radius, corner_radius);
+
+        primitive
+    }
+
+    /// Move the shape in front of or behind other objects, they are sorted again before the next frame
+    pub fn set_z_index(&mut self, z_index: u8) {
+        if z_index != self.z_index {
+            self.z_index = z_index;
+            self.draw_flags.insert(DrawFlags::Z_CHANGED | DrawFlags::DIRTY);
+        }
     }
 }
 
@@ -372,11 +1433,12 @@ impl Draw for Primitive {
     ) {
         draw(
             gl_handler,
-            gl_handler.get_pipeline("Primitive"),
+            self.pipeline_name,
             command_buffer,
             self.vertex_buffer.get_vertices(),
             self.vertex_buffer.get_indices(),
             self.descriptor_set.clone(),
+            (),
         )
     }
 
@@ -389,8 +1451,10 @@ impl Draw for Primitive {
         let sprite_data = write_lock.deref_mut();
 
         sprite_data.color = self.color;
-        sprite_data.global_position = self.global_position.extend(0.0).extend(0.0);
+        sprite_data.global_position = self.global_position.extend(self.rotation).extend(0.0);
         sprite_data.scale = self.scale.extend(0.0).extend(0.0);
+        // the shape is laid out in half pixels, twice the units of `scale`
+        sprite_data.corner_radii = self.corner_radii * 2.0;
     }
 
     fn write_flags(&mut self) -> &mut DrawFlags {
@@ -409,3 +1473,197 @@ impl Draw for Primitive {
         self.draw_flags.set(DrawFlags::VISIBLE, visible);
     }
 }
+
+/// Vertices of a regular polygon inscribed in the circle of radius 1, with the indices of a triangle strip covering it
+/// Pipelines draw triangle strips, so instead of a fan around a center the strip zigzags between the two sides
+/// of the rim (0, 1, n - 1, 2, n - 2...), which covers a convex polygon without overlapping triangles
+/// There's no center vertex, so the strip has one vertex per segment (at least 3, a triangle)
+fn circle_strip(segments: u32) -> (Vec<Vertex>, Vec<u32>) {
+    let segments = segments.max(3);
+    let vertices = (0..segments)
+        .map(|i| {
+            let angle = i as f32 / segments as f32 * std::f32::consts::TAU;
+            Vertex {
+                vert_pos: [angle.cos(), angle.sin()],
+            }
+        })
+        .collect();
+
+    let indices = (0..segments)
+        .map(|i| if i % 2 == 1 { (i + 1) / 2 } else { (segments - i / 2) % segments })
+        .collect();
+
+    (vertices, indices)
+}
+
+/// Vertices of a quad going from -1 to 1 on both axes
+fn quad_vertices() -> VertexArray {
+    VertexArray::from(vec![
+        Vertex {
+            vert_pos: [-1.0, -1.0],
+        },
+        Vertex {
+            vert_pos: [-1.0, 1.0],
+        },
+        Vertex {
+            vert_pos: [1.0, 1.0],
+        },
+        Vertex {
+            vert_pos: [1.0, -1.0],
+        },
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sprite_state() -> SpriteState {
+        SpriteState {
+            texture_path: String::from("assets/rust.png"),
+            data_mode: SpriteDataMode::PushConstants,
+            z_index: 3,
+            visible: false,
+            color: Vector4::new(0.25, 0.5, 0.75, 0.5),
+            global_position: Vector2::new(-12.5, 40.0),
+            scale: Vector2::new(2.0, 0.5),
+            source_rect: Vector4::new(4, 8, 16, 32),
+            pixel_snap: true,
+            flip_x: true,
+            flip_y: false,
+        }
+    }
+
+    #[cfg(feature = "serialize")]
+    #[test]
+    fn sprite_state_survives_serialization() {
+        let state = sprite_state();
+
+        let json = serde_json::to_string(&state).unwrap();
+        let loaded: SpriteState = serde_json::from_str(&json).unwrap();
+
+        assert_eq!(loaded, state);
+    }
+
+    #[cfg(feature = "serialize")]
+    #[test]
+    fn sprite_state_loads_without_the_newer_fields() {
+        let mut json = serde_json::to_value(&sprite_state()).unwrap();
+        for field in ["pixel_snap", "flip_x", "flip_y"].iter() {
+            json.as_object_mut().unwrap().remove(*field);
+        }
+
+        let loaded: SpriteState = serde_json::from_value(json).unwrap();
+
+        assert!(!loaded.pixel_snap && !loaded.flip_x && !loaded.flip_y);
+    }
+
+    #[test]
+    #[ignore = "needs a display and a Vulkan device"]
+    fn sprite_state_is_kept_by_from_state() {
+        let sdl = sdl2::init().unwrap();
+        let video = crate::engine::ctx::video::VideoHandler::new(&sdl).unwrap();
+
+        let state = sprite_state();
+        let sprite = Sprite::from_state(&state, &video.gl_handler);
+
+        assert_eq!(sprite.get_sprite_state(), Some(state));
+    }
+
+    /// Object with flags only, to check the default methods of `Draw`
+    struct FlagsOnly(DrawFlags);
+
+    impl Draw for FlagsOnly {
+        fn draw(&self, _: &mut GraphicsHandler, _: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>) {}
+
+        fn get_z_index(&self) -> u8 {
+            0
+        }
+
+        fn flush_data(&self) {}
+
+        fn write_flags(&mut self) -> &mut DrawFlags {
+            &mut self.0
+        }
+
+        fn read_flags(&self) -> DrawFlags {
+            self.0
+        }
+
+        fn set_dead(&mut self) {
+            self.0.remove(DrawFlags::USED);
+        }
+
+        fn set_visible(&mut self, visible: bool) {
+            self.0.set(DrawFlags::VISIBLE, visible);
+        }
+    }
+
+    #[test]
+    fn set_flags_changes_the_user_flags_only() {
+        let mut object = FlagsOnly(DrawFlags::USED | DrawFlags::VISIBLE | DrawFlags::MASK);
+
+        object.set_flags(DrawFlags::PIXEL_SNAP | DrawFlags::FLIP_X | DrawFlags::SCREEN_SPACE);
+
+        assert_eq!(
+            object.flags(),
+            DrawFlags::USED | DrawFlags::MASK | DrawFlags::DIRTY | DrawFlags::PIXEL_SNAP | DrawFlags::FLIP_X
+        );
+    }
+
+    #[test]
+    fn circle_strip_has_one_vertex_per_segment() {
+        for &segments in [3, 4, 5, 32, 33].iter() {
+            let (vertices, indices) = circle_strip(segments);
+
+            assert_eq!(vertices.len(), segments as usize);
+            // every vertex is used once by the strip
+            let mut used = indices.clone();
+            used.sort_unstable();
+            assert_eq!(used, (0..segments).collect::<Vec<u32>>());
+
+            for vertex in vertices {
+                let [x, y] = vertex.vert_pos;
+                assert!(((x * x + y * y).sqrt() - 1.0).abs() < 1e-5);
+            }
+        }
+    }
+
+    #[test]
+    fn circle_strip_is_at_least_a_triangle() {
+        for &segments in [0, 1, 2].iter() {
+            let (vertices, indices) = circle_strip(segments);
+
+            assert_eq!(vertices.len(), 3);
+            assert_eq!(indices.len(), 3);
+        }
+    }
+
+    #[test]
+    fn looping_animation_wraps_to_the_first_frame() {
+        // 3 frames of 0.1s, 0.35s in is 0.05s into the first frame of the second loop
+        let (frame, frame_time) = advance_frame(&[0.1; 3], 0, 0.35, true);
+
+        assert_eq!(frame, 0);
+        assert!((frame_time - 0.05).abs() < 1e-5);
+    }
+
+    #[test]
+    fn animation_without_looping_stops_on_the_last_frame() {
+        let (frame, frame_time) = advance_frame(&[0.1; 3], 0, 0.35, false);
+
+        assert_eq!(frame, 2);
+        assert_eq!(frame_time, 0.0);
+
+        // more time doesn't move it anymore
+        assert_eq!(advance_frame(&[0.1; 3], frame, 1.0, false), (2, 0.0));
+    }
+
+    #[test]
+    fn animation_keeps_the_time_spent_in_the_current_frame() {
+        let (frame, frame_time) = advance_frame(&[0.1, 0.5, 0.1], 0, 0.3, true);
+
+        assert_eq!(frame, 1);
+        assert!((frame_time - 0.2).abs() < 1e-5);
+    }
+}
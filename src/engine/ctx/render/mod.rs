@@ -1,3 +1,10 @@
 pub mod vulkan;
 pub mod draw_objects;
+pub mod text;
+mod background;
+mod batch;
+mod debug_draw;
 mod sendable;
+pub mod texture;
+mod texture_cache;
+pub mod texture_loader;
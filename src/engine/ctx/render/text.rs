@@ -0,0 +1,255 @@
+//! Layout of text in lines, independent of the way glyphs are drawn, and bitmap fonts to draw it with
+
+// vulkan implementation imports
+use super::vulkan::{GraphicsHandler, Texture, TextureError};
+
+// other imports
+use cgmath::{Vector2, Vector4};
+
+/// Horizontal alignment of the lines of a text
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TextAlign {
+    Left,
+    Center,
+    Right,
+}
+
+/// How the edges of the glyphs are sampled
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TextAntialiasing {
+    /// Hard edges, for pixel fonts: nearest sampling, and the pixels less than half opaque are discarded
+    None,
+    /// Smooth edges, from linear sampling
+    Grayscale,
+}
+
+/// Stroke drawn around the glyphs, to keep text readable over busy backgrounds
+/// Each glyph is drawn again under the text, shifted in 8 directions and tinted with `color`
+#[derive(Copy, Clone, Debug)]
+pub struct TextOutline {
+    pub color: Vector4<f32>,
+    /// Thickness (in pixels) of the stroke outside the glyphs
+    pub width: f32,
+}
+
+/// Layout and rendering options of a text
+#[derive(Copy, Clone, Debug)]
+pub struct TextStyle {
+    /// Width (in pixels) after which lines are wrapped, `None` to only break on new lines
+    pub max_width: Option<f32>,
+    pub align: TextAlign,
+    /// Distance between the tops of two lines, as a multiple of the line height
+    pub line_spacing: f32,
+    pub antialiasing: TextAntialiasing,
+    pub outline: Option<TextOutline>,
+}
+
+impl Default for TextStyle {
+    fn default() -> Self {
+        Self {
+            max_width: None,
+            align: TextAlign::Left,
+            line_spacing: 1.0,
+            antialiasing: TextAntialiasing::Grayscale,
+            outline: None,
+        }
+    }
+}
+
+impl TextStyle {
+    /// Space (in pixels) taken by the outline on each side of the glyphs
+    pub fn outline_width(&self) -> f32 {
+        self.outline.map_or(0.0, |outline| outline.width.max(0.0))
+    }
+}
+
+/// Size information of the glyphs of a font
+pub trait GlyphMetrics {
+    /// Horizontal distance (in pixels) from this glyph to the next one
+    fn advance(&self, c: char) -> f32;
+    fn line_height(&self) -> f32;
+}
+
+/// Monospace font read from a texture atlas: a grid of glyphs of the same size,
+/// stored left to right and top to bottom in character code order
+#[derive(Clone)]
+pub struct BitmapFont {
+    texture: Texture,
+    dimensions: Vector2<u32>,
+    glyph_size: Vector2<u32>,
+    first_char: u32,
+    columns: u32,
+    count: u32,
+}
+
+impl BitmapFont {
+    /// Load a PNG atlas (through the texture cache) with cells of `glyph_size` pixels,
+    /// the top left one being `first_char` (usually `' '`)
+    pub fn from_file(
+        path: &str,
+        glyph_size: Vector2<u32>,
+        first_char: char,
+        gl_handler: &GraphicsHandler,
+    ) -> Result<Self, TextureError> {
+        let (texture, dimensions) = gl_handler.try_get_texture(path)?;
+
+        let glyph_size = Vector2::new(glyph_size.x.max(1), glyph_size.y.max(1));
+        let columns = dimensions.x / glyph_size.x;
+        let rows = dimensions.y / glyph_size.y;
+
+        Ok(Self {
+            texture,
+            dimensions,
+            glyph_size,
+            first_char: first_char as u32,
+            columns,
+            count: columns * rows,
+        })
+    }
+
+    /// Texture of the atlas and its dimensions
+    pub fn get_texture(&self) -> (Texture, Vector2<u32>) {
+        (self.texture.clone(), self.dimensions)
+    }
+
+    pub fn get_glyph_size(&self) -> Vector2<u32> {
+        self.glyph_size
+    }
+
+    /// Region of the atlas (x, y, width, height) drawing `c`, `None` if the font doesn't have it
+    pub fn glyph_rect(&self, c: char) -> Option<Vector4<u32>> {
+        let index = (c as u32).checked_sub(self.first_char).filter(|i| *i < self.count)?;
+
+        Some(Vector4::new(
+            (index % self.columns) * self.glyph_size.x,
+            (index / self.columns) * self.glyph_size.y,
+            self.glyph_size.x,
+            self.glyph_size.y,
+        ))
+    }
+}
+
+impl GlyphMetrics for BitmapFont {
+    fn advance(&self, _c: char) -> f32 {
+        self.glyph_size.x as f32
+    }
+
+    fn line_height(&self) -> f32 {
+        self.glyph_size.y as f32
+    }
+}
+
+/// Line of text positioned relatively to the top left corner of the text block
+#[derive(Clone, Debug)]
+pub struct TextLine {
+    pub content: String,
+    pub position: Vector2<f32>,
+    pub width: f32,
+}
+
+/// Break a text in lines (on new lines and, with a `max_width`, on whitespace) and align them
+pub fn layout_text(text: &str, style: &TextStyle, metrics: &impl GlyphMetrics) -> Vec<TextLine> {
+    let mut lines = Vec::new();
+
+    for paragraph in text.split('\n') {
+        match style.max_width {
+            Some(max_width) => wrap_paragraph(paragraph, max_width, metrics, &mut lines),
+            None => lines.push(paragraph.to_string()),
+        }
+    }
+
+    let widths: Vec<f32> = lines.iter().map(|l| line_width(l, metrics)).collect();
+
+    // lines are aligned inside the wrapping width, or inside the widest line without one
+    let block_width = style
+        .max_width
+        .unwrap_or_else(|| widths.iter().cloned().fold(0.0, f32::max));
+    let line_step = metrics.line_height() * style.line_spacing;
+    // the outline of the first line and column must fit in the block too
+    let outline_width = style.outline_width();
+
+    lines
+        .into_iter()
+        .zip(widths)
+        .enumerate()
+        .map(|(i, (content, width))| {
+            let x = match style.align {
+                TextAlign::Left => 0.0,
+                TextAlign::Center => (block_width - width) / 2.0,
+                TextAlign::Right => block_width - width,
+            };
+
+            TextLine {
+                content,
+                position: Vector2::new(x + outline_width, i as f32 * line_step + outline_width),
+                width,
+            }
+        })
+        .collect()
+}
+
+/// Size (in pixels) of the block of text once laid out
+pub fn measure_text(text: &str, style: &TextStyle, metrics: &impl GlyphMetrics) -> Vector2<f32> {
+    let lines = layout_text(text, style, metrics);
+
+    let outline_width = style.outline_width();
+    let width = lines.iter().map(|l| l.width).fold(0.0, f32::max) + 2.0 * outline_width;
+    let height = match lines.last() {
+        Some(last) => last.position.y + metrics.line_height() + outline_width,
+        None => 0.0,
+    };
+
+    Vector2::new(width, height)
+}
+
+fn line_width(line: &str, metrics: &impl GlyphMetrics) -> f32 {
+    line.chars().map(|c| metrics.advance(c)).sum()
+}
+
+/// Greedy word wrap of a paragraph without new lines
+fn wrap_paragraph(
+    paragraph: &str,
+    max_width: f32,
+    metrics: &impl GlyphMetrics,
+    lines: &mut Vec<String>,
+) {
+    let space_width = metrics.advance(' ');
+    let mut current = String::new();
+    let mut current_width = 0.0;
+
+    for word in paragraph.split_whitespace() {
+        let word_width = line_width(word, metrics);
+
+        if !current.is_empty() && current_width + space_width + word_width <= max_width {
+            current.push(' ');
+            current.push_str(word);
+            current_width += space_width + word_width;
+            continue;
+        }
+
+        if !current.is_empty() {
+            lines.push(std::mem::take(&mut current));
+            current_width = 0.0;
+        }
+
+        if word_width <= max_width {
+            current.push_str(word);
+            current_width = word_width;
+            continue;
+        }
+
+        // words longer than a whole line are broken between characters
+        for c in word.chars() {
+            let advance = metrics.advance(c);
+            if !current.is_empty() && current_width + advance > max_width {
+                lines.push(std::mem::take(&mut current));
+                current_width = 0.0;
+            }
+            current.push(c);
+            current_width += advance;
+        }
+    }
+
+    // empty paragraphs still take a line
+    lines.push(current);
+}
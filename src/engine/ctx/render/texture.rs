@@ -0,0 +1,381 @@
+//! CPU side decoding of image files, before their upload to the GPU
+
+// standard imports
+use std::fs::File;
+use std::io::Read;
+
+// imports from the module
+use super::vulkan::TextureError;
+
+// other imports
+use cgmath::{Vector2, Vector4};
+use png::{BitDepth, BlendOp, ColorType, DisposeOp, FrameControl};
+
+/// Animation decoded from an APNG file, with all its frames laid out in a grid on a single RGBA image
+pub struct AnimationAtlas {
+    pub data: Vec<u8>,
+    pub dimensions: Vector2<u32>,
+    /// Region of the atlas used by each frame (x, y, width, height)
+    pub frames: Vec<Vector4<u32>>,
+    /// Time (in seconds) each frame stays on screen
+    pub frame_durations: Vec<f32>,
+    /// Whether the file asks to repeat the animation forever
+    pub looping: bool,
+}
+
+/// Decode every frame of an animated PNG (a still PNG results in a single frame animation)
+/// Frames are composited following their blend and dispose operations, so each atlas cell is a full frame
+pub fn decode_apng(path: &str) -> Result<AnimationAtlas, TextureError> {
+    read_apng(File::open(path)?)
+}
+
+/// Decode an animated PNG from any reader, see `decode_apng`
+fn read_apng(source: impl Read) -> Result<AnimationAtlas, TextureError> {
+    let decoder = png::Decoder::new(source);
+    let (info, mut reader) = decoder.read_info()?;
+
+    let (color_type, bit_depth) = reader.output_color_type();
+    if bit_depth != BitDepth::Eight {
+        return Err(TextureError::UnsupportedFormat(color_type, bit_depth));
+    }
+
+    let (width, height) = (info.width, info.height);
+    let animation = reader.info().animation_control;
+    let frames_count = animation.map_or(1, |a| a.num_frames as usize);
+    let looping = animation.map_or(true, |a| a.num_plays == 0);
+
+    let mut buf = vec![0; reader.output_buffer_size()];
+    let mut canvas = vec![0; (width * height * 4) as usize];
+    let mut frames_data = Vec::with_capacity(frames_count);
+    let mut frame_durations = Vec::with_capacity(frames_count);
+
+    // The default image is part of the animation only when a frame control precedes it
+    reader.next_frame(&mut buf)?;
+    let default_control = reader.info().frame_control;
+    if animation.is_none() || default_control.is_some() {
+        let control = default_control.unwrap_or(FrameControl {
+            width,
+            height,
+            ..FrameControl::default()
+        });
+        let rgba = expand_to_rgba(&buf, color_type).ok_or(TextureError::UnsupportedFormat(color_type, bit_depth))?;
+        check_frame_bounds(&control, width, height)?;
+        compose_frame(&mut canvas, width, &rgba, &control, &mut frames_data, &mut frame_durations);
+    }
+
+    while frames_data.len() < frames_count {
+        reader.next_frame(&mut buf)?;
+        let control = reader
+            .info()
+            .frame_control
+            .ok_or_else(|| png::DecodingError::Format("missing frame control in animated PNG".into()))?;
+        let rgba = expand_to_rgba(&buf, color_type).ok_or(TextureError::UnsupportedFormat(color_type, bit_depth))?;
+        check_frame_bounds(&control, width, height)?;
+        compose_frame(&mut canvas, width, &rgba, &control, &mut frames_data, &mut frame_durations);
+    }
+
+    if frames_data.is_empty() {
+        return Err(TextureError::NoFrames);
+    }
+
+    // Lay the frames out in a grid as square as possible, to stay far from the texture size limits
+    let columns = (frames_data.len() as f32).sqrt().ceil() as u32;
+    let rows = (frames_data.len() as u32 + columns - 1) / columns;
+    let dimensions = Vector2::new(columns * width, rows * height);
+
+    let mut data = vec![0; (dimensions.x * dimensions.y * 4) as usize];
+    let mut frames = Vec::with_capacity(frames_data.len());
+    for (i, frame) in frames_data.iter().enumerate() {
+        let x = (i as u32 % columns) * width;
+        let y = (i as u32 / columns) * height;
+
+        for row in 0..height {
+            let src = (row * width * 4) as usize;
+            let dst = (((y + row) * dimensions.x + x) * 4) as usize;
+            let len = (width * 4) as usize;
+            data[dst..dst + len].copy_from_slice(&frame[src..src + len]);
+        }
+
+        frames.push(Vector4::new(x, y, width, height));
+    }
+
+    Ok(AnimationAtlas {
+        data,
+        dimensions,
+        frames,
+        frame_durations,
+        looping,
+    })
+}
+
+/// Reject sub-frames drawn outside of the canvas
+fn check_frame_bounds(control: &FrameControl, width: u32, height: u32) -> Result<(), png::DecodingError> {
+    let right = control.x_offset as u64 + control.width as u64;
+    let bottom = control.y_offset as u64 + control.height as u64;
+    if right > width as u64 || bottom > height as u64 {
+        return Err(png::DecodingError::Format("frame outside of the animated PNG".into()));
+    }
+
+    Ok(())
+}
+
+/// Decode a PNG file to 8 bit RGBA pixels, without touching the GPU (it can run on any thread)
+/// Images larger than `max_dimension` on either side are rejected before decoding them
+pub fn decode_png(path: &str, max_dimension: u32) -> Result<(Vec<u8>, Vector2<u32>), TextureError> {
+    read_png(File::open(path)?, max_dimension)
+}
+
+/// Decode PNG data from any reader, see `decode_png`
+fn read_png(source: impl Read, max_dimension: u32) -> Result<(Vec<u8>, Vector2<u32>), TextureError> {
+    let decoder = png::Decoder::new(source);
+    let (info, mut reader) = decoder.read_info()?;
+
+    let dimensions = Vector2::new(info.width, info.height);
+    if dimensions.x > max_dimension || dimensions.y > max_dimension {
+        return Err(TextureError::TooLarge {
+            dimensions,
+            limit: max_dimension,
+        });
+    }
+
+    // the decoder already expands palettes and reduces 16 bit channels, only the channel count can differ
+    let (color_type, bit_depth) = reader.output_color_type();
+    if bit_depth != BitDepth::Eight {
+        return Err(TextureError::UnsupportedFormat(color_type, bit_depth));
+    }
+
+    let mut buf = vec![0; reader.output_buffer_size()];
+    reader.next_frame(&mut buf)?;
+
+    let data = expand_to_rgba(&buf, color_type).ok_or(TextureError::UnsupportedFormat(color_type, bit_depth))?;
+
+    Ok((data, dimensions))
+}
+
+/// Convert 8 bit pixels to RGBA, after the decoder expanded palettes and transparency chunks
+/// Returns `None` for data still indexed (decoded without the `EXPAND` transformation)
+pub fn expand_to_rgba(data: &[u8], color_type: ColorType) -> Option<Vec<u8>> {
+    let channels = match color_type {
+        ColorType::Grayscale => 1,
+        ColorType::GrayscaleAlpha => 2,
+        ColorType::RGB => 3,
+        ColorType::RGBA => return Some(data.to_vec()),
+        ColorType::Indexed => return None,
+    };
+
+    let mut rgba = Vec::with_capacity(data.len() / channels * 4);
+    for pixel in data.chunks_exact(channels) {
+        match color_type {
+            ColorType::Grayscale => rgba.extend_from_slice(&[pixel[0], pixel[0], pixel[0], 255]),
+            ColorType::GrayscaleAlpha => rgba.extend_from_slice(&[pixel[0], pixel[0], pixel[0], pixel[1]]),
+            _ => rgba.extend_from_slice(&[pixel[0], pixel[1], pixel[2], 255]),
+        }
+    }
+
+    Some(rgba)
+}
+
+/// Draw a sub-frame on the canvas, save the result as a new frame and dispose of the sub-frame
+fn compose_frame(
+    canvas: &mut [u8],
+    canvas_width: u32,
+    subframe: &[u8],
+    control: &FrameControl,
+    frames_data: &mut Vec<Vec<u8>>,
+    frame_durations: &mut Vec<f32>,
+) {
+    let previous = match control.dispose_op {
+        DisposeOp::Previous => Some(canvas.to_vec()),
+        _ => None,
+    };
+
+    for row in 0..control.height {
+        for column in 0..control.width {
+            let src = ((row * control.width + column) * 4) as usize;
+            let dst = (((control.y_offset + row) * canvas_width + control.x_offset + column) * 4)
+                as usize;
+
+            let pixel = &subframe[src..src + 4];
+            match control.blend_op {
+                BlendOp::Source => canvas[dst..dst + 4].copy_from_slice(pixel),
+                BlendOp::Over => blend_over(&mut canvas[dst..dst + 4], pixel),
+            }
+        }
+    }
+
+    frames_data.push(canvas.to_vec());
+
+    // A zero denominator means hundredths of a second, a zero delay is shown as 100ms like most decoders do
+    let denominator = if control.delay_den == 0 {
+        100.0
+    } else {
+        control.delay_den as f32
+    };
+    let duration = control.delay_num as f32 / denominator;
+    frame_durations.push(if duration > 0.0 { duration } else { 0.1 });
+
+    match control.dispose_op {
+        DisposeOp::None => {}
+        DisposeOp::Background => {
+            for row in 0..control.height {
+                let start = (((control.y_offset + row) * canvas_width + control.x_offset) * 4) as usize;
+                let end = start + (control.width * 4) as usize;
+                canvas[start..end].iter_mut().for_each(|c| *c = 0);
+            }
+        }
+        DisposeOp::Previous => canvas.copy_from_slice(&previous.unwrap()),
+    }
+}
+
+/// Alpha blend a non premultiplied RGBA pixel over another
+fn blend_over(dst: &mut [u8], src: &[u8]) {
+    let src_alpha = src[3] as f32 / 255.0;
+    let dst_alpha = dst[3] as f32 / 255.0;
+    let out_alpha = src_alpha + dst_alpha * (1.0 - src_alpha);
+
+    if out_alpha <= 0.0 {
+        dst.iter_mut().for_each(|c| *c = 0);
+        return;
+    }
+
+    for i in 0..3 {
+        let color = (src[i] as f32 * src_alpha + dst[i] as f32 * dst_alpha * (1.0 - src_alpha))
+            / out_alpha;
+        dst[i] = color.round() as u8;
+    }
+    dst[3] = (out_alpha * 255.0).round() as u8;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// PNG file of a single row of pixels, encoded in memory
+    fn encode(
+        color_type: ColorType,
+        bit_depth: BitDepth,
+        palette: Option<(&[u8], &[u8])>,
+        width: u32,
+        data: &[u8],
+    ) -> Vec<u8> {
+        let mut file = Vec::new();
+        {
+            let mut encoder = png::Encoder::new(&mut file, width, 1);
+            encoder.set_color(color_type);
+            encoder.set_depth(bit_depth);
+            if let Some((palette, trns)) = palette {
+                encoder.set_palette(palette.to_vec());
+                if !trns.is_empty() {
+                    encoder.set_trns(trns.to_vec());
+                }
+            }
+
+            let mut writer = encoder.write_header().unwrap();
+            writer.write_image_data(data).unwrap();
+        }
+
+        file
+    }
+
+    fn decode(file: &[u8]) -> Vec<u8> {
+        let (data, dimensions) = read_png(file, 16).unwrap();
+        assert_eq!(dimensions.y, 1);
+
+        data
+    }
+
+    #[test]
+    fn grayscale_is_expanded_to_opaque_gray() {
+        let file = encode(ColorType::Grayscale, BitDepth::Eight, None, 2, &[10, 200]);
+
+        assert_eq!(decode(&file), vec![10, 10, 10, 255, 200, 200, 200, 255]);
+    }
+
+    #[test]
+    fn grayscale_alpha_keeps_its_alpha() {
+        let file = encode(ColorType::GrayscaleAlpha, BitDepth::Eight, None, 2, &[10, 20, 30, 40]);
+
+        assert_eq!(decode(&file), vec![10, 10, 10, 20, 30, 30, 30, 40]);
+    }
+
+    #[test]
+    fn rgb_is_made_opaque() {
+        let file = encode(ColorType::RGB, BitDepth::Eight, None, 2, &[1, 2, 3, 4, 5, 6]);
+
+        assert_eq!(decode(&file), vec![1, 2, 3, 255, 4, 5, 6, 255]);
+    }
+
+    #[test]
+    fn indexed_colors_are_looked_up_in_the_palette() {
+        let palette: &[u8] = &[255, 0, 0, 0, 0, 255];
+
+        let file = encode(ColorType::Indexed, BitDepth::Eight, Some((palette, &[])), 2, &[1, 0]);
+        assert_eq!(decode(&file), vec![0, 0, 255, 255, 255, 0, 0, 255]);
+
+        // the transparency chunk gives the alpha of the first entries
+        let file = encode(ColorType::Indexed, BitDepth::Eight, Some((palette, &[128])), 2, &[0, 1]);
+        assert_eq!(decode(&file), vec![255, 0, 0, 128, 0, 0, 255, 255]);
+    }
+
+    #[test]
+    fn sixteen_bit_channels_are_reduced_to_eight_bits() {
+        // big endian samples with equal bytes, which scale and strip to the same 8 bit value
+        let data = [0x12, 0x12, 0xAB, 0xAB, 0xFF, 0xFF];
+        let file = encode(ColorType::RGB, BitDepth::Sixteen, None, 1, &data);
+
+        assert_eq!(decode(&file), vec![0x12, 0xAB, 0xFF, 255]);
+    }
+
+    #[test]
+    fn images_above_the_size_limit_are_rejected() {
+        let file = encode(ColorType::Grayscale, BitDepth::Eight, None, 32, &[0; 32]);
+
+        assert!(matches!(read_png(&file[..], 16), Err(TextureError::TooLarge { .. })));
+    }
+
+    /// Insert a chunk right after the header of a PNG file
+    fn insert_chunk(file: &[u8], chunk_type: &[u8; 4], data: &[u8]) -> Vec<u8> {
+        // CRC-32 of the chunk type and data, as in the PNG specification
+        let mut crc = !0u32;
+        for byte in chunk_type.iter().chain(data) {
+            crc ^= *byte as u32;
+            for _ in 0..8 {
+                crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+            }
+        }
+
+        // signature (8 bytes) and IHDR chunk (25 bytes)
+        let mut result = file[..33].to_vec();
+        result.extend_from_slice(&(data.len() as u32).to_be_bytes());
+        result.extend_from_slice(chunk_type);
+        result.extend_from_slice(data);
+        result.extend_from_slice(&(!crc).to_be_bytes());
+        result.extend_from_slice(&file[33..]);
+
+        result
+    }
+
+    #[test]
+    fn still_png_is_a_single_frame_animation() {
+        let file = encode(ColorType::RGB, BitDepth::Eight, None, 2, &[1, 2, 3, 4, 5, 6]);
+        let atlas = read_apng(&file[..]).unwrap();
+
+        assert_eq!(atlas.frames, vec![Vector4::new(0, 0, 2, 1)]);
+        assert_eq!(atlas.data, vec![1, 2, 3, 255, 4, 5, 6, 255]);
+        assert!(atlas.looping);
+    }
+
+    #[test]
+    fn animation_without_frames_is_rejected() {
+        // the default image isn't part of the animation without a frame control before it
+        let file = encode(ColorType::RGB, BitDepth::Eight, None, 2, &[1, 2, 3, 4, 5, 6]);
+        let file = insert_chunk(&file, b"acTL", &[0, 0, 0, 0, 0, 0, 0, 0]);
+
+        assert!(matches!(read_apng(&file[..]), Err(TextureError::NoFrames)));
+    }
+
+    #[test]
+    fn invalid_animation_is_an_error() {
+        assert!(matches!(read_apng(&b"not a png"[..]), Err(TextureError::Decode(_))));
+    }
+}
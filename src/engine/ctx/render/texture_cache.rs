@@ -0,0 +1,142 @@
+//! Textures loaded from files, kept around to be reused by new objects
+
+// standard imports
+use std::collections::HashMap;
+use std::sync::Arc;
+
+// vulkan implementation imports
+use super::vulkan::Texture;
+
+// other imports
+use cgmath::Vector2;
+
+struct CachedTexture {
+    texture: Texture,
+    dimensions: Vector2<u32>,
+    /// Estimated GPU memory (in bytes)
+    size: usize,
+    /// Value of the cache's clock on the last access, the lowest is the least recently used
+    last_used: u64,
+}
+
+/// Cache of textures by path, evicting the least recently used ones past a memory budget
+/// Evicted textures stay alive as long as an object still uses them, they just aren't shared anymore
+#[derive(Default)]
+pub struct TextureCache {
+    textures: HashMap<String, CachedTexture>,
+    clock: u64,
+    memory_usage: usize,
+    /// Maximum memory (in bytes) of the cached textures, `None` for no limit
+    budget: Option<usize>,
+}
+
+impl TextureCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&mut self, path: &str) -> Option<(Texture, Vector2<u32>)> {
+        self.clock += 1;
+        let clock = self.clock;
+
+        self.textures.get_mut(path).map(|cached| {
+            cached.last_used = clock;
+            (cached.texture.clone(), cached.dimensions)
+        })
+    }
+
+    pub fn insert(&mut self, path: &str, texture: Texture, dimensions: Vector2<u32>, mip_levels: u32) {
+        self.clock += 1;
+        let size = texture_memory_size(dimensions, mip_levels);
+
+        let old = self.textures.insert(
+            path.to_string(),
+            CachedTexture {
+                texture,
+                dimensions,
+                size,
+                last_used: self.clock,
+            },
+        );
+        if let Some(old) = old {
+            self.memory_usage -= old.size;
+        }
+        self.memory_usage += size;
+
+        self.evict(path);
+    }
+
+    /// Whether `texture` is the one cached for `path`
+    pub fn holds(&self, path: &str, texture: &Texture) -> bool {
+        self.textures.get(path).map_or(false, |cached| {
+            Arc::as_ptr(&cached.texture) as *const () == Arc::as_ptr(texture) as *const ()
+        })
+    }
+
+    pub fn remove(&mut self, path: &str) {
+        if let Some(cached) = self.textures.remove(path) {
+            self.memory_usage -= cached.size;
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.textures.clear();
+        self.memory_usage = 0;
+    }
+
+    /// Estimated GPU memory (in bytes) of all the cached textures
+    pub fn memory_usage(&self) -> usize {
+        self.memory_usage
+    }
+
+    /// Estimated GPU memory (in bytes) of a cached texture
+    pub fn texture_memory(&self, path: &str) -> Option<usize> {
+        self.textures.get(path).map(|cached| cached.size)
+    }
+
+    pub fn get_budget(&self) -> Option<usize> {
+        self.budget
+    }
+
+    pub fn set_budget(&mut self, budget: Option<usize>) {
+        self.budget = budget;
+        self.evict("");
+    }
+
+    /// Drop the least recently used textures until the cache fits the budget, keeping `keep`
+    fn evict(&mut self, keep: &str) {
+        let budget = match self.budget {
+            Some(budget) => budget,
+            None => return,
+        };
+
+        while self.memory_usage > budget {
+            let oldest = self
+                .textures
+                .iter()
+                .filter(|(path, _)| path.as_str() != keep)
+                .min_by_key(|(_, cached)| cached.last_used)
+                .map(|(path, _)| path.clone());
+
+            match oldest {
+                Some(path) => {
+                    let cached = self.textures.remove(&path).unwrap();
+                    self.memory_usage -= cached.size;
+                }
+                // only the kept texture is left, even if it's over budget alone
+                None => break,
+            }
+        }
+    }
+}
+
+/// Size (in bytes) of an RGBA8 texture with all its mipmap levels
+pub fn texture_memory_size(dimensions: Vector2<u32>, mip_levels: u32) -> usize {
+    (0..mip_levels.max(1))
+        .map(|level| {
+            let width = (dimensions.x >> level).max(1) as usize;
+            let height = (dimensions.y >> level).max(1) as usize;
+            width * height * 4
+        })
+        .sum()
+}
@@ -0,0 +1,193 @@
+//! Background decoding of texture files, leaving only the upload to the thread owning the GPU
+
+// standard imports
+use std::collections::HashSet;
+use std::io;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+
+// imports from the module
+use super::texture;
+use super::vulkan::TextureError;
+
+// other imports
+use cgmath::Vector2;
+
+/// Most threads decoding a single batch
+const MAX_WORKERS: usize = 4;
+
+/// Pixels of a file decoded on a worker thread, or the reason it couldn't be
+pub type DecodedTexture = (String, Result<(Vec<u8>, Vector2<u32>), TextureError>);
+
+/// Decodes PNG files on worker threads, handing back the pixels through a channel
+pub struct TextureLoader {
+    /// Cloned by every worker, dropped while waiting so the channel closes once they're all gone
+    sender: Option<Sender<DecodedTexture>>,
+    receiver: Receiver<DecodedTexture>,
+    /// Paths queued and not received yet
+    pending: HashSet<String>,
+    /// Files queued since the loader was last idle, and how many of them were received
+    queued: usize,
+    finished: usize,
+}
+
+impl Default for TextureLoader {
+    fn default() -> Self {
+        let (sender, receiver) = mpsc::channel();
+
+        Self {
+            sender: Some(sender),
+            receiver,
+            pending: HashSet::new(),
+            queued: 0,
+            finished: 0,
+        }
+    }
+}
+
+impl TextureLoader {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Start decoding a batch of files, ignoring the ones already pending
+    pub fn queue(&mut self, paths: Vec<String>) {
+        let paths: Vec<String> = paths
+            .into_iter()
+            .filter(|path| self.pending.insert(path.clone()))
+            .collect();
+        if paths.is_empty() {
+            return;
+        }
+
+        // a new batch after the last one was done starts a new progress count
+        if self.pending.len() == paths.len() {
+            self.queued = 0;
+            self.finished = 0;
+        }
+        self.queued += paths.len();
+
+        let workers = thread::available_parallelism()
+            .map_or(1, |n| n.get())
+            .min(MAX_WORKERS)
+            .min(paths.len());
+        let chunk_size = (paths.len() + workers - 1) / workers;
+
+        // the channel was closed by `wait`, whose workers are done with it
+        let sender = match &self.sender {
+            Some(sender) => sender.clone(),
+            None => {
+                let (sender, receiver) = mpsc::channel();
+                self.receiver = receiver;
+                self.sender = Some(sender.clone());
+                sender
+            }
+        };
+
+        for chunk in paths.chunks(chunk_size) {
+            let chunk = chunk.to_vec();
+            let sender = sender.clone();
+
+            thread::Builder::new()
+                .name(String::from("texture loader"))
+                .spawn(move || {
+                    for path in chunk {
+                        // the size is checked against the device limits when uploading
+                        let decoded = texture::decode_png(&path, u32::MAX);
+                        if sender.send((path, decoded)).is_err() {
+                            return;
+                        }
+                    }
+                })
+                .expect("Couldn't spawn texture loader thread");
+        }
+    }
+
+    /// Files decoded since the last call, without blocking
+    pub fn receive(&mut self) -> Vec<DecodedTexture> {
+        let decoded: Vec<DecodedTexture> = self.receiver.try_iter().collect();
+
+        for (path, _) in decoded.iter() {
+            self.pending.remove(path);
+        }
+        self.finished += decoded.len();
+
+        decoded
+    }
+
+    /// Every file still pending, blocking until the workers decoded them all
+    /// Files left behind by a worker that panicked come back with an error
+    pub fn wait(&mut self) -> Vec<DecodedTexture> {
+        let mut decoded = Vec::new();
+
+        // only the workers hold a sender now, so receiving fails once they all stopped
+        self.sender = None;
+
+        while !self.pending.is_empty() {
+            let texture = match self.receiver.recv() {
+                Ok(texture) => texture,
+                Err(_) => break,
+            };
+
+            self.pending.remove(&texture.0);
+            decoded.push(texture);
+        }
+
+        for path in self.pending.drain() {
+            let e = io::Error::new(io::ErrorKind::Other, "the decoding thread stopped before reading the file");
+            decoded.push((path, Err(TextureError::Io(e))));
+        }
+        self.finished = self.queued;
+
+        decoded
+    }
+
+    pub fn is_idle(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    /// Fraction (from 0 to 1) of the files received since the loader was last idle
+    pub fn progress(&self) -> f32 {
+        if self.queued == 0 {
+            return 1.0;
+        }
+
+        self.finished as f32 / self.queued as f32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wait_returns_every_queued_texture() {
+        let paths = vec![String::from("assets/rust.png"), String::from("assets/python.png")];
+
+        let mut loader = TextureLoader::new();
+        loader.queue(paths.clone());
+        let decoded = loader.wait();
+
+        assert_eq!(decoded.len(), paths.len());
+        for path in paths.iter() {
+            let (_, result) = decoded.iter().find(|(p, _)| p == path).unwrap();
+            assert!(result.is_ok(), "Couldn't decode \'{}\'", path);
+        }
+        assert!(loader.is_idle());
+        assert_eq!(loader.progress(), 1.0);
+    }
+
+    #[test]
+    fn loader_can_queue_again_after_waiting() {
+        let mut loader = TextureLoader::new();
+        loader.queue(vec![String::from("assets/rust.png")]);
+        loader.wait();
+
+        loader.queue(vec![String::from("assets/python.png"), String::from("missing.png")]);
+        let decoded = loader.wait();
+
+        assert_eq!(decoded.len(), 2);
+        assert!(decoded.iter().any(|(path, result)| path == "missing.png" && result.is_err()));
+        assert!(loader.is_idle());
+    }
+}
@@ -1,57 +1,92 @@
 // standard imports
 use std::cell::RefCell;
 use std::cmp::{max, min};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::convert::TryInto;
-use std::fs::File;
-use std::ops::DerefMut;
-use std::rc::Rc;
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::rc::{Rc, Weak};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 // Vulkano imports
-use vulkano::buffer::{BufferUsage, CpuAccessibleBuffer, ImmutableBuffer, TypedBufferAccess};
+use vulkano::buffer::{
+    BufferUsage, CpuAccessibleBuffer, CpuBufferPool, ImmutableBuffer, TypedBufferAccess,
+};
 use vulkano::command_buffer::{
-    AutoCommandBufferBuilder, CommandBufferUsage, DynamicState, SubpassContents,
+    AutoCommandBufferBuilder, CommandBufferUsage, DynamicState, PrimaryAutoCommandBuffer,
+    SubpassContents,
 };
 use vulkano::Handle;
 
 use vulkano::descriptor::descriptor_set::{
-    PersistentDescriptorSet, PersistentDescriptorSetBuilder, PersistentDescriptorSetImg,
-    PersistentDescriptorSetSampler,
+    PersistentDescriptorSet, PersistentDescriptorSetBuf, PersistentDescriptorSetBuilder,
+    PersistentDescriptorSetImg, PersistentDescriptorSetSampler,
+};
+use vulkano::device::{Device, DeviceCreationError, DeviceExtensions, DeviceOwned, Queue};
+use vulkano::format::{ClearValue, Format};
+use vulkano::image::view::{ImageView, ImageViewAbstract, ImageViewCreationError};
+use vulkano::image::{
+    AttachmentImage, ImageCreationError, ImageDimensions, ImageUsage, ImmutableImage, MipmapsCount,
+    SwapchainImage,
 };
-use vulkano::device::{Device, DeviceExtensions, Queue};
-use vulkano::format::Format;
-use vulkano::image::view::ImageView;
-use vulkano::image::{ImageDimensions, ImageUsage, ImmutableImage, MipmapsCount, SwapchainImage};
 use vulkano::instance::{Instance, InstanceExtensions, PhysicalDevice, PhysicalDeviceType};
 use vulkano::memory::DeviceMemoryAllocError;
+use vulkano::pipeline::blend::AttachmentBlend;
+use vulkano::pipeline::depth_stencil::{
+    Compare, DepthStencil, DynamicStencilValue, Stencil, StencilFaceFlags, StencilOp,
+};
 use vulkano::pipeline::vertex::SingleBufferDefinition;
 use vulkano::pipeline::viewport::Viewport;
+use vulkano::pipeline::cache::PipelineCache;
 use vulkano::pipeline::{GraphicsPipeline, GraphicsPipelineAbstract};
-use vulkano::render_pass::RenderPass;
+use vulkano::render_pass::{RenderPass, RenderPassCreationError};
 use vulkano::render_pass::{Framebuffer, FramebufferAbstract, Subpass};
-use vulkano::sampler::{Filter, MipmapMode, Sampler, SamplerAddressMode};
+use vulkano::sampler::{BorderColor, Filter, MipmapMode, Sampler, SamplerAddressMode};
 use vulkano::swapchain;
-use vulkano::swapchain::{AcquireError, Surface, Swapchain, SwapchainCreationError};
+use vulkano::swapchain::{
+    AcquireError, CapabilitiesError, ColorSpace, PresentMode, SupportedPresentModes, Surface,
+    Swapchain, SwapchainCreationError,
+};
 use vulkano::sync;
 use vulkano::sync::{FlushError, GpuFuture};
 use vulkano::Version;
 use vulkano::VulkanObject;
 
 // SDL2 imports
+use sdl2::rect::Rect;
 use sdl2::video::{Window, WindowContext};
 
 // other imports
-use super::draw_objects::{Draw, DrawFlags, DrawObject, Sprite, SpriteObject, Primitive, PrimitiveObject};
+use super::draw_objects::{
+    AnimatedSprite, AnimatedSpriteObject, Draw, DrawFlags, DrawObject, Primitive,
+    GraphicObject, PrimitiveObject, Sprite, SpriteDataMode, SpriteObject, SpriteState, Text,
+    TextObject,
+};
+use super::text::BitmapFont;
+use super::background::{BackgroundFill, BackgroundPipelines};
+use super::batch::{self, BatchDescriptorSet, BatchPipeline, SpriteBatch, SpriteInstance};
+use super::debug_draw::{self, DebugPipeline, DebugShapes, DebugVertex};
 use super::sendable::Sendable;
+use super::texture;
+use super::texture_cache::TextureCache;
+use crate::engine::spatial::Aabb;
 use cgmath::{Vector2, Vector4};
 use png;
+use rand::Rng;
+
+pub use super::background::Background;
 
 /// Use of a macro due to literals needed.
-/// This creates a new pipeline object (using the specified shaders) and appends it to the HashMap.
+/// This creates a new pipeline object (using the specified shaders and pipeline cache) and appends it to the HashMap.
+/// Errors are returned with `?`, so it must be used in a function returning a `GraphicsInitError`.
+/// `$encode_srgb` makes the fragment shader gamma-encode its output, for swapchains without an sRGB format.
+/// `$alpha_test` makes it discard the pixels less than half opaque, like the mask variants always do.
 #[macro_use]
 macro_rules! create_pipeline {
-    ($name: expr, $device: expr, $render_pass: expr, $vs_path: expr, $fs_path: expr, $map: expr) => {{
+    ($name: expr, $device: expr, $render_pass: expr, $cache: expr, $encode_srgb: expr, $alpha_test: expr, $vs_path: expr, $fs_path: expr, $map: expr, $mask_map: expr) => {{
         mod vertex_shader {
             vulkano_shaders::shader! {
                ty: "vertex",
@@ -66,38 +101,56 @@ macro_rules! create_pipeline {
             }
         }
 
-        let vert_shader = vertex_shader::Shader::load($device.clone()).expect(&format!(
-            "Couldn't load Vertex Shader: pipeline name: {},\nshader path: {}",
-            $name, $vs_path
-        ));
-        let frag_shader = fragment_shader::Shader::load($device.clone()).expect(&format!(
-            "Couldn't load Fragment Shader: pipeline name: {},\nshader path: {}",
-            $name, $fs_path
-        ));
+        let vert_shader = vertex_shader::Shader::load($device.clone()).map_err(|e| {
+            GraphicsInitError::Pipeline(format!("{} Vertex Shader ({}): {}", $name, $vs_path, e))
+        })?;
+        let frag_shader = fragment_shader::Shader::load($device.clone()).map_err(|e| {
+            GraphicsInitError::Pipeline(format!("{} Fragment Shader ({}): {}", $name, $fs_path, e))
+        })?;
+
+        // the same shaders draw the object, or write its shape to the stencil when it's a mask
+        let build = |mask: bool| {
+            let blend = if mask {
+                mask_blend()
+            } else {
+                AttachmentBlend::alpha_blending()
+            };
 
-        let pipeline = Arc::new(
             GraphicsPipeline::start()
                 .vertex_input_single_buffer::<Vertex>()
                 .vertex_shader(vert_shader.main_entry_point(), ())
                 .triangle_strip()
                 .viewports_dynamic_scissors_irrelevant(1)
-                .blend_alpha_blending()
-                .fragment_shader(frag_shader.main_entry_point(), ())
+                .blend_collective(blend)
+                .depth_stencil(stencil_state(mask))
+                .fragment_shader(
+                    frag_shader.main_entry_point(),
+                    fragment_shader::SpecializationConstants {
+                        encode_srgb: $encode_srgb as u32,
+                        alpha_test: (mask || $alpha_test) as u32,
+                    },
+                )
                 .render_pass(Subpass::from($render_pass.clone(), 0).unwrap())
+                .build_with_cache($cache.clone())
                 .build($device.clone())
-                .expect("Couldn't create new Vulkan Graphics Pipeline"),
-        );
-        $map.insert($name.to_string(), pipeline.clone());
+                .map(Arc::new)
+                .map_err(|e| GraphicsInitError::Pipeline(format!("{}: {}", $name, e)))
+        };
+        $map.insert($name.to_string(), build(false)?);
+        $mask_map.insert($name.to_string(), build(true)?);
     };};
 }
 
-pub type Texture = Arc<ImageView<Arc<ImmutableImage>>>;
-pub type DescriptorSetImg = PersistentDescriptorSetImg<Arc<ImageView<Arc<ImmutableImage>>>>;
+/// Any image view that can be sampled, from a file or from a `RenderTarget`
+pub type Texture = Arc<dyn ImageViewAbstract + Send + Sync>;
+pub type DescriptorSetImg = PersistentDescriptorSetImg<Texture>;
 pub type DescriptorSetWithImage<R> =
     PersistentDescriptorSetBuilder<((R, DescriptorSetImg), PersistentDescriptorSetSampler)>;
 pub type GlobalUniformBuffer = CpuAccessibleBuffer<GlobalUniformData>;
+type DebugDescriptorSet = PersistentDescriptorSet<((), PersistentDescriptorSetBuf<Arc<GlobalUniformBuffer>>)>;
 
 /// Struct to hold the global data needed for graphics
+/// The `z` component of `camera_position` holds the rotation of the camera
 #[derive(Clone, Copy)]
 pub struct GlobalUniformData {
     window_size: Vector4<u32>,
@@ -105,36 +158,500 @@ pub struct GlobalUniformData {
     camera_scale: Vector4<f32>,
 }
 
+impl GlobalUniformData {
+    /// `window_size` is the size of the scene, before `rotation` is applied to it
+    fn new(window_size: Vector2<u32>, projection: ProjectionMode, rotation: Rotation, camera: &Camera) -> Self {
+        Self {
+            window_size: window_size.extend(projection as u32).extend(rotation as u32),
+            camera_position: camera.position.extend(camera.rotation).extend(0.0),
+            camera_scale: camera.scale.extend(0.0).extend(0.0),
+        }
+    }
+}
+
+/// Offscreen image that objects can be rendered to, and then drawn as a texture
+pub struct RenderTarget {
+    view: Arc<ImageView<Arc<AttachmentImage>>>,
+    framebuffer: Arc<dyn FramebufferAbstract + Send + Sync>,
+    dimensions: Vector2<u32>,
+}
+
+impl RenderTarget {
+    pub fn get_dimensions(&self) -> Vector2<u32> {
+        self.dimensions
+    }
+
+    /// Texture with the last rendered content, e.g. for `new_sprite_from_texture`
+    pub fn texture(&self) -> Texture {
+        self.view.clone()
+    }
+}
+
+/// Position, zoom and rotation (in radians) of a view
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Camera {
+    pub position: Vector2<f32>,
+    pub scale: Vector2<f32>,
+    pub rotation: f32,
+}
+
+impl Default for Camera {
+    fn default() -> Self {
+        Self {
+            position: Vector2::new(0.0, 0.0),
+            scale: Vector2::new(1.0, 1.0),
+            rotation: 0.0,
+        }
+    }
+}
+
 /// Struct to handle connections to the Vulkano (and thus Vulkan) API
 pub struct GraphicsHandler {
     instance: Arc<Instance>,
     swapchain: SwapchainHandler,
     render_pass: Arc<RenderPass>,
     pipelines: HashMap<String, Arc<GraphicsPipeline<SingleBufferDefinition<Vertex>>>>,
+    /// Variants of the pipelines writing the objects used as masks to the stencil
+    mask_pipelines: HashMap<String, Arc<GraphicsPipeline<SingleBufferDefinition<Vertex>>>>,
     previous_frame_end: Option<Box<dyn GpuFuture>>,
     device: Arc<Device>,
     queue: Arc<Queue>,
     draw_objects: Vec<DrawObject<dyn Draw>>,
+    /// Objects grouped by tag, without keeping them alive
+    tags: HashMap<String, Vec<Weak<RefCell<dyn Draw>>>>,
 
     global_uniform_buffer: Arc<GlobalUniformBuffer>,
+    global_dirty: bool,
     pub window_size: Vector2<u32>,
-    pub camera_position: Vector2<f32>,
+    /// Part of the window the world is drawn in, the whole window if `None`
+    viewport_region: Option<Rect>,
+    /// Rotation of the whole output on the window
+    rotation: Rotation,
+    camera_position: Vector2<f32>,
     /// Zoom and stretch the whole view (If any of the dimensions is negative, it'll revert the view on that dimension)
-    pub camera_scale: Vector2<f32>,
+    camera_scale: Vector2<f32>,
+    /// Rotation (in radians) of the view around the camera position
+    camera_rotation: f32,
+    /// Uniform buffer of the objects created with `screen_space`, always in window pixels
+    screen_uniform_buffer: Arc<GlobalUniformBuffer>,
+    /// Fixed cameras of the objects created in a `camera_scope`, with their own uniform buffers
+    scoped_cameras: Vec<(Camera, Arc<GlobalUniformBuffer>)>,
+    /// Uniform buffer bound by new objects, `None` for the main camera
+    active_uniform_buffer: Option<Arc<GlobalUniformBuffer>>,
+    /// Living masks, in the order they were pushed
+    masks: Vec<Mask>,
+    /// Stencil bits of the masks applied to the objects created from now on
+    mask_stack: Vec<u32>,
+    /// Stencil bits of the masks applied to each object, by address
+    object_masks: HashMap<*const (), (Weak<RefCell<dyn Draw>>, u32)>,
+    /// Whether objects are being drawn as masks, to pick the variant of their pipeline
+    drawing_masks: bool,
+    /// Tagged objects to render to a target during the next frame, with the camera to use
+    layer_requests: Vec<(String, Camera, Arc<RenderTarget>)>,
+    /// Names of the missing pipelines objects tried to draw with, to only warn once about each
+    missing_pipelines: HashSet<String>,
+
+    // region of the next frame to copy back to the host and last completed copy
+    capture_request: Option<(Vector2<u32>, Vector2<u32>)>,
+    last_capture: Option<FrameCapture>,
+
+    frame_timeouts: FrameTimeouts,
+    slow_frames: u32,
+
+    // counters since startup, at the start of the current second and over the last complete second
+    present_stats: PresentStats,
+    second_start: (Instant, PresentStats),
+    last_second_stats: PresentStats,
+
+    config: GraphicsConfig,
+    limits: DeviceLimits,
+    /// Time spent creating the pipelines at startup
+    pipeline_warmup: Duration,
+    /// Sampling options of the textures of new objects
+    sampler_config: SamplerConfig,
+    /// Samplers already created, shared by all the objects with the same config
+    samplers: RefCell<Vec<(SamplerConfig, Arc<Sampler>)>>,
+    /// Textures loaded from files, shared by the objects using the same path
+    texture_cache: RefCell<TextureCache>,
+
+    // shakes currently applied and resulting offset of the view (not part of the camera position)
+    camera_shakes: Vec<CameraShake>,
+    shake_offset: Vector2<f32>,
+    last_shake_update: Instant,
+
+    redraw_mode: RedrawMode,
+    /// A change still has to reach the screen (kept until a frame is actually presented)
+    redraw_pending: bool,
+    /// Objects were added or changed z index since `draw_objects` was last sorted
+    needs_sort: bool,
+
+    /// Debug lines of the next frame, drawn over every object with the main camera
+    debug_shapes: DebugShapes,
+    debug_pipeline: Arc<DebugPipeline>,
+    debug_descriptor_set: Arc<DebugDescriptorSet>,
+
+    /// Fill drawn before the objects, `None` to only clear the window
+    background: Option<BackgroundFill>,
+    background_pipelines: BackgroundPipelines,
+
+    /// Draw consecutive sprites with the same texture, sampler and camera in a single draw call
+    batching: bool,
+    batch_pipeline: Arc<BatchPipeline>,
+    /// Quad shared by the instances of every batch
+    batch_quad: Arc<ImmutableBuffer<[Vertex]>>,
+    instance_pool: CpuBufferPool<SpriteInstance>,
+    /// Descriptor sets of the batches, by addresses of texture, sampler and uniform buffer
+    batch_sets: RefCell<HashMap<(usize, usize, usize), Weak<BatchDescriptorSet>>>,
+    draw_stats: DrawStats,
+}
+
+/// Work done by the main pass of the last rendered frame
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct DrawStats {
+    /// Objects drawn, as many as the draw calls there would be without batching
+    pub objects: u32,
+    pub draw_calls: u32,
+    /// Time from the start of `vulkan_loop` to the GPU completing the frame, e.g. to compare with and without batching
+    pub render_time: Duration,
+}
+
+/// What happened to the frames asked to `vulkan_loop`, to tell hitches apart from frames that weren't needed
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct PresentStats {
+    /// Frames rendered and presented
+    pub presented: u64,
+    /// Frames skipped because nothing changed (on-demand redraw)
+    pub unchanged: u64,
+    /// Frames dropped because the swapchain couldn't be drawn to (minimized window, resize, out of date or
+    /// suboptimal images)
+    pub swapchain_skipped: u64,
+    /// Frames lost because the GPU failed or timed out
+    pub failed: u64,
+}
+
+impl PresentStats {
+    /// Frames counted since `earlier` was taken
+    fn since(&self, earlier: &PresentStats) -> PresentStats {
+        PresentStats {
+            presented: self.presented - earlier.presented,
+            unchanged: self.unchanged - earlier.unchanged,
+            swapchain_skipped: self.swapchain_skipped - earlier.swapchain_skipped,
+            failed: self.failed - earlier.failed,
+        }
+    }
+}
+
+/// Masks alive at the same time, one for each bit of the stencil
+const MAX_MASKS: u32 = 8;
+
+/// Object whose shape is written to the stencil, limiting the objects created under it to that shape
+struct Mask {
+    object: Weak<RefCell<dyn Draw>>,
+    /// Stencil bit of the mask
+    bit: u32,
+    /// Bits of the masks this one is nested in, it's only written inside of them
+    parent_bits: u32,
+}
+
+/// Frequency (in Hz) of the noise used to move the camera during a shake
+const SHAKE_FREQUENCY: f32 = 25.0;
+
+/// Sides of the polygon drawn by `new_circle`
+pub const DEFAULT_CIRCLE_SEGMENTS: u32 = 32;
+
+/// Camera shake decaying over its duration
+struct CameraShake {
+    intensity: f32,
+    duration: f32,
+    elapsed: f32,
+    seeds: (u32, u32),
+}
+
+impl CameraShake {
+    /// Offset of the shake at its current time
+    fn offset(&self) -> Vector2<f32> {
+        let strength = self.intensity * (1.0 - self.elapsed / self.duration).max(0.0);
+        let t = self.elapsed * SHAKE_FREQUENCY;
+
+        Vector2::new(value_noise(self.seeds.0, t), value_noise(self.seeds.1, t)) * strength
+    }
+}
+
+/// When the GraphicsHandler renders a new frame
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum RedrawMode {
+    /// Every frame, the default for games that change all the time
+    Continuous,
+    /// Only after something changed (objects, camera, window size) or a `request_redraw`,
+    /// saving power in mostly static applications
+    OnDemand,
+}
+
+/// Mapping of world coordinates on the window
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ProjectionMode {
+    /// Origin at the center of the window, with a world unit being half a pixel
+    Centered = 0,
+    /// Origin at the top left corner of the window, with a world unit being a pixel (like most 2D frameworks)
+    PixelTopLeft = 1,
+}
+
+/// Clockwise rotation of the whole output, for displays mounted sideways or upside down
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Rotation {
+    Deg0 = 0,
+    Deg90 = 1,
+    Deg180 = 2,
+    Deg270 = 3,
+}
+
+impl Rotation {
+    /// Size of the scene drawn in an area of the window
+    fn scene_size<T>(self, size: Vector2<T>) -> Vector2<T> {
+        match self {
+            Rotation::Deg90 | Rotation::Deg270 => Vector2::new(size.y, size.x),
+            Rotation::Deg0 | Rotation::Deg180 => size,
+        }
+    }
+
+    /// Point of the scene shown at a point of an area of the window, both in pixels from their top left corner
+    fn scene_point(self, point: Vector2<f32>, size: Vector2<f32>) -> Vector2<f32> {
+        match self {
+            Rotation::Deg0 => point,
+            Rotation::Deg90 => Vector2::new(point.y, size.x - point.x),
+            Rotation::Deg180 => Vector2::new(size.x - point.x, size.y - point.y),
+            Rotation::Deg270 => Vector2::new(size.y - point.y, point.x),
+        }
+    }
+}
+
+/// Number of images in the swapchain
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum BufferCount {
+    /// Lowest latency
+    DoubleBuffered = 2,
+    /// Less stutter on some drivers, at the cost of a frame of latency
+    TripleBuffered = 3,
+}
+
+/// Kind of GPU picked when more than one can draw to the window
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DevicePreference {
+    /// Discrete GPUs first
+    HighPerformance,
+    /// Integrated GPUs first, to save battery on laptops
+    LowPower,
+}
+
+/// What to do with an image acquired from a swapchain that no longer matches the surface (e.g. during a resize)
+/// The swapchain is recreated for the next frame either way
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SuboptimalPolicy {
+    /// Draw into it anyway: no frame is lost, but this one may show up stretched
+    RenderAnyway,
+    /// Skip the frame: no stretched frame reaches the screen, at the cost of a dropped one
+    Skip,
+}
+
+/// Options used to build the renderer
+#[derive(Clone, Debug)]
+pub struct GraphicsConfig {
+    /// Requested swapchain image count, clamped to what the surface supports
+    pub buffering: BufferCount,
+    /// File the pipeline cache is loaded from and saved to, `None` (the default) to always compile from scratch
+    /// See `user_pipeline_cache_path` for a per-user location
+    pub pipeline_cache_path: Option<PathBuf>,
+    pub projection: ProjectionMode,
+    /// Wait for the vertical blank to present, disabling it uses mailbox (or immediate) presentation
+    pub vsync: bool,
+    /// Present mode to use instead of the one picked from `vsync`, replaced by the closest supported one if needed
+    pub present_mode: Option<PresentMode>,
+    pub device_preference: DevicePreference,
+    pub suboptimal_policy: SuboptimalPolicy,
+}
+
+impl GraphicsConfig {
+    /// Pipeline cache file in the per-user data folder of the application (created if needed),
+    /// e.g. `~/.local/share/<org>/<app>/` on Linux or `%APPDATA%\<org>\<app>\` on Windows
+    /// `None` if the platform has no such folder
+    pub fn user_pipeline_cache_path(org_name: &str, app_name: &str) -> Option<PathBuf> {
+        match sdl2::filesystem::pref_path(org_name, app_name) {
+            Ok(dir) => Some(PathBuf::from(dir).join("pipeline_cache.bin")),
+            Err(e) => {
+                eprintln!("Couldn't get the user folder for the pipeline cache: {}", e);
+                None
+            }
+        }
+    }
+}
+
+impl Default for GraphicsConfig {
+    fn default() -> Self {
+        Self {
+            buffering: BufferCount::DoubleBuffered,
+            pipeline_cache_path: None,
+            projection: ProjectionMode::Centered,
+            vsync: true,
+            present_mode: None,
+            device_preference: DevicePreference::HighPerformance,
+            suboptimal_policy: SuboptimalPolicy::RenderAnyway,
+        }
+    }
+}
+
+/// What texture sampling returns outside of the texture
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum AddressMode {
+    Repeat,
+    MirroredRepeat,
+    ClampToEdge,
+    /// Use the border color of the sampler
+    ClampToBorder,
+}
+
+/// Border colors allowed by Vulkan for `AddressMode::ClampToBorder`
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TextureBorderColor {
+    TransparentBlack,
+    OpaqueBlack,
+    OpaqueWhite,
+}
+
+/// How texture sampling blends neighbouring texels
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TextureFilter {
+    /// Sharp texels, for pixel art
+    Nearest,
+    /// Smooth interpolation, for scaled or rotated images
+    Linear,
+}
+
+/// Sampling options of a texture
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct SamplerConfig {
+    pub filter: TextureFilter,
+    pub address_mode: AddressMode,
+    /// Only used with `AddressMode::ClampToBorder`
+    pub border_color: TextureBorderColor,
+    /// Offset added to the mipmap level picked by the GPU: negative values sharpen, positive ones soften
+    pub lod_bias: f32,
+    /// Lowest and highest mipmap level that can be sampled (the minimum must not exceed the maximum)
+    pub lod_range: (f32, f32),
+}
+
+/// Highest level of detail value meaning "no limit" (`VK_LOD_CLAMP_NONE`)
+pub const LOD_CLAMP_NONE: f32 = 1000.0;
+
+impl Default for SamplerConfig {
+    fn default() -> Self {
+        Self {
+            filter: TextureFilter::Linear,
+            address_mode: AddressMode::Repeat,
+            border_color: TextureBorderColor::TransparentBlack,
+            lod_bias: 0.0,
+            lod_range: (0.0, LOD_CLAMP_NONE),
+        }
+    }
+}
+
+impl SamplerConfig {
+    fn vulkan_filter(&self) -> Filter {
+        match self.filter {
+            TextureFilter::Nearest => Filter::Nearest,
+            TextureFilter::Linear => Filter::Linear,
+        }
+    }
+
+    fn vulkan_address_mode(&self) -> SamplerAddressMode {
+        match self.address_mode {
+            AddressMode::Repeat => SamplerAddressMode::Repeat,
+            AddressMode::MirroredRepeat => SamplerAddressMode::MirroredRepeat,
+            AddressMode::ClampToEdge => SamplerAddressMode::ClampToEdge,
+            // textures are sampled as floats, so the float variants are the valid ones
+            AddressMode::ClampToBorder => SamplerAddressMode::ClampToBorder(match self.border_color {
+                TextureBorderColor::TransparentBlack => BorderColor::FloatTransparentBlack,
+                TextureBorderColor::OpaqueBlack => BorderColor::FloatOpaqueBlack,
+                TextureBorderColor::OpaqueWhite => BorderColor::FloatOpaqueWhite,
+            }),
+        }
+    }
+}
+
+/// Limits of the physical device, to validate resources before creating them
+#[derive(Copy, Clone, Debug)]
+pub struct DeviceLimits {
+    /// Maximum width and height of a 2D image (and thus of a texture)
+    pub max_image_dimension_2d: u32,
+    /// Maximum size in bytes of a uniform buffer binding
+    pub max_uniform_buffer_range: u32,
+    /// Maximum size in bytes of the push constants of a pipeline
+    pub max_push_constants_size: u32,
+    pub max_sampler_anisotropy: f32,
+    /// Maximum number of device memory allocations alive at once
+    pub max_memory_allocation_count: u32,
+}
+
+impl DeviceLimits {
+    fn from_physical(physical: PhysicalDevice) -> Self {
+        let properties = physical.properties();
+
+        // these are core Vulkan 1.0 limits, always reported by the driver
+        Self {
+            max_image_dimension_2d: properties.max_image_dimension2_d.unwrap(),
+            max_uniform_buffer_range: properties.max_uniform_buffer_range.unwrap(),
+            max_push_constants_size: properties.max_push_constants_size.unwrap(),
+            max_sampler_anisotropy: properties.max_sampler_anisotropy.unwrap(),
+            max_memory_allocation_count: properties.max_memory_allocation_count.unwrap(),
+        }
+    }
+}
+
+/// Time limits for a frame to be completed by the GPU
+#[derive(Copy, Clone, Debug)]
+pub struct FrameTimeouts {
+    /// Frames taking longer than this are considered slow
+    pub soft: Duration,
+    /// Number of consecutive slow frames after which a performance warning is logged
+    pub soft_repeat: u32,
+    /// Frames not completed by then are given up on, and the renderer recovers
+    pub hard: Duration,
+}
+
+impl Default for FrameTimeouts {
+    fn default() -> Self {
+        Self {
+            soft: Duration::from_millis(100),
+            soft_repeat: 5,
+            hard: Duration::from_secs(10),
+        }
+    }
+}
+
+/// Region of a rendered frame copied back to a host visible buffer
+struct FrameCapture {
+    offset: Vector2<u32>,
+    size: Vector2<u32>,
+    format: Format,
+    buffer: Arc<CpuAccessibleBuffer<[u8]>>,
 }
 
 impl GraphicsHandler {
     /// Vulkan object handler instancing and init
-    pub fn new(window: &Window) -> Self {
-        let instance = create_instance();
+    /// Fails on machines that can't run the renderer, e.g. without a Vulkan driver or a GPU able to draw to the window
+    pub fn new(window: &Window, config: GraphicsConfig) -> Result<Self, GraphicsInitError> {
+        let instance = create_instance()?;
 
-        let surface = create_surface(instance.clone(), window);
+        let surface = create_surface(instance.clone(), window)?;
 
         // Get the device info and queue
-        let (physical, device, queue) = get_device(&instance, surface.clone());
+        let (physical, device, queue) = get_device(&instance, surface.clone(), config.device_preference)?;
+        let limits = DeviceLimits::from_physical(physical);
 
-        let (swapchain, images) = create_raw_swapchain(window, device.clone(), surface, physical);
+        let (swapchain, images, color_space, capturable, image_count_limits) =
+            create_raw_swapchain(window, device.clone(), surface, physical, config.buffering, requested_present_mode(&config))?;
 
+        // the stencil only lives during the pass, holding the shapes of the masks
+        let stencil_format = choose_stencil_format(physical).ok_or(GraphicsInitError::NoStencilFormat)?;
         let render_pass = Arc::new(
             vulkano::single_pass_renderpass!(
                 device.clone(),
@@ -144,35 +661,137 @@ impl GraphicsHandler {
                         store: Store,
                         format: swapchain.format(),
                         samples: 1,
+                    },
+                    stencil: {
+                        load: Clear,
+                        store: DontCare,
+                        format: stencil_format,
+                        samples: 1,
                     }
                 },
                 pass: {
                     color: [color],
-                    depth_stencil: {}
+                    depth_stencil: {stencil}
                 }
-            )
-            .expect("Couldn't create new Vulkan RenderPass"),
+            )?,
         );
 
+        // reuse the shaders compiled by the driver in previous runs
+        let warmup_start = Instant::now();
+        let pipeline_cache = load_pipeline_cache(device.clone(), config.pipeline_cache_path.as_deref());
+
+        // UNORM swapchains store the shader output as is, so the shaders do the sRGB encoding themselves
+        let encode_srgb = !is_srgb_format(swapchain.format());
+
         let mut pipelines = HashMap::new();
+        let mut mask_pipelines = HashMap::new();
         create_pipeline!(
             "Primitive",
             device,
             render_pass,
+            pipeline_cache,
+            encode_srgb,
+            false,
             "assets/shaders/primitive.vert",
             "assets/shaders/primitive.frag",
-            &mut pipelines
+            &mut pipelines,
+            &mut mask_pipelines
         );
         create_pipeline!(
             "Sprite",
             device,
             render_pass,
+            pipeline_cache,
+            encode_srgb,
+            false,
+            "assets/shaders/sprite.vert",
+            "assets/shaders/sprite.frag",
+            &mut pipelines,
+            &mut mask_pipelines
+        );
+        create_pipeline!(
+            "SpriteAlphaTest",
+            device,
+            render_pass,
+            pipeline_cache,
+            encode_srgb,
+            true,
             "assets/shaders/sprite.vert",
             "assets/shaders/sprite.frag",
-            &mut pipelines
+            &mut pipelines,
+            &mut mask_pipelines
+        );
+        create_pipeline!(
+            "SdfCircle",
+            device,
+            render_pass,
+            pipeline_cache,
+            encode_srgb,
+            false,
+            "assets/shaders/primitive.vert",
+            "assets/shaders/sdf_circle.frag",
+            &mut pipelines,
+            &mut mask_pipelines
+        );
+        create_pipeline!(
+            "SdfRoundedRectangle",
+            device,
+            render_pass,
+            pipeline_cache,
+            encode_srgb,
+            false,
+            "assets/shaders/primitive.vert",
+            "assets/shaders/sdf_rounded_rectangle.frag",
+            &mut pipelines,
+            &mut mask_pipelines
+        );
+        create_pipeline!(
+            "SpritePush",
+            device,
+            render_pass,
+            pipeline_cache,
+            encode_srgb,
+            false,
+            "assets/shaders/sprite_push.vert",
+            "assets/shaders/sprite.frag",
+            &mut pipelines,
+            &mut mask_pipelines
         );
 
-        let swapchain = SwapchainHandler::new(swapchain, images, render_pass.clone());
+        let debug_pipeline = debug_draw::create_debug_pipeline(
+            device.clone(),
+            render_pass.clone(),
+            pipeline_cache.clone(),
+            encode_srgb,
+        )?;
+        let batch_pipeline = batch::create_batch_pipeline(
+            device.clone(),
+            render_pass.clone(),
+            pipeline_cache.clone(),
+            encode_srgb,
+            stencil_state(false),
+        )?;
+        let background_pipelines = BackgroundPipelines::new(
+            device.clone(),
+            render_pass.clone(),
+            pipeline_cache.clone(),
+            encode_srgb,
+        )?;
+
+        if let Some(path) = &config.pipeline_cache_path {
+            save_pipeline_cache(&pipeline_cache, path);
+        }
+        let pipeline_warmup = warmup_start.elapsed();
+
+        let swapchain = SwapchainHandler::new(
+            swapchain,
+            images,
+            color_space,
+            render_pass.clone(),
+            stencil_format,
+            capturable,
+            image_count_limits,
+        );
 
         let previous_frame_end = Some(sync::now(device.clone()).boxed());
 
@@ -183,12 +802,10 @@ impl GraphicsHandler {
         let window_size = Vector2::new(window_size.0, window_size.1);
         let camera_position = Vector2::new(0.0, 0.0);
         let camera_scale = Vector2::new(1.0, 1.0);
+        let camera_rotation = 0.0;
 
-        let global_uniform_data = GlobalUniformData {
-            camera_position: camera_position.extend(0.0).extend(0.0),
-            camera_scale: camera_scale.extend(0.0).extend(0.0),
-            window_size: window_size.extend(0).extend(0),
-        };
+        let global_uniform_data =
+            GlobalUniformData::new(window_size, config.projection, Rotation::Deg0, &Camera::default());
         let global_uniform_buffer = CpuAccessibleBuffer::from_data(
             device.clone(),
             BufferUsage::uniform_buffer_transfer_destination(),
@@ -197,58 +814,163 @@ impl GraphicsHandler {
         )
         .unwrap();
 
-        Self {
+        let screen_uniform_data =
+            GlobalUniformData::new(window_size, ProjectionMode::PixelTopLeft, Rotation::Deg0, &Camera::default());
+        let screen_uniform_buffer = CpuAccessibleBuffer::from_data(
+            device.clone(),
+            BufferUsage::uniform_buffer_transfer_destination(),
+            true,
+            screen_uniform_data,
+        )
+        .unwrap();
+
+        let debug_layout = debug_pipeline
+            .layout()
+            .descriptor_set_layout(0)
+            .expect("Couldn't use Descriptor Set Layout");
+        let debug_descriptor_set = Arc::new(
+            PersistentDescriptorSet::start(debug_layout.clone())
+                .add_buffer(global_uniform_buffer.clone())
+                .expect("Couldn't add the global buffer to the debug Descriptor Set")
+                .build()
+                .expect("Couldn't build the debug Descriptor Set"),
+        );
+
+        Ok(Self {
             instance,
             swapchain,
             render_pass,
             pipelines,
+            mask_pipelines,
             previous_frame_end,
             device,
             queue,
             draw_objects,
+            tags: HashMap::new(),
 
             global_uniform_buffer,
+            global_dirty: false,
             window_size,
+            viewport_region: None,
+            rotation: Rotation::Deg0,
             camera_position,
             camera_scale,
-        }
+            camera_rotation,
+            screen_uniform_buffer,
+            scoped_cameras: Vec::new(),
+            active_uniform_buffer: None,
+            masks: Vec::new(),
+            mask_stack: Vec::new(),
+            object_masks: HashMap::new(),
+            drawing_masks: false,
+            layer_requests: Vec::new(),
+            missing_pipelines: HashSet::new(),
+
+            capture_request: None,
+            last_capture: None,
+
+            frame_timeouts: FrameTimeouts::default(),
+            slow_frames: 0,
+
+            present_stats: PresentStats::default(),
+            second_start: (Instant::now(), PresentStats::default()),
+            last_second_stats: PresentStats::default(),
+
+            config,
+            limits,
+            pipeline_warmup,
+            sampler_config: SamplerConfig::default(),
+            samplers: RefCell::new(Vec::new()),
+            texture_cache: RefCell::new(TextureCache::new()),
+
+            camera_shakes: Vec::new(),
+            shake_offset: Vector2::new(0.0, 0.0),
+            last_shake_update: Instant::now(),
+
+            redraw_mode: RedrawMode::Continuous,
+            redraw_pending: true,
+            needs_sort: false,
+
+            debug_shapes: DebugShapes::new(),
+            debug_pipeline,
+            debug_descriptor_set,
+
+            background: None,
+            background_pipelines,
+
+            batching: true,
+            batch_pipeline,
+            batch_quad: batch::create_quad_buffer(queue.clone()),
+            instance_pool: CpuBufferPool::vertex_buffer(device.clone()),
+            batch_sets: RefCell::new(HashMap::new()),
+            draw_stats: DrawStats::default(),
+        })
     }
 
     /// Rendering function to call every frame
     pub fn vulkan_loop(&mut self, resized: bool, window: &Window) {
+        let frame_start = Instant::now();
+        self.swapchain.recreated = false;
+
+        if frame_start.duration_since(self.second_start.0) >= Duration::from_secs(1) {
+            self.last_second_stats = self.present_stats.since(&self.second_start.1);
+            self.second_start = (frame_start, self.present_stats);
+        }
+
+        // debug shapes only last one frame, even if it gets skipped
+        let debug_vertices = self.debug_shapes.take_vertices();
+
         // Update the render object list and flush all the data to the gpu
         {
+            let objects_count = self.draw_objects.len();
             self.draw_objects
                 .retain(|o| o.borrow().read_flags().contains(DrawFlags::USED));
-            self.flush_global_data();
-            for o in &self.draw_objects {
-                o.borrow().flush_data();
+            self.prune_tags();
+            self.prune_masks();
+            self.update_camera_shake();
+
+            if self.check_changes(objects_count != self.draw_objects.len(), resized)
+                && self.redraw_mode == RedrawMode::OnDemand
+            {
+                // Nothing to show, the last presented frame is still valid
+                self.previous_frame_end.as_mut().unwrap().cleanup_finished();
+                self.present_stats.unchanged += 1;
+                return;
+            }
+
+            // once per frame, however many objects were added or moved
+            if self.needs_sort {
+                self.sort_draw_objects();
             }
         }
 
         // Check the window resize and make new framebuffers if needed
         {
-            // If the window is being resized, return true, otherwise keep the original value (in case of pending resizes)
-            let recreate: bool = {
-                if resized {
-                    self.window_size = window.size().into();
-                    true
-                } else {
-                    self.swapchain.get_recreate()
-                }
-            };
-
-            self.swapchain.set_recreate(recreate);
+            if resized {
+                self.swapchain.notify_resize();
+            }
 
             let pass = self.render_pass.clone();
             let swapchain = self.get_swapchain();
 
             // Not an actual error, just a way to signify the need to retry the procedure
             if swapchain.check_and_recreate(window, pass).is_err() {
+                self.present_stats.swapchain_skipped += 1;
                 return;
             }
+
+            // The view follows the size of the images actually drawn to, which lags behind the window while resizing
+            // Read every frame, so recreations without a resize event (out of date or suboptimal swapchains) are caught too
+            let dimensions = Vector2::from(self.swapchain.chain.dimensions());
+            if self.window_size != dimensions {
+                self.window_size = dimensions;
+                self.global_dirty = true;
+            }
         }
 
+        // Flush all the data to the gpu
+        self.flush_now();
+
         // START OF THE ACTUAL LOOP
 
         // Get the future image
@@ -257,11 +979,28 @@ impl GraphicsHandler {
                 Ok(r) => r,
                 Err(AcquireError::OutOfDate) => {
                     self.get_swapchain().set_recreate(true);
+                    self.present_stats.swapchain_skipped += 1;
                     return;
                 }
                 Err(e) => panic!("Couldn't acquire next image from Vulkan Swapchain: {}", e),
             };
-        self.get_swapchain().set_recreate(suboptimal);
+        if self.swapchain.notify_suboptimal(suboptimal) && self.config.suboptimal_policy == SuboptimalPolicy::Skip {
+            // the image is never presented, the next submission still waits for the acquire to complete
+            let previous_frame_end = self.previous_frame_end.take().unwrap();
+            self.previous_frame_end = Some(previous_frame_end.join(acquire_future).boxed());
+            self.present_stats.swapchain_skipped += 1;
+            return;
+        }
+
+        // The uniform window size must describe the framebuffer drawn to, or objects get stretched
+        debug_assert_eq!(
+            self.window_size,
+            {
+                let dimensions = self.swapchain.framebuffers[image_num].dimensions();
+                Vector2::new(dimensions[0], dimensions[1])
+            },
+            "window_size is out of sync with the framebuffer"
+        );
 
         // Create Command Buffer for draw calls
         let mut builder = AutoCommandBufferBuilder::primary(
@@ -271,29 +1010,102 @@ impl GraphicsHandler {
         )
         .expect("Couldn't build Vulkan AutoCommandBuffer");
 
+        // Render the requested layers first, so the main pass can sample their targets
+        let layer_requests = std::mem::take(&mut self.layer_requests);
+        for (tag, camera, target) in &layer_requests {
+            self.record_layer(&mut builder, tag, camera, target);
+        }
+        if !layer_requests.is_empty() {
+            // the layers overwrote the global uniform with their cameras
+            let global_data =
+                GlobalUniformData::new(self.view_size(), self.config.projection, self.rotation, &self.main_camera());
+            builder
+                .update_buffer(self.global_uniform_buffer.clone(), Box::new(global_data))
+                .expect("Couldn't restore the main camera after rendering layers");
+        }
+
         // Initialize Command Buffer with the Render Pass
         builder
             .begin_render_pass(
                 self.get_swapchain().framebuffers[image_num].clone(),
                 SubpassContents::Inline,
-                vec![[0.0, 0.0, 0.0, 1.0].into()],
+                vec![[0.0, 0.0, 0.0, 1.0].into(), ClearValue::DepthStencil((1.0, 0))],
             )
             .expect("Couldn't begin Vulkan Render Pass");
 
+        if let Some(background) = &self.background {
+            let dynamic_state = DynamicState {
+                viewports: self.swapchain.dynamic_state.viewports.clone(),
+                ..DynamicState::none()
+            };
+            self.background_pipelines
+                .draw(background, self.rotation as u32, &mut builder, &dynamic_state);
+        }
+
+        // The masks must be in the stencil before the objects testing it
+        self.record_masks(&mut builder);
+        let mut screen_space = true;
+        self.set_viewport(screen_space);
+
         // Filter all visible DrawObjects
+        // Consecutive sprites sharing a batch are drawn together, the others break it and keep the z order
+        self.draw_stats = DrawStats::default();
+        let mut batch = SpriteBatch::new();
         let cloned_list = self.draw_objects.clone();
-        for obj in cloned_list
-            .iter()
-            .filter(|o| o.borrow().read_flags().contains(DrawFlags::VISIBLE))
-        {
-            // Draw object if visible
-            obj.borrow_mut().draw(self, &mut builder);
+        for obj in cloned_list.iter().filter(|o| {
+            let flags = o.borrow().read_flags();
+            flags.contains(DrawFlags::VISIBLE) && !flags.contains(DrawFlags::MASK)
+        }) {
+            // Draw object if visible, only where all its masks were written
+            let bits = self.mask_bits(obj);
+            let object_screen_space = obj.borrow().read_flags().contains(DrawFlags::SCREEN_SPACE);
+            if object_screen_space != screen_space {
+                // the batch was collected for the other viewport
+                self.record_batch(&mut batch, &mut builder);
+                screen_space = object_screen_space;
+                self.set_viewport(screen_space);
+            }
+            let instance = if self.batching {
+                obj.borrow().batch_instance()
+            } else {
+                None
+            };
+            self.draw_stats.objects += 1;
+
+            match instance {
+                Some((descriptor_set, instance)) => {
+                    if !batch.accepts(&descriptor_set, bits) {
+                        self.record_batch(&mut batch, &mut builder);
+                    }
+                    batch.push(descriptor_set, bits, instance);
+                }
+                None => {
+                    self.record_batch(&mut batch, &mut builder);
+                    self.set_stencil(bits, 0, bits);
+                    obj.borrow_mut().draw(self, &mut builder);
+                    self.draw_stats.draw_calls += 1;
+                }
+            }
         }
+        self.record_batch(&mut batch, &mut builder);
+        self.set_stencil(0, 0, 0);
+
+        if !debug_vertices.is_empty() {
+            self.set_viewport(false);
+            self.record_debug_shapes(&mut builder, debug_vertices);
+        }
+        self.set_viewport(true);
 
         // Build Command Buffer
         builder
             .end_render_pass()
             .expect("Couldn't properly end Vulkan Render Pass");
+
+        // Copy back the requested region of the frame (the image is only accessible while acquired)
+        let capture = match self.capture_request.take() {
+            Some((offset, size)) => self.record_frame_capture(&mut builder, image_num, offset, size),
+            None => None,
+        };
         let command_buffer = builder
             .build()
             .expect("Couldn't build Vulkan Command Buffer");
@@ -315,22 +1127,42 @@ impl GraphicsHandler {
 
         // Check the Future's output
         match future {
-            Ok(future) => {
-                // If the GPU is stuck rendering for too long terminate the program
-                future
-                    .wait(Some(std::time::Duration::from_secs(10)))
-                    .expect("GPU Timeout, terminating the program");
-                self.previous_frame_end = Some(future.boxed());
-            }
+            Ok(future) => match future.wait(Some(self.frame_timeouts.hard)) {
+                Ok(()) => {
+                    self.draw_stats.render_time = frame_start.elapsed();
+                    self.check_frame_time(self.draw_stats.render_time);
+                    self.previous_frame_end = Some(future.boxed());
+                    self.redraw_pending = false;
+                    self.present_stats.presented += 1;
+
+                    // The frame is complete, so is the copy
+                    if capture.is_some() {
+                        self.last_capture = capture;
+                    }
+                }
+                // The GPU is stuck on this frame: give up on it and rebuild the swapchain
+                Err(e) => {
+                    eprintln!(
+                        "GPU couldn't complete the frame within {:?} ({:?}), recovering",
+                        self.frame_timeouts.hard, e
+                    );
+                    self.slow_frames = 0;
+                    self.get_swapchain().set_recreate(true);
+                    self.previous_frame_end = Some(future.boxed());
+                    self.present_stats.failed += 1;
+                }
+            },
             // Not a real error, may happen with weird Window resizing
             Err(FlushError::OutOfDate) => {
                 self.get_swapchain().set_recreate(true);
                 self.previous_frame_end = Some(sync::now(self.get_device()).boxed());
+                self.present_stats.swapchain_skipped += 1;
             }
             // Couldn't flush the future, big problem, pls fix yourself
             Err(e) => {
                 eprintln!("Failed to flush Vulkan Future: {:?}", e);
                 self.previous_frame_end = Some(sync::now(self.get_device()).boxed());
+                self.present_stats.failed += 1;
             }
         }
 
@@ -338,106 +1170,1289 @@ impl GraphicsHandler {
         self.previous_frame_end.as_mut().unwrap().cleanup_finished();
     }
 
-    /// Sorter for the DrawObjects
-    fn sort_draw_objects(&mut self) {
-        self.draw_objects.sort_by(|a, b| {
-            a.borrow_mut()
-                .get_z_index()
-                .cmp(&b.borrow_mut().get_z_index())
-        });
-    }
-
-    /// Getter for the used Swapchain
-    pub fn get_swapchain(&mut self) -> &mut SwapchainHandler {
-        &mut self.swapchain
-    }
+    /// Render and present exactly one frame, for tools driving the rendering by hand (thumbnails, frame by frame tests)
+    /// The frame is drawn even in on-demand redraw mode, and retried while the swapchain has to be recreated
+    /// Returns whether it was presented
+    pub fn render_once(&mut self, resized: bool, window: &Window) -> bool {
+        let presented = self.present_stats.presented;
 
-    /// Getter for the used Device
-    pub fn get_device(&self) -> Arc<Device> {
-        self.device.clone()
-    }
+        // a single frame can't wait for the resize to settle
+        if resized {
+            self.swapchain.notify_resize();
+        }
+        self.swapchain.resize_debounce = 0;
 
-    /// Getter for a specific pipeline with a name
-    pub fn get_pipeline(
-        &self,
-        name: &str,
-    ) -> Arc<GraphicsPipeline<SingleBufferDefinition<Vertex>>> {
-        self.pipelines
-            .get(name)
-            .expect("No Vulkan Pipeline under this name was found")
-            .clone()
-    }
+        for _ in 0..RENDER_ONCE_ATTEMPTS {
+            self.redraw_pending = true;
+            self.vulkan_loop(false, window);
 
-    /// Getter for the Vulkan Queue
-    fn get_queue(&self) -> Arc<Queue> {
-        self.queue.clone()
-    }
+            if self.present_stats.presented != presented {
+                return true;
+            }
+        }
 
-    /// Getter for the global uniform buffer
-    pub fn get_global_uniform_buffer(&self) -> Arc<GlobalUniformBuffer> {
-        self.global_uniform_buffer.clone()
+        false
     }
 
-    /// Flusher for the global uniform buffer
-    fn flush_global_data(&self) {
-        let mut write_lock = self
-            .global_uniform_buffer
-            .write()
-            .expect("Couldn't write global GPU buffer");
-        let global_data = write_lock.deref_mut();
+    /// Create an offscreen image to render layers to, with the same format as the window
+    /// When the window isn't sRGB the target holds encoded colors, so sampling it encodes them again and brightens it
+    pub fn new_render_target(&self, dimensions: Vector2<u32>) -> Arc<RenderTarget> {
+        let usage = ImageUsage {
+            color_attachment: true,
+            sampled: true,
+            ..ImageUsage::none()
+        };
+        let image = AttachmentImage::with_usage(
+            self.get_device(),
+            [dimensions.x, dimensions.y],
+            self.swapchain.chain.format(),
+            usage,
+        )
+        .expect("Couldn't create image for Render Target");
+        let view = ImageView::new(image).expect("Couldn't create Image View for Render Target");
 
-        global_data.window_size = self.window_size.extend(0).extend(0);
-        global_data.camera_position = self.camera_position.extend(0.0).extend(0.0);
-        global_data.camera_scale = self.camera_scale.extend(0.0).extend(0.0);
+        let stencil = AttachmentImage::transient(
+            self.get_device(),
+            [dimensions.x, dimensions.y],
+            self.swapchain.stencil_format,
+        )
+        .expect("Couldn't create stencil image for Render Target");
+        let stencil = ImageView::new(stencil).expect("Couldn't create stencil Image View for Render Target");
+
+        // sharing the main render pass keeps all the pipelines compatible with the target
+        let framebuffer = Arc::new(
+            Framebuffer::start(self.render_pass.clone())
+                .add(view.clone())
+                .expect("Couldn't add Image View on Framebuffer creation")
+                .add(stencil)
+                .expect("Couldn't add stencil Image View on Framebuffer creation")
+                .build()
+                .expect("Couldn't build Framebuffer for Render Target"),
+        ) as Arc<dyn FramebufferAbstract + Send + Sync>;
+
+        Arc::new(RenderTarget {
+            view,
+            framebuffer,
+            dimensions,
+        })
     }
 
-    /// Create a new Immutable Vertex Buffer
-    pub fn new_vertex_buffer(
-        &self,
-        vao: VertexArray,
-        indices: Arc<dyn TypedBufferAccess<Content = [u16]> + Send + Sync>,
-    ) -> VertexBuffer {
-        VertexBuffer::new(self, vao, indices)
-            .expect("Device Memory Allocation Error during creation of new Vertex Buffer")
+    /// Render the visible objects with a tag to a target during the next frame, seen from `camera`
+    /// Every layer gets its own render pass before the main one, in the same command buffer:
+    /// the global uniform is rewritten with the layer camera right before it and restored after all layers
+    /// Objects created in a `camera_scope` or `screen_space` keep their own camera
+    pub fn render_layer_to_target(&mut self, tag: &str, camera: Camera, target: &Arc<RenderTarget>) {
+        self.layer_requests.push((tag.to_string(), camera, target.clone()));
+        self.redraw_pending = true;
     }
 
-    /// Create a new Immutable Index Buffer (used to order the vertices on drawing)
-    pub fn new_index_buffer(
-        &self,
-        indices: &[u16],
-    ) -> Arc<dyn TypedBufferAccess<Content = [u16]> + Send + Sync> {
-        let (buffer, future) = ImmutableBuffer::from_iter(
-            indices.iter().cloned(),
-            BufferUsage::index_buffer(),
-            self.queue.clone(),
-        )
-        .unwrap();
-        future.flush().unwrap();
-        buffer
+    fn record_layer(
+        &mut self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        tag: &str,
+        camera: &Camera,
+        target: &RenderTarget,
+    ) {
+        // keep the drawing order of the main list
+        let tagged = self.tags.get(tag).cloned().unwrap_or_default();
+        let objects: Vec<_> = self
+            .draw_objects
+            .iter()
+            .filter(|o| {
+                let flags = o.borrow().read_flags();
+                flags.contains(DrawFlags::VISIBLE) && !flags.contains(DrawFlags::MASK)
+            })
+            .filter(|o| tagged.iter().any(|t| same_object(t, o)))
+            .cloned()
+            .collect();
+
+        // targets are drawn upright, the sprites showing them get rotated with the rest of the window
+        let global_data = GlobalUniformData::new(target.dimensions, self.config.projection, Rotation::Deg0, camera);
+        builder
+            .update_buffer(self.global_uniform_buffer.clone(), Box::new(global_data))
+            .expect("Couldn't write the camera of a layer");
+
+        builder
+            .begin_render_pass(
+                target.framebuffer.clone(),
+                SubpassContents::Inline,
+                vec![[0.0, 0.0, 0.0, 0.0].into(), ClearValue::DepthStencil((1.0, 0))],
+            )
+            .expect("Couldn't begin Render Pass of a layer");
+
+        // the draws use the viewport of the swapchain, switch it to the target for the layer
+        let viewport = Viewport {
+            origin: [0.0, 0.0],
+            dimensions: [target.dimensions.x as f32, target.dimensions.y as f32],
+            depth_range: 0.0..1.0,
+        };
+        let main_viewports = self
+            .swapchain
+            .get_dynamic_state()
+            .viewports
+            .replace(vec![viewport]);
+
+        // masks only apply to the main pass
+        self.set_stencil(0, 0, 0);
+        for obj in objects {
+            obj.borrow().draw(self, builder);
+        }
+
+        self.swapchain.get_dynamic_state().viewports = main_viewports;
+
+        builder
+            .end_render_pass()
+            .expect("Couldn't end Render Pass of a layer");
+    }
+
+    /// Main camera as currently shown, shake included
+    fn main_camera(&self) -> Camera {
+        Camera {
+            position: self.camera_position + self.shake_offset,
+            scale: self.camera_scale,
+            rotation: self.camera_rotation,
+        }
+    }
+
+    /// Collect the changes since the last frame into `redraw_pending`, and clear the objects' dirty flags
+    /// Returns whether the frame can be skipped
+    fn check_changes(&mut self, objects_removed: bool, resized: bool) -> bool {
+        let mut changed = objects_removed
+            || resized
+            || self.global_dirty
+            || self.capture_request.is_some()
+            || self.swapchain.get_recreate();
+
+        for o in &self.draw_objects {
+            let mut object = o.borrow_mut();
+            changed |= object.read_flags().contains(DrawFlags::DIRTY);
+            self.needs_sort |= object.read_flags().contains(DrawFlags::Z_CHANGED);
+            object.write_flags().remove(DrawFlags::DIRTY | DrawFlags::Z_CHANGED);
+        }
+
+        self.redraw_pending |= changed;
+
+        !self.redraw_pending
+    }
+
+    pub fn get_redraw_mode(&self) -> RedrawMode {
+        self.redraw_mode
+    }
+
+    /// Render every frame, or only when something changed
+    pub fn set_redraw_mode(&mut self, mode: RedrawMode) {
+        self.redraw_mode = mode;
+        self.redraw_pending = true;
+    }
+
+    /// Render the next frame even if nothing changed (e.g. after editing an object through a `DrawObject` handle)
+    pub fn request_redraw(&mut self) {
+        self.redraw_pending = true;
+    }
+
+    /// Read the color of a pixel (in window coordinates, origin at the top left) as RGBA
+    /// The frame being rendered isn't accessible, so the pixel is copied during the next frame:
+    /// this returns the value from the last completed frame that included it, `None` until then
+    pub fn read_pixel(&mut self, screen: Vector2<u32>) -> Option<Vector4<u8>> {
+        if screen.x >= self.window_size.x || screen.y >= self.window_size.y {
+            return None;
+        }
+
+        self.capture_request = Some((screen, Vector2::new(1, 1)));
+
+        let capture = self.last_capture.as_ref()?;
+        if capture.offset != screen || capture.size != Vector2::new(1, 1) {
+            return None;
+        }
+
+        let pixel = capture.buffer.read().ok()?;
+        match capture.format {
+            Format::B8G8R8A8Unorm | Format::B8G8R8A8Srgb => {
+                Some(Vector4::new(pixel[2], pixel[1], pixel[0], pixel[3]))
+            }
+            Format::R8G8B8A8Unorm | Format::R8G8B8A8Srgb => {
+                Some(Vector4::new(pixel[0], pixel[1], pixel[2], pixel[3]))
+            }
+            // Formats that aren't 8 bit per channel can't be returned as such
+            _ => None,
+        }
+    }
+
+    /// Record the copy of a region of the swapchain image to a new host visible buffer
+    fn record_frame_capture(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        image_num: usize,
+        offset: Vector2<u32>,
+        size: Vector2<u32>,
+    ) -> Option<FrameCapture> {
+        if !self.swapchain.capturable {
+            return None;
+        }
+
+        let image = self.swapchain.images[image_num].clone();
+        let dimensions = image.dimensions();
+        if offset.x + size.x > dimensions[0] || offset.y + size.y > dimensions[1] {
+            return None;
+        }
+
+        let format = self.swapchain.chain.format();
+        let pixel_size = format.size()?;
+
+        let buffer = CpuAccessibleBuffer::from_iter(
+            self.get_device(),
+            BufferUsage::transfer_destination(),
+            true,
+            (0..(size.x * size.y) as usize * pixel_size).map(|_| 0u8),
+        )
+        .ok()?;
+
+        builder
+            .copy_image_to_buffer_dimensions(
+                image,
+                buffer.clone(),
+                [offset.x, offset.y, 0],
+                [size.x, size.y, 1],
+                0,
+                1,
+                0,
+            )
+            .ok()?;
+
+        Some(FrameCapture {
+            offset,
+            size,
+            format,
+            buffer,
+        })
+    }
+
+    /// Keep track of slow frames and warn when they keep happening
+    fn check_frame_time(&mut self, frame_time: Duration) {
+        if frame_time <= self.frame_timeouts.soft {
+            self.slow_frames = 0;
+            return;
+        }
+
+        self.slow_frames += 1;
+        if self.slow_frames == self.frame_timeouts.soft_repeat {
+            eprintln!(
+                "Performance warning: {} consecutive frames over {:?} (last one took {:?})",
+                self.slow_frames, self.frame_timeouts.soft, frame_time
+            );
+        }
+    }
+
+    pub fn get_frame_timeouts(&self) -> FrameTimeouts {
+        self.frame_timeouts
+    }
+
+    pub fn set_frame_timeouts(&mut self, timeouts: FrameTimeouts) {
+        self.frame_timeouts = timeouts;
+    }
+
+    pub fn get_graphics_config(&self) -> &GraphicsConfig {
+        &self.config
+    }
+
+    /// Time spent creating all the pipelines at startup, faster when the pipeline cache is warm
+    pub fn get_pipeline_warmup(&self) -> Duration {
+        self.pipeline_warmup
+    }
+
+    /// Change the number of swapchain images, the swapchain is rebuilt before the next frame
+    pub fn set_buffering(&mut self, buffering: BufferCount) {
+        self.config.buffering = buffering;
+        self.swapchain.set_image_count(buffering);
+    }
+
+    pub fn set_suboptimal_policy(&mut self, policy: SuboptimalPolicy) {
+        self.config.suboptimal_policy = policy;
+    }
+
+    /// Whether the last frame recreated the swapchain, because of a resize or because it went out of date
+    /// Useful to lay the UI out again after the resolution changed
+    pub fn swapchain_recreated_last_frame(&self) -> bool {
+        self.swapchain.recreated
+    }
+
+    /// Format of the swapchain images, an sRGB one when the surface supports it
+    pub fn swapchain_format(&self) -> Format {
+        self.swapchain.chain.format()
+    }
+
+    pub fn swapchain_color_space(&self) -> ColorSpace {
+        self.swapchain.color_space
+    }
+
+    /// Whether the swapchain images encode to sRGB on write, otherwise the fragment shaders encode their output
+    /// Either way a sprite of a plain (128, 128, 128) gray texture should read back as 128 in a frame capture,
+    /// a value near 188 means the colors got encoded twice, and near 55 that they weren't encoded at all
+    pub fn is_swapchain_srgb(&self) -> bool {
+        is_srgb_format(self.swapchain_format())
+    }
+
+    /// Present mode actually used, which may differ from the requested one if the surface doesn't support it
+    pub fn present_mode(&self) -> PresentMode {
+        self.swapchain.chain.present_mode()
+    }
+
+    /// Wait for the vertical blank (FIFO) or present as soon as possible (Mailbox, or Immediate without it),
+    /// the swapchain is rebuilt before the next frame
+    /// Clears the explicit present mode of the config
+    pub fn set_vsync(&mut self, vsync: bool) {
+        self.config.vsync = vsync;
+        self.config.present_mode = None;
+        self.update_present_mode();
+    }
+
+    /// Present with a specific mode, replaced by the closest supported one, `None` to follow `vsync` again
+    pub fn set_present_mode(&mut self, present_mode: Option<PresentMode>) {
+        self.config.present_mode = present_mode;
+        self.update_present_mode();
+    }
+
+    fn update_present_mode(&mut self) {
+        let requested_mode = requested_present_mode(&self.config);
+
+        let physical = self.device.physical_device();
+        let supported = match self.swapchain.chain.surface().capabilities(physical) {
+            Ok(caps) => caps.present_modes,
+            Err(e) => {
+                eprintln!("Couldn't get the present modes of the surface: {}", e);
+                return;
+            }
+        };
+
+        let present_mode = choose_present_mode(requested_mode, supported);
+        if present_mode != requested_mode {
+            eprintln!(
+                "Present mode {:?} isn't supported, using {:?} instead",
+                requested_mode, present_mode
+            );
+        }
+
+        self.swapchain.set_present_mode(present_mode);
+    }
+
+    /// Number of images actually used by the swapchain, after clamping to the surface limits
+    pub fn get_swapchain_image_count(&self) -> u32 {
+        self.swapchain.chain.num_images()
+    }
+
+    /// Block until the GPU has finished all the submitted work, to free resources safely
+    pub fn wait_idle(&mut self) {
+        // dropping the last frame's future waits for its fence
+        self.previous_frame_end = Some(sync::now(self.get_device()).boxed());
+
+        // nothing else submits to the device's queues while the engine is waiting here
+        if let Err(e) = unsafe { self.device.wait() } {
+            eprintln!("Couldn't wait for the GPU to be idle: {}", e);
+        }
+    }
+
+    /// Sorter for the DrawObjects, stable so objects with the same z index keep their creation order
+    fn sort_draw_objects(&mut self) {
+        self.draw_objects.sort_by(|a, b| {
+            a.borrow()
+                .get_z_index()
+                .cmp(&b.borrow().get_z_index())
+        });
+        self.needs_sort = false;
+    }
+
+    /// Getter for the used Swapchain
+    pub fn get_swapchain(&mut self) -> &mut SwapchainHandler {
+        &mut self.swapchain
+    }
+
+    /// Getter for the used Device
+    pub fn get_device(&self) -> Arc<Device> {
+        self.device.clone()
+    }
+
+    /// Render pass used for all the drawing, to build compatible pipelines
+    pub fn render_pass(&self) -> Arc<RenderPass> {
+        self.render_pass.clone()
+    }
+
+    /// Subpass the engine's pipelines draw in
+    pub fn subpass(&self) -> Subpass {
+        Subpass::from(self.render_pass.clone(), 0).expect("Couldn't find the main Subpass")
+    }
+
+    /// Limits of the physical device in use
+    pub fn device_limits(&self) -> DeviceLimits {
+        self.limits
+    }
+
+    /// Check that a texture of the given size can be created on this device
+    fn validate_texture_dimensions(&self, dimensions: Vector2<u32>) -> Result<(), TextureError> {
+        let limit = self.limits.max_image_dimension_2d;
+        if dimensions.x > limit || dimensions.y > limit {
+            return Err(TextureError::TooLarge { dimensions, limit });
+        }
+
+        Ok(())
+    }
+
+    /// Getter for a specific pipeline with a name
+    pub fn get_pipeline(
+        &self,
+        name: &str,
+    ) -> Option<Arc<GraphicsPipeline<SingleBufferDefinition<Vertex>>>> {
+        self.pipelines.get(name).cloned()
+    }
+
+    /// Pipeline used by the objects being drawn: the one with a name, or its mask variant while writing masks
+    pub fn get_draw_pipeline(
+        &self,
+        name: &str,
+    ) -> Option<Arc<GraphicsPipeline<SingleBufferDefinition<Vertex>>>> {
+        if self.drawing_masks {
+            self.mask_pipelines.get(name).cloned()
+        } else {
+            self.get_pipeline(name)
+        }
+    }
+
+    /// Warn that there's no pipeline with a name, only the first time it's asked for
+    pub(crate) fn warn_missing_pipeline(&mut self, name: &str) {
+        if self.missing_pipelines.insert(name.to_owned()) {
+            eprintln!("No Vulkan Pipeline named \"{}\", skipping its draws", name);
+        }
+    }
+
+    /// Names of all the available pipelines
+    pub fn pipeline_names(&self) -> Vec<String> {
+        self.pipelines.keys().cloned().collect()
+    }
+
+    /// Getter for the Vulkan Queue
+    fn get_queue(&self) -> Arc<Queue> {
+        self.queue.clone()
+    }
+
+    /// Getter for the global uniform buffer (the one of the current `camera_scope`, if any)
+    pub fn get_global_uniform_buffer(&self) -> Arc<GlobalUniformBuffer> {
+        match &self.active_uniform_buffer {
+            Some(buffer) => buffer.clone(),
+            None => self.global_uniform_buffer.clone(),
+        }
+    }
+
+    /// Run `scope` with the objects it creates drawn under a fixed `camera` instead of the main one
+    /// e.g. `camera_scope(Camera::default(), |gl| gl.new_sprite(...))` for UI that ignores the world camera
+    /// Each camera gets its own uniform buffer, so both views are drawn in the same frame
+    pub fn camera_scope<R>(&mut self, camera: Camera, scope: impl FnOnce(&mut GraphicsHandler) -> R) -> R {
+        let buffer = match self.scoped_cameras.iter().find(|(c, _)| *c == camera) {
+            Some((_, buffer)) => buffer.clone(),
+            None => {
+                let data = GlobalUniformData::new(self.view_size(), self.config.projection, self.rotation, &camera);
+                let buffer = CpuAccessibleBuffer::from_data(
+                    self.get_device(),
+                    BufferUsage::uniform_buffer_transfer_destination(),
+                    true,
+                    data,
+                )
+                .expect("Couldn't create uniform buffer for scoped camera");
+
+                self.scoped_cameras.push((camera, buffer.clone()));
+                buffer
+            }
+        };
+
+        self.with_uniform_buffer(buffer, scope)
+    }
+
+    /// Run `scope` with the objects it creates pinned to the screen, ignoring the camera
+    /// Their positions are in window pixels from the top left corner, whatever the projection mode
+    pub fn screen_space<R>(&mut self, scope: impl FnOnce(&mut GraphicsHandler) -> R) -> R {
+        self.with_uniform_buffer(self.screen_uniform_buffer.clone(), scope)
+    }
+
+    /// Whether the objects created now are pinned to the screen, i.e. inside a `screen_space` scope
+    pub fn is_screen_space(&self) -> bool {
+        self.active_uniform_buffer
+            .as_ref()
+            .map_or(false, |buffer| Arc::ptr_eq(buffer, &self.screen_uniform_buffer))
+    }
+
+    fn with_uniform_buffer<R>(&mut self, buffer: Arc<GlobalUniformBuffer>, scope: impl FnOnce(&mut GraphicsHandler) -> R) -> R {
+        // scopes can be nested, the outer camera is restored afterwards
+        let previous = self.active_uniform_buffer.replace(buffer);
+        let result = scope(self);
+        self.active_uniform_buffer = previous;
+
+        result
+    }
+
+    pub fn get_camera_position(&self) -> Vector2<f32> {
+        self.camera_position
+    }
+
+    pub fn get_camera_scale(&self) -> Vector2<f32> {
+        self.camera_scale
+    }
+
+    pub fn get_camera_rotation(&self) -> f32 {
+        self.camera_rotation
+    }
+
+    /// Set the whole camera state at once
+    pub fn set_camera(&mut self, position: Vector2<f32>, scale: Vector2<f32>, rotation: f32) {
+        self.camera_position = position;
+        self.camera_scale = scale;
+        self.camera_rotation = rotation;
+        self.global_dirty = true;
+    }
+
+    pub fn set_camera_position(&mut self, position: Vector2<f32>) {
+        self.camera_position = position;
+        self.global_dirty = true;
+    }
+
+    pub fn set_camera_scale(&mut self, scale: Vector2<f32>) {
+        self.camera_scale = scale;
+        self.global_dirty = true;
+    }
+
+    pub fn set_camera_rotation(&mut self, rotation: f32) {
+        self.camera_rotation = rotation;
+        self.global_dirty = true;
+    }
+
+    /// World position shown at a point of the window (in pixels, origin at the top left)
+    pub fn screen_to_world(&self, screen: Vector2<f32>) -> Vector2<f32> {
+        // the world is drawn in the viewport region, relative to its own top left corner
+        let (screen, window_size) = match self.viewport_region {
+            Some(region) => (
+                screen - Vector2::new(region.x() as f32, region.y() as f32),
+                Vector2::new(region.width() as f32, region.height() as f32),
+            ),
+            None => (screen, Vector2::new(self.window_size.x as f32, self.window_size.y as f32)),
+        };
+        // the scene may be drawn rotated in the window
+        let screen = self.rotation.scene_point(screen, window_size);
+        let window_size = self.rotation.scene_size(window_size);
+
+        let view = match self.config.projection {
+            ProjectionMode::Centered => {
+                let clip = Vector2::new(2.0 * screen.x / window_size.x - 1.0, 2.0 * screen.y / window_size.y - 1.0);
+                Vector2::new(clip.x * window_size.x * self.camera_scale.x, clip.y * window_size.y * self.camera_scale.y)
+            }
+            ProjectionMode::PixelTopLeft => Vector2::new(screen.x * self.camera_scale.x, screen.y * self.camera_scale.y),
+        };
+
+        // undo the rotation of the view around the camera
+        let (sin, cos) = self.camera_rotation.sin_cos();
+        let rotated = Vector2::new(cos * view.x - sin * view.y, sin * view.x + cos * view.y);
+
+        self.camera_position + self.shake_offset + rotated
+    }
+
+    /// Draw the world (every object not created in `screen_space`) in a region of the window only, in pixels from
+    /// its top left corner, e.g. to leave room for an editor panel made of screen space objects
+    /// `None` draws the world in the whole window again
+    pub fn set_viewport_region(&mut self, region: Option<Rect>) {
+        self.viewport_region = region;
+        self.global_dirty = true;
+    }
+
+    pub fn get_viewport_region(&self) -> Option<Rect> {
+        self.viewport_region
+    }
+
+    /// Size of the area the world is drawn in, sideways when the output is rotated by a quarter turn
+    fn view_size(&self) -> Vector2<u32> {
+        let size = match self.viewport_region {
+            Some(region) => Vector2::new(region.width(), region.height()),
+            None => self.window_size,
+        };
+
+        self.rotation.scene_size(size)
+    }
+
+    /// Rotate the whole output (not the camera) to match a display mounted sideways or upside down
+    /// Applied in the projection, `screen_to_world` takes the window coordinates of the rotated output
+    pub fn set_surface_transform(&mut self, rotation: Rotation) {
+        self.rotation = rotation;
+        self.global_dirty = true;
+    }
+
+    pub fn get_surface_transform(&self) -> Rotation {
+        self.rotation
+    }
+
+    /// Box around the part of the world currently shown (camera, zoom and viewport region included)
+    pub fn visible_world_bounds(&self) -> Aabb {
+        let (origin, size) = match self.viewport_region {
+            Some(region) => (
+                Vector2::new(region.x() as f32, region.y() as f32),
+                Vector2::new(region.width() as f32, region.height() as f32),
+            ),
+            None => (Vector2::new(0.0, 0.0), Vector2::new(self.window_size.x as f32, self.window_size.y as f32)),
+        };
+
+        // the view may be rotated, so every corner counts
+        let corners = [
+            origin,
+            origin + Vector2::new(size.x, 0.0),
+            origin + Vector2::new(0.0, size.y),
+            origin + size,
+        ];
+        let first = self.screen_to_world(corners[0]);
+        corners[1..].iter().fold(Aabb::new(first, first), |bounds, corner| {
+            let point = self.screen_to_world(*corner);
+            Aabb::new(
+                Vector2::new(bounds.min.x.min(point.x), bounds.min.y.min(point.y)),
+                Vector2::new(bounds.max.x.max(point.x), bounds.max.y.max(point.y)),
+            )
+        })
+    }
+
+    /// Shake the view with a random offset of up to `intensity` units, decaying over `duration` seconds
+    /// Multiple shakes add up, and the camera position itself is never modified
+    pub fn add_camera_shake(&mut self, intensity: f32, duration: f32) {
+        if duration <= 0.0 {
+            return;
+        }
+
+        // start counting time from the first shake, not from the last one that ended
+        if self.camera_shakes.is_empty() {
+            self.last_shake_update = Instant::now();
+        }
+
+        let mut rng = rand::thread_rng();
+        self.camera_shakes.push(CameraShake {
+            intensity,
+            duration,
+            elapsed: 0.0,
+            seeds: (rng.gen(), rng.gen()),
+        });
+    }
+
+    /// Move the camera to `target` at once, stopping the shakes so the view doesn't jolt right after
+    /// Meant for scene changes and respawns, where the view must not travel across the level
+    pub fn snap_camera_to(&mut self, target: Vector2<f32>) {
+        self.camera_position = target;
+        self.camera_shakes.clear();
+        self.shake_offset = Vector2::new(0.0, 0.0);
+        self.global_dirty = true;
+    }
+
+    /// Advance the camera shakes by the time elapsed since the last frame
+    fn update_camera_shake(&mut self) {
+        if self.camera_shakes.is_empty() {
+            if self.shake_offset != Vector2::new(0.0, 0.0) {
+                self.shake_offset = Vector2::new(0.0, 0.0);
+                self.global_dirty = true;
+            }
+            return;
+        }
+
+        let delta = self.last_shake_update.elapsed().as_secs_f32();
+        self.last_shake_update = Instant::now();
+
+        for shake in &mut self.camera_shakes {
+            shake.elapsed += delta;
+        }
+        self.camera_shakes.retain(|s| s.elapsed < s.duration);
+
+        self.shake_offset = self
+            .camera_shakes
+            .iter()
+            .fold(Vector2::new(0.0, 0.0), |offset, s| offset + s.offset());
+        self.global_dirty = true;
+    }
+
+    /// Draw a line for the next frame only, e.g. to show a path or a vector
+    pub fn debug_line(&mut self, a: Vector2<f32>, b: Vector2<f32>, color: Vector4<f32>) {
+        self.debug_shapes.line(a, b, color);
+        self.redraw_pending = true;
+    }
+
+    /// Draw the outline of a rectangle for the next frame only, e.g. to show a hitbox
+    pub fn debug_rect(&mut self, center: Vector2<f32>, size: Vector2<f32>, color: Vector4<f32>) {
+        self.debug_shapes.rect(center, size, color);
+        self.redraw_pending = true;
+    }
+
+    /// Draw the outline of a circle for the next frame only
+    pub fn debug_circle(&mut self, center: Vector2<f32>, radius: f32, color: Vector4<f32>) {
+        self.debug_shapes.circle(center, radius, color);
+        self.redraw_pending = true;
+    }
+
+    /// Draw the debug lines of this frame in a single draw call
+    fn record_debug_shapes(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        vertices: Vec<DebugVertex>,
+    ) {
+        let buffer = match CpuAccessibleBuffer::from_iter(
+            self.get_device(),
+            BufferUsage::vertex_buffer(),
+            false,
+            vertices.into_iter(),
+        ) {
+            Ok(buffer) => buffer,
+            Err(e) => {
+                eprintln!("Couldn't create the debug lines buffer: {}", e);
+                return;
+            }
+        };
+
+        // the debug pipeline doesn't test the stencil, so it only takes the viewport
+        let dynamic_state = DynamicState {
+            viewports: self.swapchain.dynamic_state.viewports.clone(),
+            ..DynamicState::none()
+        };
+
+        builder
+            .draw(
+                self.debug_pipeline.clone(),
+                &dynamic_state,
+                vec![buffer],
+                self.debug_descriptor_set.clone(),
+                (),
+                vec![],
+            )
+            .expect("Couldn't add the debug Draw command to Vulkan Render Pass");
+    }
+
+    /// Fill the window with a gradient or an image before drawing the objects, following its size and ignoring the camera
+    pub fn set_background(&mut self, background: Background) -> Result<(), TextureError> {
+        let fill = match background {
+            Background::Gradient(top, bottom) => BackgroundFill::Gradient(top, bottom),
+            Background::Image(path) => {
+                let (texture, _) = self.try_get_texture(&path)?;
+                self.background_pipelines.image_fill(texture, self.create_texture_sampler())
+            }
+        };
+
+        self.background = Some(fill);
+        self.redraw_pending = true;
+        Ok(())
+    }
+
+    /// Go back to clearing the window with a solid color
+    pub fn clear_background(&mut self) {
+        self.background = None;
+        self.redraw_pending = true;
+    }
+
+    /// Write the data of the camera and of every object to the GPU buffers without presenting a frame,
+    /// e.g. right before a frame capture or a one-off offscreen render
+    /// Flushing writes the current state, so doing it again (or before the per-frame flush) changes nothing
+    pub fn flush_now(&mut self) {
+        self.previous_frame_end.as_mut().unwrap().cleanup_finished();
+
+        self.flush_global_data();
+        for o in &self.draw_objects {
+            o.borrow().flush_data();
+        }
+    }
+
+    /// Flusher for the global uniform buffer (only writes when the data changed)
+    fn flush_global_data(&mut self) {
+        // cameras of scopes whose objects are all gone aren't needed anymore
+        self.scoped_cameras
+            .retain(|(_, buffer)| Arc::strong_count(buffer) > 1);
+
+        if !self.global_dirty {
+            return;
+        }
+        self.global_dirty = false;
+
+        let camera = self.main_camera();
+
+        let mut write_lock = self
+            .global_uniform_buffer
+            .write()
+            .expect("Couldn't write global GPU buffer");
+        *write_lock = GlobalUniformData::new(self.view_size(), self.config.projection, self.rotation, &camera);
+
+        let mut write_lock = self
+            .screen_uniform_buffer
+            .write()
+            .expect("Couldn't write global GPU buffer");
+        *write_lock = GlobalUniformData::new(
+            self.rotation.scene_size(self.window_size),
+            ProjectionMode::PixelTopLeft,
+            self.rotation,
+            &Camera::default(),
+        );
+
+        // scoped cameras don't move, but still follow the window size
+        let view_size = self.view_size();
+        for (camera, buffer) in &self.scoped_cameras {
+            let mut write_lock = buffer.write().expect("Couldn't write global GPU buffer");
+            *write_lock = GlobalUniformData::new(view_size, self.config.projection, self.rotation, camera);
+        }
+    }
+
+    /// Create a new Immutable Vertex Buffer
+    pub fn new_vertex_buffer(
+        &self,
+        vao: VertexArray,
+        indices: IndexBuffer,
+    ) -> Result<VertexBuffer, BufferCreationError> {
+        VertexBuffer::new(self, vao, indices)
+    }
+
+    /// Create a new Immutable Index Buffer (used to order the vertices on drawing)
+    /// The indices are stored as `u16` whenever they all fit in its range, as `u32` otherwise
+    pub fn new_index_buffer(&self, indices: &[u32]) -> Result<IndexBuffer, BufferCreationError> {
+        if indices.iter().all(|&i| i <= u16::MAX as u32) {
+            let (buffer, future) = ImmutableBuffer::from_iter(
+                indices.iter().map(|&i| i as u16),
+                BufferUsage::index_buffer(),
+                self.queue.clone(),
+            )?;
+            future.flush()?;
+            Ok(IndexBuffer::U16(buffer))
+        } else {
+            let (buffer, future) = ImmutableBuffer::from_iter(
+                indices.iter().cloned(),
+                BufferUsage::index_buffer(),
+                self.queue.clone(),
+            )?;
+            future.flush()?;
+            Ok(IndexBuffer::U32(buffer))
+        }
+    }
+
+    /// Create a new SpriteObject
+    pub fn new_sprite(&mut self, texture_path: &str, z_index: u8) -> SpriteObject {
+        self.new_sprite_with_data_mode(texture_path, z_index, SpriteDataMode::UniformBuffer)
+    }
+
+    /// Create a new SpriteObject sending its data to the GPU in the chosen way
+    pub fn new_sprite_with_data_mode(
+        &mut self,
+        texture_path: &str,
+        z_index: u8,
+        data_mode: SpriteDataMode,
+    ) -> SpriteObject {
+        let sprite = Rc::new(RefCell::new(Sprite::with_data_mode(
+            texture_path,
+            self,
+            z_index,
+            data_mode,
+        )));
+
+        self.append_draw_object(sprite.clone());
+
+        SpriteObject::new(sprite)
+    }
+
+    /// State of every sprite loaded from a file, in drawing order
+    /// Other objects (primitives, animated sprites) aren't part of the snapshot
+    pub fn snapshot(&self) -> Vec<SpriteState> {
+        let mut states: Vec<SpriteState> = self
+            .draw_objects
+            .iter()
+            .filter_map(|o| o.borrow().get_sprite_state())
+            .collect();
+        // objects added or moved since the last frame aren't sorted yet
+        states.sort_by_key(|state| state.z_index);
+
+        states
+    }
+
+    /// Create the sprites of a snapshot again
+    /// Existing objects are left untouched: drop their handles to replace the scene
+    pub fn restore(&mut self, snapshot: &[SpriteState]) -> Vec<SpriteObject> {
+        snapshot
+            .iter()
+            .map(|state| {
+                let sprite = Rc::new(RefCell::new(Sprite::from_state(state, self)));
+                self.append_draw_object(sprite.clone());
+
+                SpriteObject::new(sprite)
+            })
+            .collect()
+    }
+
+    /// Create a new SpriteObject showing an already uploaded texture, like the one of a `RenderTarget`
+    pub fn new_sprite_from_texture(&mut self, texture: Texture, dimensions: Vector2<u32>, z_index: u8) -> SpriteObject {
+        let sprite = Rc::new(RefCell::new(Sprite::from_texture(texture, dimensions, self, z_index)));
+
+        self.append_draw_object(sprite.clone());
+
+        SpriteObject::new(sprite)
+    }
+
+    /// Create a new AnimatedSpriteObject playing all the frames of an animated PNG
+    pub fn new_animated_sprite_from_file(
+        &mut self,
+        texture_path: &str,
+        z_index: u8,
+    ) -> Result<AnimatedSpriteObject, TextureError> {
+        let sprite = Rc::new(RefCell::new(AnimatedSprite::from_file(
+            texture_path,
+            self,
+            z_index,
+        )?));
+
+        self.append_draw_object(sprite.clone());
+
+        Ok(AnimatedSpriteObject::new(sprite))
+    }
+
+    /// Create a new AnimatedSpriteObject cycling through regions of a texture loaded from a file
+    pub fn new_animated_sprite(
+        &mut self,
+        texture_path: &str,
+        frames: Vec<Vector4<u32>>,
+        frame_duration: f32,
+        z_index: u8,
+    ) -> AnimatedSpriteObject {
+        let sprite = Sprite::new(texture_path, self, z_index);
+        let sprite = Rc::new(RefCell::new(AnimatedSprite::from_frames(sprite, frames, frame_duration)));
+
+        self.append_draw_object(sprite.clone());
+
+        AnimatedSpriteObject::new(sprite)
+    }
+
+    /// Create a new TextObject drawing `content` with a bitmap font, `position` being its top left corner
+    pub fn new_text(&mut self, content: &str, font: &BitmapFont, position: Vector2<f32>, z_index: u8) -> TextObject {
+        let text = Rc::new(RefCell::new(Text::new(content, font, position, self, z_index)));
+
+        self.append_draw_object(text.clone());
+
+        TextObject::new(text)
+    }
+
+    /// Create a new rectangular PrimitiveObject
+    pub fn new_rectangle(&mut self, scale: Vector2<f32>, color: Vector4<f32>, global_position: Vector2<f32>, z_index: u8) -> PrimitiveObject {
+        let primitive = Rc::new(RefCell::new(Primitive::rectangle(scale, color, global_position, self, z_index)));
+
+        self.append_draw_object(primitive.clone());
+
+        PrimitiveObject::new(primitive)
+    }
+
+    /// Create a new circle approximated by a polygon of `DEFAULT_CIRCLE_SEGMENTS` sides
+    pub fn new_circle(&mut self, radius: f32, color: Vector4<f32>, global_position: Vector2<f32>, z_index: u8) -> PrimitiveObject {
+        self.new_circle_with_segments(radius, DEFAULT_CIRCLE_SEGMENTS, color, global_position, z_index)
+    }
+
+    /// Like `new_circle`, with more segments for big circles or fewer for small ones
+    pub fn new_circle_with_segments(&mut self, radius: f32, segments: u32, color: Vector4<f32>, global_position: Vector2<f32>, z_index: u8) -> PrimitiveObject {
+        let primitive = Rc::new(RefCell::new(Primitive::circle(radius, segments, color, global_position, self, z_index)));
+
+        self.append_draw_object(primitive.clone());
+
+        PrimitiveObject::new(primitive)
+    }
+
+    /// Create a new antialiased circle, drawn on a single quad
+    pub fn new_sdf_circle(&mut self, radius: f32, color: Vector4<f32>, global_position: Vector2<f32>, z_index: u8) -> PrimitiveObject {
+        let primitive = Rc::new(RefCell::new(Primitive::sdf_circle(radius, color, global_position, self, z_index)));
+
+        self.append_draw_object(primitive.clone());
+
+        PrimitiveObject::new(primitive)
+    }
+
+    /// Create a new rectangle with antialiased rounded corners, drawn on a single quad
+    pub fn new_rounded_rectangle(&mut self, scale: Vector2<f32>, corner_radius: f32, color: Vector4<f32>, global_position: Vector2<f32>, z_index: u8) -> PrimitiveObject {
+        let primitive = Rc::new(RefCell::new(Primitive::rounded_rectangle(scale, corner_radius, color, global_position, self, z_index)));
+
+        self.append_draw_object(primitive.clone());
+
+        PrimitiveObject::new(primitive)
+    }
+
+    /// Add an object with its own `Draw` implementation, drawn in z index order with the built-in ones
+    /// It's marked as used (its flags keep their visibility) and dropped from the scene when its handle is dropped
+    pub fn add_custom(&mut self, obj: DrawObject<dyn Draw>) -> GraphicObject<dyn Draw> {
+        obj.borrow_mut().write_flags().insert(DrawFlags::USED | DrawFlags::DIRTY);
+
+        self.append_draw_object(obj.clone());
+
+        GraphicObject::new(obj)
+    }
+
+    /// Append a new DrawObject to the draw_object vector for draw
+    fn append_draw_object(&mut self, obj: DrawObject<dyn Draw>) {
+        if self.is_screen_space() {
+            obj.borrow_mut().write_flags().insert(DrawFlags::SCREEN_SPACE);
+        }
+
+        // a new object may reuse the address of a dead one
+        let key = Rc::as_ptr(&obj) as *const ();
+        self.object_masks.remove(&key);
+        let bits = self.mask_stack.iter().fold(0, |bits, bit| bits | bit);
+        if bits != 0 {
+            self.object_masks.insert(key, (Rc::downgrade(&obj), bits));
+        }
+
+        self.draw_objects.push(obj);
+        self.redraw_pending = true;
+        self.needs_sort = true;
+    }
+
+    /// Add an object to a tag group, an object can have many tags
+    pub fn set_tag<O: Draw + 'static>(&mut self, object: &GraphicObject<O>, tag: &str) {
+        let object = object.get_draw_object();
+        let group = self.tags.entry(tag.to_string()).or_insert_with(Vec::new);
+
+        if !group.iter().any(|o| same_object(o, &object)) {
+            group.push(Rc::downgrade(&object));
+        }
+    }
+
+    pub fn remove_tag<O: Draw + 'static>(&mut self, object: &GraphicObject<O>, tag: &str) {
+        let object = object.get_draw_object();
+
+        if let Some(group) = self.tags.get_mut(tag) {
+            group.retain(|o| !same_object(o, &object));
+        }
+    }
+
+    /// All the living objects with a tag
+    pub fn objects_with_tag(&self, tag: &str) -> Vec<DrawObject<dyn Draw>> {
+        match self.tags.get(tag) {
+            Some(group) => group
+                .iter()
+                .filter_map(|o| o.upgrade())
+                .filter(|o| o.borrow().read_flags().contains(DrawFlags::USED))
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Forget the objects that were removed from the draw list
+    fn prune_tags(&mut self) {
+        for group in self.tags.values_mut() {
+            group.retain(|o| o.strong_count() > 0);
+        }
+        self.tags.retain(|_, group| !group.is_empty());
+    }
+
+    /// Use an object as a mask: the objects created until the matching `pop_mask` are only drawn inside its shape
+    /// The shape itself isn't shown, and its transparent half (alpha below 0.5) is left out of the mask
+    /// Masks can be nested, limiting the objects to the intersection of all the masks pushed
+    ///
+    /// Each mask gets one bit of an 8 bit stencil attachment: the masks are written to it at the start of
+    /// the frame (whatever their z index), with a variant of their pipeline that only writes their bit,
+    /// then every object draws where the bits of its masks are all set
+    /// Up to 8 masks can be alive at once, the objects under a removed mask stop being clipped by it
+    pub fn push_mask<O: Draw + 'static>(&mut self, shape: &GraphicObject<O>) {
+        self.prune_masks();
+
+        let used = self.masks.iter().fold(0, |bits, mask| bits | mask.bit);
+        let bit = match (0..MAX_MASKS).map(|i| 1 << i).find(|bit| used & bit == 0) {
+            Some(bit) => bit,
+            None => {
+                eprintln!("Couldn't push mask: all {} stencil bits are in use", MAX_MASKS);
+                // keep the stack balanced for the matching pop
+                self.mask_stack.push(0);
+                return;
+            }
+        };
+
+        let object = shape.get_draw_object();
+        object.borrow_mut().write_flags().insert(DrawFlags::MASK);
+
+        self.masks.push(Mask {
+            object: Rc::downgrade(&object),
+            bit,
+            parent_bits: self.mask_stack.iter().fold(0, |bits, bit| bits | bit),
+        });
+        self.mask_stack.push(bit);
+        self.redraw_pending = true;
+    }
+
+    /// Stop applying the last pushed mask to new objects
+    pub fn pop_mask(&mut self) {
+        if self.mask_stack.pop().is_none() {
+            eprintln!("pop_mask called without a matching push_mask");
+        }
+    }
+
+    /// Forget the dead masks, and the objects that were removed from the draw list
+    fn prune_masks(&mut self) {
+        let mut dead_bits = 0;
+        self.masks.retain(|mask| {
+            let alive = mask
+                .object
+                .upgrade()
+                .map_or(false, |o| o.borrow().read_flags().contains(DrawFlags::USED));
+            if !alive {
+                dead_bits |= mask.bit;
+            }
+            alive
+        });
+
+        self.object_masks.retain(|_, (object, _)| object.strong_count() > 0);
+
+        // the bits may be reused by new masks, which mustn't clip the objects of the old ones
+        if dead_bits != 0 {
+            for (_, bits) in self.object_masks.values_mut() {
+                *bits &= !dead_bits;
+            }
+            for mask in self.masks.iter_mut() {
+                mask.parent_bits &= !dead_bits;
+            }
+        }
+    }
+
+    /// Stencil bits of the masks applied to an object
+    fn mask_bits(&self, object: &DrawObject<dyn Draw>) -> u32 {
+        self.object_masks
+            .get(&(Rc::as_ptr(object) as *const ()))
+            .map_or(0, |(_, bits)| *bits)
+    }
+
+    /// Draw in the whole window (screen space objects) or in the viewport region (the world)
+    fn set_viewport(&mut self, screen_space: bool) {
+        let (origin, dimensions) = match self.viewport_region {
+            Some(region) if !screen_space => (
+                [region.x() as f32, region.y() as f32],
+                [region.width() as f32, region.height() as f32],
+            ),
+            _ => ([0.0, 0.0], [self.window_size.x as f32, self.window_size.y as f32]),
+        };
+
+        self.swapchain.get_dynamic_state().viewports = Some(vec![Viewport {
+            origin,
+            dimensions,
+            depth_range: 0.0..1.0,
+        }]);
+    }
+
+    /// Stencil test of the next draws: the `compare_mask` bits must match `reference`,
+    /// and the `write_mask` bits are replaced by it
+    fn set_stencil(&mut self, compare_mask: u32, write_mask: u32, reference: u32) {
+        let state = self.swapchain.get_dynamic_state();
+        state.compare_mask = Some(stencil_value(compare_mask));
+        state.write_mask = Some(stencil_value(write_mask));
+        state.reference = Some(stencil_value(reference));
+    }
+
+    /// Write the bits of the living masks to the stencil, each one only inside the masks it's nested in
+    fn record_masks(&mut self, builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>) {
+        let masks: Vec<_> = self
+            .masks
+            .iter()
+            .filter_map(|mask| Some((mask.object.upgrade()?, mask.bit, mask.parent_bits)))
+            .collect();
+        if masks.is_empty() {
+            return;
+        }
+
+        self.drawing_masks = true;
+        for (object, bit, parent_bits) in masks {
+            let screen_space = object.borrow().read_flags().contains(DrawFlags::SCREEN_SPACE);
+            self.set_viewport(screen_space);
+            self.set_stencil(parent_bits, bit, parent_bits | bit);
+            object.borrow().draw(self, builder);
+        }
+        self.drawing_masks = false;
+    }
+
+    /// Draw the sprites collected in a batch with a single instanced draw call
+    fn record_batch(
+        &mut self,
+        batch: &mut SpriteBatch,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+    ) {
+        let (descriptor_set, mask_bits, instances) = match batch.take() {
+            Some(content) => content,
+            None => return,
+        };
+
+        let instances = match self.instance_pool.chunk(instances) {
+            Ok(chunk) => chunk,
+            Err(e) => {
+                eprintln!("Couldn't allocate the instances of a sprite batch: {}", e);
+                return;
+            }
+        };
+
+        self.set_stencil(mask_bits, 0, mask_bits);
+        builder
+            .draw(
+                self.batch_pipeline.clone(),
+                &self.swapchain.dynamic_state,
+                (self.batch_quad.clone(), instances),
+                descriptor_set,
+                (),
+                vec![],
+            )
+            .expect("Couldn't add the batch Draw command to Vulkan Render Pass");
+        self.draw_stats.draw_calls += 1;
     }
 
-    /// Create a new SpriteObject
-    pub fn new_sprite(&mut self, texture_path: &str, z_index: u8) -> SpriteObject {
-        let sprite = Rc::new(RefCell::new(Sprite::new(texture_path, self, z_index)));
+    /// Descriptor set to draw a texture in sprite batches, shared by the sprites with the same texture,
+    /// sampler config and camera (which can then be drawn in the same batch)
+    pub fn get_batch_descriptor_set(
+        &self,
+        texture: Texture,
+        sampler_config: SamplerConfig,
+        global_buffer: Arc<GlobalUniformBuffer>,
+    ) -> Arc<BatchDescriptorSet> {
+        let sampler = self.create_texture_sampler_with_config(sampler_config);
+        let key = (
+            Arc::as_ptr(&texture) as *const () as usize,
+            Arc::as_ptr(&sampler) as usize,
+            Arc::as_ptr(&global_buffer) as usize,
+        );
 
-        self.append_draw_object(sprite.clone());
+        let mut sets = self.batch_sets.borrow_mut();
+        if let Some(set) = sets.get(&key).and_then(Weak::upgrade) {
+            return set;
+        }
+        sets.retain(|_, set| set.strong_count() > 0);
 
-        SpriteObject::new(sprite)
+        let layout = self
+            .batch_pipeline
+            .layout()
+            .descriptor_set_layout(0)
+            .expect("Couldn't use Descriptor Set Layout");
+        let set = Arc::new(
+            PersistentDescriptorSet::start(layout.clone())
+                .add_sampled_image(texture, sampler)
+                .expect("Couldn't add Sampled Image to the batch Descriptor Set")
+                .add_buffer(global_buffer)
+                .expect("Couldn't add the global buffer to the batch Descriptor Set")
+                .build()
+                .expect("Couldn't build the batch Descriptor Set"),
+        );
+        sets.insert(key, Arc::downgrade(&set));
+
+        set
     }
 
-    /// Create a new rectangular PrimitiveObject
-    pub fn new_rectangle(&mut self, scale: Vector2<f32>, color: Vector4<f32>, global_position: Vector2<f32>, z_index: u8) -> PrimitiveObject {
-        let primitive = Rc::new(RefCell::new(Primitive::rectangle(scale, color, global_position, self, z_index)));
+    /// Frames presented and skipped (with the reason) since startup, to compare with the frame time history
+    pub fn present_stats(&self) -> PresentStats {
+        self.present_stats
+    }
 
-        self.append_draw_object(primitive.clone());
+    /// Frames presented and skipped during the last complete second
+    pub fn present_stats_per_second(&self) -> PresentStats {
+        self.last_second_stats
+    }
 
-        PrimitiveObject::new(primitive)
+    /// Objects and draw calls of the last rendered frame, e.g. to check how well sprites are batched
+    pub fn get_draw_stats(&self) -> DrawStats {
+        self.draw_stats
     }
 
-    /// Append a new DrawObject to the draw_object vector for draw
-    fn append_draw_object(&mut self, obj: DrawObject<dyn Draw>) {
-        self.draw_objects.push(obj);
-        self.sort_draw_objects();
+    pub fn get_batching(&self) -> bool {
+        self.batching
+    }
+
+    /// Draw consecutive sprites sharing texture, sampler and camera in a single draw call (on by default)
+    /// Only the main pass is batched, render layers and masks draw every object on its own
+    pub fn set_batching(&mut self, batching: bool) {
+        self.batching = batching;
+        self.redraw_pending = true;
     }
 
     /// Create a new empty Immutable Descriptor Set
@@ -446,7 +2461,9 @@ impl GraphicsHandler {
         pipeline_name: &str,
         layout_number: usize,
     ) -> PersistentDescriptorSetBuilder<()> {
-        let pipeline = self.get_pipeline(pipeline_name);
+        let pipeline = self
+            .get_pipeline(pipeline_name)
+            .unwrap_or_else(|| panic!("No Vulkan Pipeline named \"{}\" was found", pipeline_name));
         let layout = pipeline
             .layout()
             .descriptor_set_layout(layout_number)
@@ -464,53 +2481,177 @@ impl GraphicsHandler {
         DescriptorSetWithImage<R>,
         Vector2<u32>,
     ) {
-        let decoder = png::Decoder::new(File::open(texture_path).unwrap());
-        let (info, mut reader) = decoder.read_info().unwrap();
+        let (texture, dimensions) = self.get_texture(texture_path);
+
+        (
+            self.bind_texture(desc_set_builder, texture, sampler),
+            dimensions,
+        )
+    }
+
+    /// Texture loaded from a file, shared with the other objects using it through the texture cache
+    pub fn get_texture(&self, texture_path: &str) -> (Texture, Vector2<u32>) {
+        self.try_get_texture(texture_path)
+            .unwrap_or_else(|e| panic!("Couldn't load texture \'{}\': {}", texture_path, e))
+    }
+
+    /// Like `get_texture`, returning the loading errors instead of panicking
+    pub fn try_get_texture(&self, texture_path: &str) -> Result<(Texture, Vector2<u32>), TextureError> {
+        let cached = self.texture_cache.borrow_mut().get(texture_path);
+        if let Some(cached) = cached {
+            return Ok(cached);
+        }
+
+        let (texture, dimensions) = self.load_texture(texture_path)?;
+        self.texture_cache.borrow_mut().insert(texture_path, texture.clone(), dimensions, 1);
+
+        Ok((texture, dimensions))
+    }
+
+    /// Estimated GPU memory (in bytes) of the cached textures
+    pub fn texture_memory_usage(&self) -> usize {
+        self.texture_cache.borrow().memory_usage()
+    }
+
+    /// Estimated GPU memory (in bytes) of the texture loaded from a file, if it's cached
+    pub fn texture_memory(&self, texture_path: &str) -> Option<usize> {
+        self.texture_cache.borrow().texture_memory(texture_path)
+    }
+
+    /// Whether `texture` is the one the texture cache shares for `texture_path`
+    pub fn is_texture_cached(&self, texture_path: &str, texture: &Texture) -> bool {
+        self.texture_cache.borrow().holds(texture_path, texture)
+    }
+
+    /// Stop sharing the texture loaded from a file, the next object using the file loads it again
+    /// Objects still using it keep it alive until they are dropped
+    pub fn evict_texture(&self, texture_path: &str) {
+        self.texture_cache.borrow_mut().remove(texture_path);
+    }
+
+    /// Stop sharing every texture loaded from a file, e.g. on a level transition
+    /// Textures still used by objects are only freed once those objects are gone
+    pub fn clear_texture_cache(&mut self) {
+        self.texture_cache.borrow_mut().clear();
+    }
+
+    pub fn get_texture_budget(&self) -> Option<usize> {
+        self.texture_cache.borrow().get_budget()
+    }
+
+    /// Limit the memory (in bytes) of the cached textures, evicting the least recently used ones
+    /// Textures still used by objects are only freed once those objects are gone
+    pub fn set_texture_budget(&mut self, budget: Option<usize>) {
+        self.texture_cache.borrow_mut().set_budget(budget);
+    }
+
+    /// Decode a PNG file and upload it to a new Texture, to bind it later with `bind_texture`
+    pub fn load_texture(&self, texture_path: &str) -> Result<(Texture, Vector2<u32>), TextureError> {
+        let (data, dimensions) = texture::decode_png(texture_path, self.max_texture_dimension())?;
+        let texture = self.upload_texture(data, dimensions)?;
+
+        Ok((texture, dimensions))
+    }
+
+    /// Largest width or height of a texture on this device
+    pub fn max_texture_dimension(&self) -> u32 {
+        self.limits.max_image_dimension_2d
+    }
 
-        let mut buf = vec![0; info.buffer_size()];
+    /// Upload RGBA pixels decoded from a file and add them to the texture cache,
+    /// so the sprites later created from `texture_path` don't load the file again
+    pub fn cache_texture_data(
+        &self,
+        texture_path: &str,
+        data: Vec<u8>,
+        dimensions: Vector2<u32>,
+    ) -> Result<(Texture, Vector2<u32>), TextureError> {
+        let cached = self.texture_cache.borrow_mut().get(texture_path);
+        if let Some(cached) = cached {
+            return Ok(cached);
+        }
+
+        let texture = self.upload_texture(data, dimensions)?;
+        self.texture_cache.borrow_mut().insert(texture_path, texture.clone(), dimensions, 1);
 
-        reader.next_frame(&mut buf).unwrap();
+        Ok((texture, dimensions))
+    }
+
+    /// Upload RGBA pixel data to a new Texture
+    pub fn upload_texture(&self, data: Vec<u8>, dimensions: Vector2<u32>) -> Result<Texture, TextureError> {
+        self.validate_texture_dimensions(dimensions)?;
 
-        let dimensions = ImageDimensions::Dim2d {
-            width: info.width,
-            height: info.height,
+        let image_dimensions = ImageDimensions::Dim2d {
+            width: dimensions.x,
+            height: dimensions.y,
             array_layers: 1,
         };
         let (image, future) = ImmutableImage::from_iter(
-            buf.iter().cloned(),
-            dimensions,
+            data.into_iter(),
+            image_dimensions,
             MipmapsCount::One,
             Format::R8G8B8A8Srgb,
             self.get_queue(),
-        )
-        .unwrap();
+        )?;
 
-        let (texture, _tex_future) = (ImageView::new(image).unwrap(), future);
+        let (texture, _tex_future): (Texture, _) = (ImageView::new(image)?, future);
 
-        (
-            desc_set_builder
-                .add_sampled_image(texture, sampler)
-                .expect("Couldn't add Sampled Image to Descriptor Set"),
-            Vector2::new(info.width, info.height),
-        )
+        Ok(texture)
+    }
+
+    /// Bind an already uploaded Texture to a new Immutable Descriptor Set
+    pub fn bind_texture<R>(
+        &self,
+        desc_set_builder: PersistentDescriptorSetBuilder<R>,
+        texture: Texture,
+        sampler: Arc<Sampler>,
+    ) -> DescriptorSetWithImage<R> {
+        desc_set_builder
+            .add_sampled_image(texture, sampler)
+            .expect("Couldn't add Sampled Image to Descriptor Set")
+    }
+
+    pub fn get_sampler_config(&self) -> SamplerConfig {
+        self.sampler_config
+    }
+
+    /// Set the sampling options of the objects created from now on
+    pub fn set_sampler_config(&mut self, config: SamplerConfig) {
+        self.sampler_config = config;
     }
 
-    /// Create a Texture Sampler to bind Textures to
+    /// Create a Texture Sampler to bind Textures to, with the current sampler config
     pub fn create_texture_sampler(&self) -> Arc<Sampler> {
-        Sampler::new(
+        self.create_texture_sampler_with_config(self.sampler_config)
+    }
+
+    /// Texture Sampler with the given config, only created the first time a config is used
+    pub fn create_texture_sampler_with_config(&self, config: SamplerConfig) -> Arc<Sampler> {
+        let mut samplers = self.samplers.borrow_mut();
+        if let Some((_, sampler)) = samplers.iter().find(|(c, _)| *c == config) {
+            return sampler.clone();
+        }
+
+        let filter = config.vulkan_filter();
+        let address_mode = config.vulkan_address_mode();
+
+        let sampler = Sampler::new(
             self.get_device(),
-            Filter::Linear,
-            Filter::Linear,
+            filter,
+            filter,
             MipmapMode::Nearest,
-            SamplerAddressMode::Repeat,
-            SamplerAddressMode::Repeat,
-            SamplerAddressMode::Repeat,
-            0.0,
+            address_mode,
+            address_mode,
+            address_mode,
+            config.lod_bias,
             1.0,
-            0.0,
-            0.0,
+            config.lod_range.0,
+            config.lod_range.1,
         )
-        .expect("Couldn't create Vulkan Texture Sampler")
+        .expect("Couldn't create Vulkan Texture Sampler");
+
+        samplers.push((config, sampler.clone()));
+        sampler
     }
 }
 
@@ -519,62 +2660,174 @@ pub struct SwapchainHandler {
     chain: Arc<Swapchain<Sendable<Rc<WindowContext>>>>,
     images: Vec<Arc<SwapchainImage<Sendable<Rc<WindowContext>>>>>,
     framebuffers: Vec<Arc<dyn FramebufferAbstract + Send + Sync>>,
+    /// Color space the images are presented in, kept when recreating
+    color_space: ColorSpace,
     must_recreate: bool,
     dynamic_state: Box<DynamicState>,
+    /// Format of the stencil attachment of the framebuffers
+    stencil_format: Format,
+    /// Whether the images can be copied from (needed for frame captures)
+    capturable: bool,
+    /// Minimum and maximum image count supported by the surface
+    image_count_limits: (u32, Option<u32>),
+    /// Image count to use at the next recreation, if it must change
+    new_image_count: Option<u32>,
+    /// Present mode to use at the next recreation, if it must change
+    new_present_mode: Option<PresentMode>,
+    /// Frames left before recreating after a resize, reset by every new resize event
+    resize_debounce: u32,
+    /// Recreations in a row that still gave a suboptimal swapchain
+    suboptimal_recreations: u32,
+    /// Whether the swapchain was recreated during the last frame
+    recreated: bool,
 }
 
+/// Frames without resize events before the swapchain follows the new window size
+const RESIZE_SETTLE_FRAMES: u32 = 2;
+
+/// Tries of `render_once` to get an image from the swapchain, recreating it in between
+const RENDER_ONCE_ATTEMPTS: u32 = 3;
+
+/// Recreations tried before keeping a swapchain that stays suboptimal
+const MAX_SUBOPTIMAL_RECREATIONS: u32 = 3;
+
 impl SwapchainHandler {
     fn new(
         swapchain: Arc<Swapchain<Sendable<Rc<WindowContext>>>>,
         images: Vec<Arc<SwapchainImage<Sendable<Rc<WindowContext>>>>>,
+        color_space: ColorSpace,
         render_pass: Arc<RenderPass>,
+        stencil_format: Format,
+        capturable: bool,
+        image_count_limits: (u32, Option<u32>),
     ) -> Self {
         let mut dynamic_state = Box::new(DynamicState {
             line_width: None,
             viewports: None,
             scissors: None,
-            compare_mask: None,
-            write_mask: None,
-            reference: None,
+            compare_mask: Some(stencil_value(0)),
+            write_mask: Some(stencil_value(0)),
+            reference: Some(stencil_value(0)),
         });
 
-        let framebuffers =
-            window_size_dependent_setup(&images[..], render_pass, dynamic_state.as_mut());
+        let framebuffers = window_size_dependent_setup(
+            &images[..],
+            render_pass,
+            stencil_format,
+            dynamic_state.as_mut(),
+        );
 
         Self {
             chain: swapchain,
             images,
             framebuffers,
+            color_space,
             must_recreate: false,
             dynamic_state,
+            stencil_format,
+            capturable,
+            image_count_limits,
+            new_image_count: None,
+            new_present_mode: None,
+            resize_debounce: 0,
+            suboptimal_recreations: 0,
+            recreated: false,
         }
     }
 
     fn check_and_recreate(&mut self, window: &Window, pass: Arc<RenderPass>) -> Result<(), ()> {
+        // Keep the current images while the window is still being resized
+        if self.resize_debounce > 0 {
+            self.resize_debounce -= 1;
+            return Ok(());
+        }
+
         if self.must_recreate {
             let dimensions: [u32; 2] = {
                 let size = window.size();
                 [size.0, size.1]
             };
 
-            let (new_swapchain, new_images) =
-                match self.chain.recreate().dimensions(dimensions).build() {
-                    Ok(r) => r,
-                    Err(SwapchainCreationError::UnsupportedDimensions) => return Err(()),
-                    Err(e) => panic!("Failed to recreate swapchain: {:?}", e),
-                };
+            let mut builder = self.chain.recreate().dimensions(dimensions);
+            if let Some(count) = self.new_image_count {
+                builder = builder.num_images(count);
+            }
+            if let Some(mode) = self.new_present_mode {
+                builder = builder.present_mode(mode);
+            }
+
+            let (new_swapchain, new_images) = match builder.build() {
+                Ok(r) => r,
+                Err(SwapchainCreationError::UnsupportedDimensions) => return Err(()),
+                Err(e) => panic!("Failed to recreate swapchain: {:?}", e),
+            };
 
             self.chain = new_swapchain;
+            self.new_image_count = None;
+            self.new_present_mode = None;
             self.images = new_images;
 
-            let framebuffers =
-                window_size_dependent_setup(&self.images[..], pass, &mut self.dynamic_state);
+            let framebuffers = window_size_dependent_setup(
+                &self.images[..],
+                pass,
+                self.stencil_format,
+                &mut self.dynamic_state,
+            );
             self.framebuffers = framebuffers;
             self.must_recreate = false;
+            self.recreated = true;
         }
         Ok(())
     }
 
+    /// Recreate the swapchain once the window stops being resized for `RESIZE_SETTLE_FRAMES` frames
+    fn notify_resize(&mut self) {
+        self.must_recreate = true;
+        self.resize_debounce = RESIZE_SETTLE_FRAMES;
+        self.suboptimal_recreations = 0;
+    }
+
+    /// Recreate the swapchain after acquiring a suboptimal image, returns whether it will be recreated
+    /// Some surfaces are still suboptimal after a recreation (e.g. a HiDPI scale mismatch), so after
+    /// `MAX_SUBOPTIMAL_RECREATIONS` tries the swapchain is kept instead of being rebuilt every frame
+    fn notify_suboptimal(&mut self, suboptimal: bool) -> bool {
+        if !suboptimal {
+            self.suboptimal_recreations = 0;
+            return false;
+        }
+
+        // a pending recreation (e.g. waiting for a resize to settle) must not be cancelled or counted twice
+        if self.must_recreate {
+            return true;
+        }
+        if self.suboptimal_recreations >= MAX_SUBOPTIMAL_RECREATIONS {
+            return false;
+        }
+
+        self.suboptimal_recreations += 1;
+        self.must_recreate = true;
+        true
+    }
+
+    /// Ask for a new image count, applied by recreating the swapchain
+    fn set_image_count(&mut self, buffering: BufferCount) {
+        let (min_count, max_count) = self.image_count_limits;
+        let count = clamp_image_count(buffering, min_count, max_count);
+
+        if count != self.chain.num_images() {
+            self.new_image_count = Some(count);
+            self.must_recreate = true;
+        }
+    }
+
+    /// Present mode must already be supported by the surface
+    fn set_present_mode(&mut self, mode: PresentMode) {
+        if mode != self.chain.present_mode() {
+            self.new_present_mode = Some(mode);
+            self.must_recreate = true;
+        }
+    }
+
     fn get_recreate(&self) -> bool {
         self.must_recreate
     }
@@ -588,6 +2841,198 @@ impl SwapchainHandler {
     }
 }
 
+/// Error while setting up the renderer, on machines that can't run it
+#[derive(Debug)]
+pub enum GraphicsInitError {
+    /// The Vulkan instance couldn't be created, e.g. no Vulkan driver is installed
+    Instance(String),
+    /// SDL couldn't create a Vulkan surface for the window
+    Surface(String),
+    /// No GPU supports Vulkan
+    NoPhysicalDevice,
+    /// No GPU has a queue able to draw to the window
+    NoGraphicsQueue,
+    Device(DeviceCreationError),
+    /// The capabilities of the window's surface couldn't be queried
+    SurfaceCapabilities(CapabilitiesError),
+    Swapchain(SwapchainCreationError),
+    /// No stencil format can be used as an attachment, so masks can't be drawn
+    NoStencilFormat,
+    RenderPass(RenderPassCreationError),
+    /// A shader couldn't be loaded or a pipeline couldn't be built, with the name of the pipeline
+    Pipeline(String),
+}
+
+impl fmt::Display for GraphicsInitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GraphicsInitError::Instance(e) => write!(f, "couldn't create the Vulkan instance: {}", e),
+            GraphicsInitError::Surface(e) => write!(f, "couldn't create the Vulkan surface of the window: {}", e),
+            GraphicsInitError::NoPhysicalDevice => write!(f, "no GPU supporting Vulkan was found"),
+            GraphicsInitError::NoGraphicsQueue => write!(f, "no GPU can draw to the window"),
+            GraphicsInitError::Device(e) => write!(f, "couldn't create the Vulkan device: {}", e),
+            GraphicsInitError::SurfaceCapabilities(e) => {
+                write!(f, "couldn't query the capabilities of the window surface: {}", e)
+            }
+            GraphicsInitError::Swapchain(e) => write!(f, "couldn't create the swapchain: {}", e),
+            GraphicsInitError::NoStencilFormat => write!(f, "no supported stencil format was found"),
+            GraphicsInitError::RenderPass(e) => write!(f, "couldn't create the render pass: {}", e),
+            GraphicsInitError::Pipeline(e) => write!(f, "couldn't create a graphics pipeline: {}", e),
+        }
+    }
+}
+
+impl Error for GraphicsInitError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            GraphicsInitError::Device(e) => Some(e),
+            GraphicsInitError::SurfaceCapabilities(e) => Some(e),
+            GraphicsInitError::Swapchain(e) => Some(e),
+            GraphicsInitError::RenderPass(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<DeviceCreationError> for GraphicsInitError {
+    fn from(e: DeviceCreationError) -> Self {
+        GraphicsInitError::Device(e)
+    }
+}
+
+impl From<CapabilitiesError> for GraphicsInitError {
+    fn from(e: CapabilitiesError) -> Self {
+        GraphicsInitError::SurfaceCapabilities(e)
+    }
+}
+
+impl From<SwapchainCreationError> for GraphicsInitError {
+    fn from(e: SwapchainCreationError) -> Self {
+        GraphicsInitError::Swapchain(e)
+    }
+}
+
+impl From<RenderPassCreationError> for GraphicsInitError {
+    fn from(e: RenderPassCreationError) -> Self {
+        GraphicsInitError::RenderPass(e)
+    }
+}
+
+/// Error during the creation of a vertex or index buffer
+#[derive(Debug)]
+pub enum BufferCreationError {
+    /// Not enough memory for the buffer
+    Alloc(DeviceMemoryAllocError),
+    /// The upload of the data to the buffer failed
+    Flush(FlushError),
+}
+
+impl fmt::Display for BufferCreationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BufferCreationError::Alloc(e) => write!(f, "couldn't allocate the buffer: {}", e),
+            BufferCreationError::Flush(e) => write!(f, "couldn't upload the buffer data: {}", e),
+        }
+    }
+}
+
+impl Error for BufferCreationError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            BufferCreationError::Alloc(e) => Some(e),
+            BufferCreationError::Flush(e) => Some(e),
+        }
+    }
+}
+
+impl From<DeviceMemoryAllocError> for BufferCreationError {
+    fn from(e: DeviceMemoryAllocError) -> Self {
+        BufferCreationError::Alloc(e)
+    }
+}
+
+impl From<FlushError> for BufferCreationError {
+    fn from(e: FlushError) -> Self {
+        BufferCreationError::Flush(e)
+    }
+}
+
+/// Error while loading or uploading a texture
+#[derive(Debug)]
+pub enum TextureError {
+    /// The file couldn't be read
+    Io(io::Error),
+    /// The file isn't a valid PNG
+    Decode(png::DecodingError),
+    /// The pixels can't be converted to 8 bit RGBA
+    UnsupportedFormat(png::ColorType, png::BitDepth),
+    /// The texture is bigger than the device supports
+    TooLarge { dimensions: Vector2<u32>, limit: u32 },
+    /// The animated PNG has no frames to show
+    NoFrames,
+    /// The GPU image couldn't be created
+    Image(ImageCreationError),
+    View(ImageViewCreationError),
+}
+
+impl fmt::Display for TextureError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TextureError::Io(e) => write!(f, "couldn't read the file: {}", e),
+            TextureError::Decode(e) => write!(f, "couldn't decode the PNG: {}", e),
+            TextureError::UnsupportedFormat(color_type, bit_depth) => write!(
+                f,
+                "unsupported pixel format {:?} with {} bits per channel",
+                color_type, *bit_depth as u8
+            ),
+            TextureError::TooLarge { dimensions, limit } => write!(
+                f,
+                "texture of {}x{} exceeds the device's maximum image dimension of {}",
+                dimensions.x, dimensions.y, limit
+            ),
+            TextureError::NoFrames => write!(f, "the animation has no frames"),
+            TextureError::Image(e) => write!(f, "couldn't create the image: {}", e),
+            TextureError::View(e) => write!(f, "couldn't create the image view: {}", e),
+        }
+    }
+}
+
+impl Error for TextureError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            TextureError::Io(e) => Some(e),
+            TextureError::Decode(e) => Some(e),
+            TextureError::Image(e) => Some(e),
+            TextureError::View(e) => Some(e),
+            TextureError::UnsupportedFormat(..) | TextureError::TooLarge { .. } | TextureError::NoFrames => None,
+        }
+    }
+}
+
+impl From<io::Error> for TextureError {
+    fn from(e: io::Error) -> Self {
+        TextureError::Io(e)
+    }
+}
+
+impl From<png::DecodingError> for TextureError {
+    fn from(e: png::DecodingError) -> Self {
+        TextureError::Decode(e)
+    }
+}
+
+impl From<ImageCreationError> for TextureError {
+    fn from(e: ImageCreationError) -> Self {
+        TextureError::Image(e)
+    }
+}
+
+impl From<ImageViewCreationError> for TextureError {
+    fn from(e: ImageViewCreationError) -> Self {
+        TextureError::View(e)
+    }
+}
+
 /// Struct to hold vertex data
 #[derive(Default, Copy, Clone)]
 pub struct Vertex {
@@ -606,27 +3051,48 @@ impl From<Vec<Vertex>> for VertexArray {
     }
 }
 
+/// Index buffer holding either `u16` or `u32` indices
+/// `u16` halves the memory used, `u32` is needed for meshes with more than 65536 vertices
+#[derive(Clone)]
+pub enum IndexBuffer {
+    U16(Arc<dyn TypedBufferAccess<Content = [u16]> + Send + Sync>),
+    U32(Arc<dyn TypedBufferAccess<Content = [u32]> + Send + Sync>),
+}
+
+impl IndexBuffer {
+    /// Number of indices in the buffer
+    pub fn len(&self) -> usize {
+        match self {
+            IndexBuffer::U16(buffer) => buffer.len(),
+            IndexBuffer::U32(buffer) => buffer.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
 /// Struct to hold a vertex buffer with data
 #[derive(Clone)]
 pub struct VertexBuffer {
     buffer: Arc<ImmutableBuffer<[Vertex]>>,
-    indices: Arc<dyn TypedBufferAccess<Content = [u16]> + Send + Sync>,
+    indices: IndexBuffer,
 }
 
 impl VertexBuffer {
     pub fn new(
         handler: &GraphicsHandler,
         array: VertexArray,
-        indices: Arc<dyn TypedBufferAccess<Content = [u16]> + Send + Sync>,
-    ) -> Result<Self, DeviceMemoryAllocError> {
+        indices: IndexBuffer,
+    ) -> Result<Self, BufferCreationError> {
         let (buffer, future) = ImmutableBuffer::from_iter(
             array.data.iter().cloned(),
             BufferUsage::vertex_buffer(),
             handler.queue.clone(),
-        )
-        .unwrap();
+        )?;
 
-        future.flush().unwrap();
+        future.flush()?;
 
         Ok(Self { buffer, indices })
     }
@@ -635,7 +3101,7 @@ impl VertexBuffer {
         self.buffer.clone()
     }
 
-    pub fn get_indices(&self) -> Arc<dyn TypedBufferAccess<Content = [u16]> + Send + Sync> {
+    pub fn get_indices(&self) -> IndexBuffer {
         self.indices.clone()
     }
 }
@@ -645,10 +3111,16 @@ impl VertexBuffer {
 fn window_size_dependent_setup(
     images: &[Arc<SwapchainImage<Sendable<Rc<WindowContext>>>>],
     render_pass: Arc<RenderPass>,
+    stencil_format: Format,
     dynamic_state: &mut DynamicState,
 ) -> Vec<Arc<dyn FramebufferAbstract + Send + Sync>> {
     let dimensions = images[0].dimensions();
 
+    // frames are rendered one after the other, so they can all share the same stencil
+    let stencil = AttachmentImage::transient(render_pass.device().clone(), dimensions, stencil_format)
+        .expect("Couldn't create stencil image on window resize/init");
+    let stencil = ImageView::new(stencil).expect("Couldn't create stencil Image View on window resize/init");
+
     let viewport = Viewport {
         origin: [0.0, 0.0],
         dimensions: [dimensions[0] as f32, dimensions[1] as f32],
@@ -664,6 +3136,8 @@ fn window_size_dependent_setup(
                 Framebuffer::start(render_pass.clone())
                     .add(view)
                     .expect("Couldn't add Image View on Framebuffer creation")
+                    .add(stencil.clone())
+                    .expect("Couldn't add stencil Image View on Framebuffer creation")
                     .build()
                     .expect("Couldn't build Framebuffer on window resize"),
             ) as Arc<dyn FramebufferAbstract + Send + Sync>
@@ -671,49 +3145,75 @@ fn window_size_dependent_setup(
         .collect::<Vec<_>>()
 }
 
-fn create_instance() -> Arc<Instance> {
+/// Smooth 1D noise in the range -1..1, with a new random value at every integer step of `t`
+fn value_noise(seed: u32, t: f32) -> f32 {
+    fn hash(seed: u32, n: i32) -> f32 {
+        let mut x = (n as u32).wrapping_mul(0x27d4_eb2d) ^ seed;
+        x ^= x >> 15;
+        x = x.wrapping_mul(0x85eb_ca6b);
+        x ^= x >> 13;
+        (x as f32 / u32::MAX as f32) * 2.0 - 1.0
+    }
+
+    let step = t.floor();
+    let fraction = t - step;
+    let smooth = fraction * fraction * (3.0 - 2.0 * fraction);
+
+    let a = hash(seed, step as i32);
+    let b = hash(seed, step as i32 + 1);
+    a + (b - a) * smooth
+}
+
+fn create_instance() -> Result<Arc<Instance>, GraphicsInitError> {
     let instance_extensions = InstanceExtensions::supported_by_core()
-        .expect("Couldn't obtain Vulkan Instance Extensions");
+        .map_err(|e| GraphicsInitError::Instance(e.to_string()))?;
 
     Instance::new(None, Version::V1_2, &instance_extensions, None)
-        .expect("Couldn't create a new Vulkan instance")
+        .map_err(|e| GraphicsInitError::Instance(e.to_string()))
 }
 
 fn create_surface(
     instance: Arc<Instance>,
     window: &Window,
-) -> Arc<Surface<Sendable<Rc<WindowContext>>>> {
+) -> Result<Arc<Surface<Sendable<Rc<WindowContext>>>>, GraphicsInitError> {
     let surface_handle = window
         .vulkan_create_surface(instance.internal_object().as_raw().try_into().unwrap())
-        .expect("Couldn't create a new surface from the Vulkan Instance");
+        .map_err(GraphicsInitError::Surface)?;
     // Use the SDL2 surface from the Window as surface
     unsafe {
-        Arc::new(Surface::from_raw_surface(
+        Ok(Arc::new(Surface::from_raw_surface(
             instance,
             ash::vk::SurfaceKHR::from_raw(surface_handle),
             Sendable::new(window.context()),
-        ))
+        )))
     }
 }
 
 fn get_device(
     instance: &'_ Arc<Instance>,
     surface: Arc<Surface<Sendable<Rc<WindowContext>>>>,
-) -> (PhysicalDevice<'_>, Arc<Device>, Arc<Queue>) {
+    preference: DevicePreference,
+) -> Result<(PhysicalDevice<'_>, Arc<Device>, Arc<Queue>), GraphicsInitError> {
+    if PhysicalDevice::enumerate(&instance).next().is_none() {
+        return Err(GraphicsInitError::NoPhysicalDevice);
+    }
+
     let (physical_device, queue_family) = PhysicalDevice::enumerate(&instance)
         .filter_map(|p| {
             p.queue_families()
                 .find(|&q| q.supports_graphics() && surface.is_supported(q).unwrap_or(false))
                 .map(|q| (p, q))
         })
-        .min_by_key(|(p, _)| match p.properties().device_type.unwrap() {
-            PhysicalDeviceType::DiscreteGpu => 0,
-            PhysicalDeviceType::IntegratedGpu => 1,
-            PhysicalDeviceType::VirtualGpu => 2,
-            PhysicalDeviceType::Cpu => 3,
-            PhysicalDeviceType::Other => 4,
+        .min_by_key(|(p, _)| match (p.properties().device_type.unwrap(), preference) {
+            (PhysicalDeviceType::DiscreteGpu, DevicePreference::HighPerformance) => 0,
+            (PhysicalDeviceType::IntegratedGpu, DevicePreference::HighPerformance) => 1,
+            (PhysicalDeviceType::IntegratedGpu, DevicePreference::LowPower) => 0,
+            (PhysicalDeviceType::DiscreteGpu, DevicePreference::LowPower) => 1,
+            (PhysicalDeviceType::VirtualGpu, _) => 2,
+            (PhysicalDeviceType::Cpu, _) => 3,
+            (PhysicalDeviceType::Other, _) => 4,
         })
-        .unwrap();
+        .ok_or(GraphicsInitError::NoGraphicsQueue)?;
 
     let device_ext = DeviceExtensions {
         khr_swapchain: true,
@@ -724,14 +3224,13 @@ fn get_device(
         physical_device.supported_features(),
         &device_ext,
         [(queue_family, 0.5)].iter().cloned(),
-    )
-    .expect("Couldn't create Vulkan Device");
+    )?;
 
-    (
+    Ok((
         physical_device,
         device,
         queues.next().expect("Couldn't get first queue object"),
-    )
+    ))
 }
 
 type SdlSwapchain = Arc<Swapchain<Sendable<Rc<WindowContext>>>>;
@@ -742,31 +3241,230 @@ fn create_raw_swapchain(
     device: Arc<Device>,
     surface: Arc<Surface<Sendable<Rc<WindowContext>>>>,
     physical: PhysicalDevice,
-) -> (
-    SdlSwapchain,
-    SdlSwapchainImagesVector,
-) {
+    buffering: BufferCount,
+    present_mode: PresentMode,
+) -> Result<(SdlSwapchain, SdlSwapchainImagesVector, ColorSpace, bool, (u32, Option<u32>)), GraphicsInitError> {
     // Get all the device capabilities and limitations
-    let caps = surface
-        .capabilities(physical)
-        .expect("Couldn't obtain Vulkan Capabilities from Physical Device");
+    let caps = surface.capabilities(physical)?;
     let alpha = caps.supported_composite_alpha.iter().next().unwrap();
-    let format = caps.supported_formats[0].0;
+    let (format, color_space) = choose_surface_format(&caps.supported_formats);
+
+    let requested_mode = present_mode;
+    let present_mode = choose_present_mode(requested_mode, caps.present_modes);
+    if present_mode != requested_mode {
+        eprintln!(
+            "Present mode {:?} isn't supported, using {:?} instead",
+            requested_mode, present_mode
+        );
+    }
 
-    let buffers_count = match caps.max_image_count {
-        None => max(2, caps.min_image_count),
-        Some(limit) => min(max(2, caps.min_image_count), limit),
+    let buffers_count = clamp_image_count(buffering, caps.min_image_count, caps.max_image_count);
+    // Allow copying from the images when supported, to read back rendered frames
+    let capturable = caps.supported_usage_flags.transfer_source;
+    let usage = ImageUsage {
+        transfer_source: capturable,
+        ..ImageUsage::color_attachment()
     };
+
     let dimensions: [u32; 2] = {
         let size = window.size();
         [size.0, size.1]
     };
-    Swapchain::start(device, surface)
+    let (swapchain, images) = Swapchain::start(device, surface)
         .dimensions(dimensions)
-        .usage(ImageUsage::color_attachment())
+        .usage(usage)
         .format(format)
+        .color_space(color_space)
         .composite_alpha(alpha)
+        .present_mode(present_mode)
         .num_images(buffers_count)
-        .build()
-        .expect("Couldn't build Vulkan Swapchain")
+        .build()?;
+
+    Ok((
+        swapchain,
+        images,
+        color_space,
+        capturable,
+        (caps.min_image_count, caps.max_image_count),
+    ))
+}
+
+/// Present mode asked by a config, an explicit one or the one matching its vsync
+fn requested_present_mode(config: &GraphicsConfig) -> PresentMode {
+    match config.present_mode {
+        Some(mode) => mode,
+        None if config.vsync => PresentMode::Fifo,
+        None => PresentMode::Mailbox,
+    }
+}
+
+/// Closest supported match of a present mode, keeping its intent:
+/// low latency modes go Mailbox -> Immediate -> FIFO, vsync modes fall back to FIFO
+/// FIFO is the only mode every driver has to support
+fn choose_present_mode(requested: PresentMode, supported: SupportedPresentModes) -> PresentMode {
+    let fallbacks: &[PresentMode] = match requested {
+        PresentMode::Mailbox => &[PresentMode::Mailbox, PresentMode::Immediate],
+        PresentMode::Immediate => &[PresentMode::Immediate, PresentMode::Mailbox],
+        PresentMode::Relaxed => &[PresentMode::Relaxed],
+        _ => &[],
+    };
+
+    fallbacks
+        .iter()
+        .copied()
+        .find(|&mode| supported.supports(mode))
+        .unwrap_or(PresentMode::Fifo)
+}
+
+/// Pick an 8 bit sRGB format in the standard sRGB color space, so blending and textures get the right gamma
+/// Surfaces without one (or with only HDR formats) fall back to the first format listed by the driver
+fn choose_surface_format(formats: &[(Format, ColorSpace)]) -> (Format, ColorSpace) {
+    formats
+        .iter()
+        .copied()
+        .find(|&(format, color_space)| {
+            is_srgb_format(format) && color_space == ColorSpace::SrgbNonLinear
+        })
+        .unwrap_or(formats[0])
+}
+
+/// Whether writes to a format are encoded to sRGB by the hardware
+fn is_srgb_format(format: Format) -> bool {
+    matches!(
+        format,
+        Format::B8G8R8A8Srgb | Format::R8G8B8A8Srgb | Format::A8B8G8R8SrgbPack32
+    )
+}
+
+/// Stencil format for the masks, the first ones are guaranteed to be supported as attachments
+fn choose_stencil_format(physical: PhysicalDevice) -> Option<Format> {
+    [
+        Format::D24Unorm_S8Uint,
+        Format::D32Sfloat_S8Uint,
+        Format::D16Unorm_S8Uint,
+    ]
+    .iter()
+    .copied()
+    .find(|format| {
+        format
+            .properties(physical)
+            .optimal_tiling_features
+            .depth_stencil_attachment
+    })
+}
+
+/// Stencil test of the object pipelines, with masks and reference set for each draw:
+/// objects keep the stencil as is, masks replace their own bit
+fn stencil_state(mask: bool) -> DepthStencil {
+    let stencil = Stencil {
+        compare: Compare::Equal,
+        pass_op: if mask { StencilOp::Replace } else { StencilOp::Keep },
+        fail_op: StencilOp::Keep,
+        depth_fail_op: StencilOp::Keep,
+        compare_mask: None,
+        write_mask: None,
+        reference: None,
+    };
+
+    DepthStencil {
+        stencil_front: stencil,
+        stencil_back: stencil,
+        ..DepthStencil::disabled()
+    }
+}
+
+/// Blending of the mask pipelines, which only write to the stencil
+fn mask_blend() -> AttachmentBlend {
+    AttachmentBlend {
+        mask_red: false,
+        mask_green: false,
+        mask_blue: false,
+        mask_alpha: false,
+        ..AttachmentBlend::pass_through()
+    }
+}
+
+fn stencil_value(value: u32) -> DynamicStencilValue {
+    DynamicStencilValue {
+        face: StencilFaceFlags::StencilFrontAndBack,
+        value,
+    }
+}
+
+/// Load a pipeline cache saved by a previous run, or start from an empty one
+fn load_pipeline_cache(device: Arc<Device>, path: Option<&Path>) -> Arc<PipelineCache> {
+    if let Some(data) = path.and_then(|p| std::fs::read(p).ok()) {
+        // the driver checks the header of the data and ignores caches made by other devices or drivers
+        match unsafe { PipelineCache::with_data(device.clone(), &data) } {
+            Ok(cache) => return cache,
+            Err(e) => eprintln!("Couldn't use the saved pipeline cache: {}", e),
+        }
+    }
+
+    PipelineCache::empty(device).expect("Couldn't create Vulkan Pipeline Cache")
+}
+
+/// Write the pipeline cache to disk for the next runs
+fn save_pipeline_cache(cache: &PipelineCache, path: &Path) {
+    let result = cache
+        .get_data()
+        .map_err(|e| e.to_string())
+        .and_then(|data| std::fs::write(path, data).map_err(|e| e.to_string()));
+
+    if let Err(e) = result {
+        eprintln!("Couldn't save the pipeline cache to \'{}\': {}", path.display(), e);
+    }
+}
+
+/// Whether a tag entry points to the given object
+fn same_object(tagged: &Weak<RefCell<dyn Draw>>, object: &DrawObject<dyn Draw>) -> bool {
+    // compare the addresses only, vtable pointers of the same type may differ
+    tagged.as_ptr() as *const () == Rc::as_ptr(object) as *const ()
+}
+
+/// Fit the requested buffering in the image count limits of a surface
+fn clamp_image_count(buffering: BufferCount, min_count: u32, max_count: Option<u32>) -> u32 {
+    let count = max(buffering as u32, min_count);
+
+    match max_count {
+        None => count,
+        Some(limit) => min(count, limit),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::ctx::video::VideoHandler;
+
+    #[test]
+    #[ignore = "needs a display and a Vulkan device"]
+    fn restored_snapshot_matches_the_original() {
+        let sdl = sdl2::init().unwrap();
+        let mut video = VideoHandler::new(&sdl).unwrap();
+        let gl_handler = &mut video.gl_handler;
+
+        let _rust = gl_handler.new_sprite("assets/rust.png", 2);
+        let python = gl_handler.new_sprite_with_data_mode("assets/python.png", 1, SpriteDataMode::PushConstants);
+        python.update(|sprite| {
+            sprite.color = Vector4::new(1.0, 0.5, 0.25, 0.75);
+            sprite.global_position = Vector2::new(-30.0, 12.5);
+            sprite.scale = Vector2::new(0.5, 2.0);
+            sprite.set_source_rect(Vector4::new(1, 2, 3, 4));
+            sprite.set_pixel_snap(true);
+            sprite.set_flip(true, true);
+            sprite.set_visible(false);
+        });
+
+        let snapshot = gl_handler.snapshot();
+        let _restored = gl_handler.restore(&snapshot);
+
+        // the copies are sorted right after their originals, the z indices being different
+        let expected: Vec<SpriteState> = snapshot
+            .iter()
+            .flat_map(|state| vec![state.clone(), state.clone()])
+            .collect();
+        assert_eq!(snapshot.len(), 2);
+        assert_eq!(gl_handler.snapshot(), expected);
+    }
 }
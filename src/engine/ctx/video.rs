@@ -1,45 +1,129 @@
 // standard imports
+use std::sync::Arc;
 
 // SDL2 imports
-use sdl2::video::Window;
+use sdl2::sys::SDL_WindowFlags;
+#[cfg(feature = "always_on_top")]
+use sdl2::sys::{SDL_Window, SDL_bool};
+use sdl2::mouse::{Cursor, SystemCursor};
+use sdl2::pixels::PixelFormatEnum;
+use sdl2::rect::Rect;
+use sdl2::surface::Surface;
+use sdl2::keyboard::TextInputUtil;
+use sdl2::video::{DisplayMode, FullscreenType, Window};
 use sdl2::{Sdl, VideoSubsystem};
 
 // vulkan implementation imports
-use super::vulkan::GraphicsHandler;
+use super::render::text::BitmapFont;
+use super::render::texture::decode_png;
+use super::vulkan::{
+    Background, Camera, DrawStats, GraphicsConfig, GraphicsHandler, GraphicsInitError, PresentStats,
+    RedrawMode, RenderTarget, Rotation, Texture, TextureError, TextureFilter,
+};
 
 // other imports
-use super::draw_objects::{SpriteObject, PrimitiveObject};
+use super::draw_objects::{
+    AnimatedSpriteObject, Draw, DrawObject, GraphicObject, PrimitiveObject, SpriteDataMode,
+    SpriteObject, SpriteState, TextObject,
+};
+use super::physics::{Bodies, Body};
 use cgmath::{Vector2, Vector4};
 
+#[cfg(feature = "always_on_top")]
+extern "C" {
+    // not bound by sdl2-sys yet, needs SDL 2.0.16 or newer
+    fn SDL_SetWindowAlwaysOnTop(window: *mut SDL_Window, on_top: SDL_bool);
+}
+
+/// Options used to create the window
+#[derive(Clone, Debug)]
+pub struct WindowConfig {
+    pub title: String,
+    /// Size of the window (in pixels)
+    pub size: (u32, u32),
+    pub resizable: bool,
+    /// Start in exclusive fullscreen, at the desktop resolution
+    pub fullscreen: bool,
+    /// Keep the window above all the others, even when it isn't focused
+    pub always_on_top: bool,
+}
+
+impl Default for WindowConfig {
+    fn default() -> Self {
+        Self {
+            title: String::from("Rust Testing Grounds"),
+            size: (800, 600),
+            resizable: true,
+            fullscreen: false,
+            always_on_top: false,
+        }
+    }
+}
+
+/// Description of a connected display
+#[derive(Clone, Debug)]
+pub struct DisplayInfo {
+    pub index: i32,
+    pub name: String,
+    /// Position and size (in pixels) of the display in the desktop
+    pub bounds: Rect,
+    /// Diagonal, horizontal and vertical DPI, `None` if the platform doesn't report them
+    pub dpi: Option<(f32, f32, f32)>,
+}
+
 /// Component of the CtxHandler to handle all calls to graphic APIs
 pub struct VideoHandler {
     video_subsystem: VideoSubsystem,
     window: Window,
     pub gl_handler: GraphicsHandler,
+    /// Cursor set by the game, SDL only keeps a pointer to it while it's active
+    cursor: Option<Cursor>,
+    bodies: Bodies,
 
     window_resized: bool,
 }
 
 impl VideoHandler {
-    pub fn new(ctx: &Sdl) -> VideoHandler {
+    pub fn new(ctx: &Sdl) -> Result<VideoHandler, GraphicsInitError> {
+        Self::with_config(ctx, &WindowConfig::default(), GraphicsConfig::default())
+    }
+
+    /// Open the window and set up the renderer on it, failing if the machine can't run the renderer
+    pub fn with_config(
+        ctx: &Sdl,
+        window_config: &WindowConfig,
+        graphics_config: GraphicsConfig,
+    ) -> Result<VideoHandler, GraphicsInitError> {
         let video_subsystem = ctx.video().expect("Couldn't obtain SDL2 Video Subsystem");
 
-        let window = video_subsystem
-            .window("Rust Testing Grounds", 800, 600)
-            .position_centered()
-            .vulkan()
-            .resizable()
+        let (width, height) = window_config.size;
+        let mut builder = video_subsystem.window(&window_config.title, width, height);
+        builder.position_centered().vulkan();
+        if window_config.resizable {
+            builder.resizable();
+        }
+        if window_config.fullscreen {
+            builder.fullscreen();
+        }
+        if window_config.always_on_top {
+            let flags = builder.window_flags() | SDL_WindowFlags::SDL_WINDOW_ALWAYS_ON_TOP as u32;
+            builder.set_window_flags(flags);
+        }
+
+        let window = builder
             .build()
             .expect("Couldn't build SDL2 Window from Video Subsystem");
 
-        let gl_handler = GraphicsHandler::new(&window);
+        let gl_handler = GraphicsHandler::new(&window, graphics_config)?;
 
-        VideoHandler {
+        Ok(VideoHandler {
             video_subsystem,
             window,
             gl_handler,
+            cursor: None,
+            bodies: Bodies::new(),
             window_resized: false,
-        }
+        })
     }
 
     fn get_window_resized(&self) -> bool {
@@ -53,10 +137,362 @@ impl VideoHandler {
         self.gl_handler.new_sprite(texture_path, z_index)
     }
 
+    /// Create a sprite using push constants (or a uniform buffer) for its data
+    pub fn new_sprite_with_data_mode(&mut self, texture_path: &str, z_index: u8, data_mode: SpriteDataMode) -> SpriteObject {
+        self.gl_handler.new_sprite_with_data_mode(texture_path, z_index, data_mode)
+    }
+
+    /// Forget the textures loaded from files, see `GraphicsHandler::clear_texture_cache`
+    pub fn clear_texture_cache(&mut self) {
+        self.gl_handler.clear_texture_cache();
+    }
+
+    /// Create a sprite showing an already uploaded texture, like the one of a `RenderTarget`
+    pub fn new_sprite_from_texture(&mut self, texture: Texture, dimensions: Vector2<u32>, z_index: u8) -> SpriteObject {
+        self.gl_handler.new_sprite_from_texture(texture, dimensions, z_index)
+    }
+
+    /// Draw a gradient or an image behind every object, e.g. a sky
+    pub fn set_background(&mut self, background: Background) -> Result<(), TextureError> {
+        self.gl_handler.set_background(background)
+    }
+
+    pub fn clear_background(&mut self) {
+        self.gl_handler.clear_background();
+    }
+
+    /// Add an object implementing `Draw` outside the engine, like a mesh or a particle effect
+    pub fn add_custom(&mut self, obj: DrawObject<dyn Draw>) -> GraphicObject<dyn Draw> {
+        self.gl_handler.add_custom(obj)
+    }
+
+    /// Switch a sprite between sharp and smooth sampling while it's on screen
+    pub fn set_sprite_filter(&mut self, sprite: &SpriteObject, filter: TextureFilter) {
+        sprite.get_mut().set_filter(filter, &self.gl_handler);
+    }
+
+    /// Create a sprite animated by regions of a spritesheet, each shown for `frame_duration` seconds
+    pub fn new_animated_sprite(
+        &mut self,
+        texture_path: &str,
+        frames: Vec<Vector4<u32>>,
+        frame_duration: f32,
+        z_index: u8,
+    ) -> AnimatedSpriteObject {
+        self.gl_handler.new_animated_sprite(texture_path, frames, frame_duration, z_index)
+    }
+
+    /// Create a sprite animated by the frames (and their delays) of an APNG file
+    pub fn new_animated_sprite_from_file(&mut self, texture_path: &str, z_index: u8) -> Result<AnimatedSpriteObject, TextureError> {
+        self.gl_handler.new_animated_sprite_from_file(texture_path, z_index)
+    }
+
+    /// Create a text drawn with a bitmap font, `position` being its top left corner
+    pub fn new_text(&mut self, content: &str, font: &BitmapFont, position: Vector2<f32>, z_index: u8) -> TextObject {
+        self.gl_handler.new_text(content, font, position, z_index)
+    }
+
     pub fn new_rectangle(&mut self, scale: Vector2<f32>, color: Vector4<f32>, global_position: Vector2<f32>, z_index: u8) -> PrimitiveObject {
         self.gl_handler.new_rectangle(scale, color, global_position, z_index)
     }
 
+    pub fn new_circle(&mut self, radius: f32, color: Vector4<f32>, global_position: Vector2<f32>, z_index: u8) -> PrimitiveObject {
+        self.gl_handler.new_circle(radius, color, global_position, z_index)
+    }
+
+    pub fn new_circle_with_segments(&mut self, radius: f32, segments: u32, color: Vector4<f32>, global_position: Vector2<f32>, z_index: u8) -> PrimitiveObject {
+        self.gl_handler.new_circle_with_segments(radius, segments, color, global_position, z_index)
+    }
+
+    pub fn new_sdf_circle(&mut self, radius: f32, color: Vector4<f32>, global_position: Vector2<f32>, z_index: u8) -> PrimitiveObject {
+        self.gl_handler.new_sdf_circle(radius, color, global_position, z_index)
+    }
+
+    pub fn new_rounded_rectangle(&mut self, scale: Vector2<f32>, corner_radius: f32, color: Vector4<f32>, global_position: Vector2<f32>, z_index: u8) -> PrimitiveObject {
+        self.gl_handler.new_rounded_rectangle(scale, corner_radius, color, global_position, z_index)
+    }
+
+    /// Copy the state of all the sprites, e.g. for save files or replays
+    pub fn snapshot(&self) -> Vec<SpriteState> {
+        self.gl_handler.snapshot()
+    }
+
+    /// Create again the sprites of a snapshot, returning their handles
+    pub fn restore(&mut self, snapshot: &[SpriteState]) -> Vec<SpriteObject> {
+        self.gl_handler.restore(snapshot)
+    }
+
+    /// Set position, scale and rotation (in radians) of the camera at once
+    pub fn set_camera(&mut self, position: Vector2<f32>, scale: Vector2<f32>, rotation: f32) {
+        self.gl_handler.set_camera(position, scale, rotation);
+    }
+
+    /// Create objects drawn under a fixed camera instead of the main one (e.g. UI in screen space)
+    pub fn camera_scope<R>(&mut self, camera: Camera, scope: impl FnOnce(&mut GraphicsHandler) -> R) -> R {
+        self.gl_handler.camera_scope(camera, scope)
+    }
+
+    /// Create objects pinned to the screen (HUD, menus), positioned in window pixels from the top left
+    pub fn screen_space<R>(&mut self, scope: impl FnOnce(&mut GraphicsHandler) -> R) -> R {
+        self.gl_handler.screen_space(scope)
+    }
+
+    /// Clip the objects created until the matching `pop_mask` to the shape of an object, e.g. a rounded UI panel
+    pub fn push_mask<O: Draw + 'static>(&mut self, shape: &GraphicObject<O>) {
+        self.gl_handler.push_mask(shape);
+    }
+
+    pub fn pop_mask(&mut self) {
+        self.gl_handler.pop_mask();
+    }
+
+    /// Offscreen image to render a layer to, e.g. a minimap
+    pub fn new_render_target(&self, dimensions: Vector2<u32>) -> Arc<RenderTarget> {
+        self.gl_handler.new_render_target(dimensions)
+    }
+
+    /// Render the objects with a tag to a target during the next frame, from a different camera
+    /// Call it every frame the target should be updated, and draw it with `new_sprite_from_texture`
+    pub fn render_layer_to_target(&mut self, tag: &str, camera: Camera, target: &Arc<RenderTarget>) {
+        self.gl_handler.render_layer_to_target(tag, camera, target);
+    }
+
+    /// Draw a line for the next frame only, over every object
+    pub fn debug_line(&mut self, a: Vector2<f32>, b: Vector2<f32>, color: Vector4<f32>) {
+        self.gl_handler.debug_line(a, b, color);
+    }
+
+    /// Draw the outline of a rectangle for the next frame only, over every object
+    pub fn debug_rect(&mut self, center: Vector2<f32>, size: Vector2<f32>, color: Vector4<f32>) {
+        self.gl_handler.debug_rect(center, size, color);
+    }
+
+    /// Draw the outline of a circle for the next frame only, over every object
+    pub fn debug_circle(&mut self, center: Vector2<f32>, radius: f32, color: Vector4<f32>) {
+        self.gl_handler.debug_circle(center, radius, color);
+    }
+
+    /// World position under a point of the window, e.g. the mouse cursor
+    pub fn screen_to_world(&self, screen: Vector2<f32>) -> Vector2<f32> {
+        self.gl_handler.screen_to_world(screen)
+    }
+
+    /// Rotate everything drawn, for a panel mounted sideways (portrait kiosks, handhelds)
+    pub fn set_surface_transform(&mut self, rotation: Rotation) {
+        self.gl_handler.set_surface_transform(rotation);
+    }
+
+    /// Draw the world in a part of the window only, leaving the rest to screen space objects (e.g. an inspector panel)
+    pub fn set_viewport_region(&mut self, region: Option<Rect>) {
+        self.gl_handler.set_viewport_region(region);
+    }
+
+    /// Teleport the camera, cancelling its shakes (e.g. on a scene change)
+    pub fn snap_camera_to(&mut self, target: Vector2<f32>) {
+        self.gl_handler.snap_camera_to(target);
+    }
+
+    pub fn set_camera_position(&mut self, position: Vector2<f32>) {
+        self.gl_handler.set_camera_position(position);
+    }
+
+    pub fn set_camera_scale(&mut self, scale: Vector2<f32>) {
+        self.gl_handler.set_camera_scale(scale);
+    }
+
+    pub fn set_camera_rotation(&mut self, rotation: f32) {
+        self.gl_handler.set_camera_rotation(rotation);
+    }
+
+    /// Render every frame, or only when something changed
+    pub fn set_redraw_mode(&mut self, mode: RedrawMode) {
+        self.gl_handler.set_redraw_mode(mode);
+    }
+
+    pub fn get_redraw_mode(&self) -> RedrawMode {
+        self.gl_handler.get_redraw_mode()
+    }
+
+    /// Render the next frame even if nothing seems to have changed
+    pub fn request_redraw(&mut self) {
+        self.gl_handler.request_redraw();
+    }
+
+    /// Displays currently connected, e.g. to place the window on one or to scale the UI to its DPI
+    pub fn displays(&self) -> Vec<DisplayInfo> {
+        let count = match self.video_subsystem.num_video_displays() {
+            Ok(count) => count,
+            Err(e) => {
+                eprintln!("Couldn't get the number of displays: {}", e);
+                return Vec::new();
+            }
+        };
+
+        (0..count)
+            .filter_map(|index| {
+                let bounds = self.video_subsystem.display_bounds(index).ok()?;
+
+                Some(DisplayInfo {
+                    index,
+                    name: self.video_subsystem.display_name(index).unwrap_or_default(),
+                    bounds,
+                    dpi: self.video_subsystem.display_dpi(index).ok(),
+                })
+            })
+            .collect()
+    }
+
+    /// Index of the display the window is on
+    pub fn current_display(&self) -> Option<i32> {
+        self.window.display_index().ok()
+    }
+
+    /// Resolutions and refresh rates supported by the display the window is on
+    pub fn available_display_modes(&self) -> Vec<DisplayMode> {
+        let display = self.window.display_index().unwrap_or(0);
+        let count = match self.video_subsystem.num_display_modes(display) {
+            Ok(count) => count,
+            Err(e) => {
+                eprintln!("Couldn't get the display modes of display {}: {}", display, e);
+                return Vec::new();
+            }
+        };
+
+        (0..count)
+            .filter_map(|i| self.video_subsystem.display_mode(display, i).ok())
+            .collect()
+    }
+
+    /// Mode used by the window in exclusive fullscreen
+    /// In windowed mode the mode is only stored, and applied when going fullscreen
+    pub fn set_display_mode(&mut self, mode: DisplayMode) {
+        if let Err(e) = self.window.set_display_mode(mode) {
+            eprintln!("Couldn't set display mode {}x{}@{}Hz: {}", mode.w, mode.h, mode.refresh_rate, e);
+            return;
+        }
+
+        if self.window.fullscreen_state() == FullscreenType::True {
+            self.set_window_resized(true);
+        }
+    }
+
+    pub fn get_display_mode(&self) -> Option<DisplayMode> {
+        self.window.display_mode().ok()
+    }
+
+    /// Switch between windowed and exclusive fullscreen (using the mode set with `set_display_mode`)
+    pub fn set_fullscreen(&mut self, fullscreen: bool) {
+        let state = if fullscreen {
+            FullscreenType::True
+        } else {
+            FullscreenType::Off
+        };
+
+        match self.window.set_fullscreen(state) {
+            Ok(()) => self.set_window_resized(true),
+            Err(e) => eprintln!("Couldn't change fullscreen state: {}", e),
+        }
+    }
+
+    pub fn is_fullscreen(&self) -> bool {
+        self.window.fullscreen_state() != FullscreenType::Off
+    }
+
+    /// Keep the window above all the others, even when it isn't focused
+    /// Only with the `always_on_top` feature, as it needs SDL 2.0.16 or newer (`WindowConfig::always_on_top` doesn't)
+    #[cfg(feature = "always_on_top")]
+    pub fn set_always_on_top(&mut self, on_top: bool) {
+        let on_top = if on_top {
+            SDL_bool::SDL_TRUE
+        } else {
+            SDL_bool::SDL_FALSE
+        };
+
+        unsafe { SDL_SetWindowAlwaysOnTop(self.window.raw(), on_top) };
+    }
+
+    pub fn get_always_on_top(&self) -> bool {
+        self.window.window_flags() & SDL_WindowFlags::SDL_WINDOW_ALWAYS_ON_TOP as u32 != 0
+    }
+
+    /// Confine the mouse (and keyboard shortcuts, where supported) to the window
+    pub fn set_input_grab(&mut self, grab: bool) {
+        self.window.set_grab(grab);
+    }
+
+    pub fn get_input_grab(&self) -> bool {
+        self.window.grab()
+    }
+
+    /// Use a PNG image as mouse cursor, e.g. a crosshair, clicking at the `(hotspot_x, hotspot_y)` pixel of it
+    pub fn set_cursor_from_file(&mut self, path: &str, hotspot_x: i32, hotspot_y: i32) -> Result<(), String> {
+        let surface = surface_from_png(path)?;
+        let cursor = Cursor::from_surface(surface, hotspot_x, hotspot_y)?;
+
+        self.set_cursor(cursor);
+        Ok(())
+    }
+
+    /// Use one of the cursors of the system (arrow, hand, I-beam...)
+    pub fn set_system_cursor(&mut self, cursor: SystemCursor) -> Result<(), String> {
+        let cursor = Cursor::from_system(cursor)?;
+
+        self.set_cursor(cursor);
+        Ok(())
+    }
+
+    fn set_cursor(&mut self, cursor: Cursor) {
+        cursor.set();
+        // the previous cursor is freed only after it stopped being used
+        self.cursor = Some(cursor);
+    }
+
+    /// Text input of the keyboard and of the input methods, see `CtxHandler::start_text_input`
+    pub fn text_input(&self) -> TextInputUtil {
+        self.video_subsystem.text_input()
+    }
+
+    pub fn set_cursor_visible(&mut self, visible: bool) {
+        self.video_subsystem.sdl().mouse().show_cursor(visible);
+    }
+
+    pub fn is_cursor_visible(&self) -> bool {
+        self.video_subsystem.sdl().mouse().is_cursor_showing()
+    }
+
+    /// Move a sprite by velocity and gravity every update, replacing the body it already had
+    pub fn attach_body(&mut self, sprite: &SpriteObject, body: Body) {
+        self.bodies.attach(sprite, body);
+    }
+
+    pub fn detach_body(&mut self, sprite: &SpriteObject) {
+        self.bodies.detach(sprite);
+    }
+
+    /// Body attached to a sprite, e.g. to make it jump by changing its velocity
+    pub fn get_body_mut(&mut self, sprite: &SpriteObject) -> Option<&mut Body> {
+        self.bodies.get_mut(sprite)
+    }
+
+    /// Move the sprites with a body by `dt` seconds, done by the engine every frame
+    pub fn update_bodies(&mut self, dt: f32) {
+        self.bodies.integrate(dt);
+    }
+
+    /// Tag an object, to find it later with `objects_with_tag`
+    pub fn set_tag<O: Draw + 'static>(&mut self, object: &GraphicObject<O>, tag: &str) {
+        self.gl_handler.set_tag(object, tag);
+    }
+
+    pub fn remove_tag<O: Draw + 'static>(&mut self, object: &GraphicObject<O>, tag: &str) {
+        self.gl_handler.remove_tag(object, tag);
+    }
+
+    /// All the objects with a tag, e.g. to hide every "ui" object at once
+    pub fn objects_with_tag(&self, tag: &str) -> Vec<DrawObject<dyn Draw>> {
+        self.gl_handler.objects_with_tag(tag)
+    }
+
     /// Frame-by-frame update of the graphics and everything related
     pub fn update(&mut self) {
         let resized = self.get_window_resized();
@@ -65,4 +501,74 @@ impl VideoHandler {
 
         self.set_window_resized(false);
     }
+
+    /// Render a single frame outside of the engine's loop, returns whether it reached the window
+    /// e.g. right after a `GraphicsHandler::read_pixel` request, to get the pixel without waiting for the next update
+    pub fn render_once(&mut self) -> bool {
+        let resized = self.get_window_resized();
+
+        let presented = self.gl_handler.render_once(resized, &self.window);
+
+        self.set_window_resized(false);
+        presented
+    }
+
+    /// Send the data of every object to the GPU now, instead of waiting for the next update
+    pub fn flush_now(&mut self) {
+        self.gl_handler.flush_now();
+    }
+
+    /// Objects and draw calls of the last rendered frame
+    pub fn draw_stats(&self) -> DrawStats {
+        self.gl_handler.get_draw_stats()
+    }
+
+    /// Frames presented and skipped since startup
+    pub fn present_stats(&self) -> PresentStats {
+        self.gl_handler.present_stats()
+    }
+
+    /// Turn vsync on to save power, or off for uncapped presentation on high refresh rate displays
+    pub fn set_vsync(&mut self, vsync: bool) {
+        self.gl_handler.set_vsync(vsync);
+    }
+
+    pub fn get_batching(&self) -> bool {
+        self.gl_handler.get_batching()
+    }
+
+    /// Draw consecutive sprites sharing a texture in a single draw call, on by default
+    pub fn set_batching(&mut self, batching: bool) {
+        self.gl_handler.set_batching(batching);
+    }
+
+    /// Whether the last update recreated the swapchain (after a resize, or when the driver asked for it)
+    pub fn swapchain_recreated_last_frame(&self) -> bool {
+        self.gl_handler.swapchain_recreated_last_frame()
+    }
+
+    /// Wait for the last frames to be rendered before the window gets closed
+    pub fn shutdown(&mut self) {
+        self.gl_handler.wait_idle();
+    }
+}
+
+/// Largest cursor image accepted, far beyond what any system displays
+const MAX_CURSOR_DIMENSION: u32 = 512;
+
+/// Decode a PNG into an RGBA SDL Surface, e.g. to make a cursor out of it
+fn surface_from_png(path: &str) -> Result<Surface<'static>, String> {
+    let (data, dimensions) = decode_png(path, MAX_CURSOR_DIMENSION).map_err(|e| e.to_string())?;
+    let mut surface = Surface::new(dimensions.x, dimensions.y, PixelFormatEnum::RGBA32)?;
+
+    // rows of the surface may be padded
+    let row_size = dimensions.x as usize * 4;
+    let pitch = surface.pitch() as usize;
+    surface.with_lock_mut(|pixels| {
+        for (y, row) in data.chunks_exact(row_size).enumerate() {
+            pixels[y * pitch..y * pitch + row_size].copy_from_slice(row);
+        }
+    });
+
+    Ok(surface)
 }
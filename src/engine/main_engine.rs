@@ -1,8 +1,14 @@
 // standard imports
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+// SDL2 imports
+use sdl2::keyboard::Keycode;
+use sdl2::Sdl;
 
 // import the ctx mdule
-use super::ctx::CtxHandler;
+use super::ctx::vulkan::{DevicePreference, GraphicsConfig, GraphicsInitError};
+use super::ctx::{AudioConfig, CtxHandler, WindowConfig};
 
 // other imports
 
@@ -11,14 +17,177 @@ pub struct Engine {
     ctx_handler: CtxHandler,
 }
 
+/// Options of the window, renderer, audio and framerate, set before creating the Engine
+/// e.g. `EngineBuilder::default().title("Game").size(1280, 720).vsync(true).build()?`
+/// There's no MSAA option: the render pass draws single sampled, smooth edges come from the SDF shapes
+/// (`new_sdf_circle`, `new_rounded_rectangle`) and from the linear filtering of the textures
+#[derive(Clone, Debug)]
+pub struct EngineBuilder {
+    window: WindowConfig,
+    graphics: GraphicsConfig,
+    audio: AudioConfig,
+    target_fps: u16,
+    /// SDL hints set before the context and its subsystems are initialized
+    hints: Vec<(String, String)>,
+}
+
+impl Default for EngineBuilder {
+    fn default() -> Self {
+        Self {
+            window: WindowConfig::default(),
+            graphics: GraphicsConfig::default(),
+            audio: AudioConfig::default(),
+            target_fps: 60,
+            hints: Vec::new(),
+        }
+    }
+}
+
+impl EngineBuilder {
+    pub fn title(mut self, title: &str) -> Self {
+        self.window.title = title.to_string();
+        self
+    }
+
+    /// Size of the window (in pixels)
+    pub fn size(mut self, width: u32, height: u32) -> Self {
+        self.window.size = (width, height);
+        self
+    }
+
+    pub fn resizable(mut self, resizable: bool) -> Self {
+        self.window.resizable = resizable;
+        self
+    }
+
+    /// Start in exclusive fullscreen, at the desktop resolution
+    pub fn fullscreen(mut self, fullscreen: bool) -> Self {
+        self.window.fullscreen = fullscreen;
+        self
+    }
+
+    /// Keep the window above all the others, even when it isn't focused
+    pub fn always_on_top(mut self, always_on_top: bool) -> Self {
+        self.window.always_on_top = always_on_top;
+        self
+    }
+
+    pub fn vsync(mut self, vsync: bool) -> Self {
+        self.graphics.vsync = vsync;
+        self
+    }
+
+    /// Keep the compiled pipelines in a file to start faster next time, see `GraphicsConfig::user_pipeline_cache_path`
+    /// Off by default, so the engine doesn't write files nobody asked for
+    pub fn pipeline_cache(mut self, path: Option<PathBuf>) -> Self {
+        self.graphics.pipeline_cache_path = path;
+        self
+    }
+
+    /// Kind of GPU to use when more than one is available
+    pub fn device_preference(mut self, preference: DevicePreference) -> Self {
+        self.graphics.device_preference = preference;
+        self
+    }
+
+    /// Audio sample rate (in Hz)
+    pub fn sample_rate(mut self, frequency: i32) -> Self {
+        self.audio.frequency = frequency;
+        self
+    }
+
+    /// Audio sample format, one of the `sdl2::mixer::AUDIO_*` constants
+    pub fn audio_format(mut self, format: u16) -> Self {
+        self.audio.format = format;
+        self
+    }
+
+    /// Audio output channels, 1 for mono and 2 for stereo
+    pub fn audio_channels(mut self, channels: i32) -> Self {
+        self.audio.channels = channels;
+        self
+    }
+
+    /// Number of sound effects that can play at the same time
+    pub fn mixing_channels(mut self, channels: i32) -> Self {
+        self.audio.mixing_channels = channels;
+        self
+    }
+
+    /// Framerate limit of the main loop
+    pub fn target_fps(mut self, fps: u16) -> Self {
+        self.target_fps = fps;
+        self
+    }
+
+    /// SDL hint (e.g. `SDL_MOUSE_FOCUS_CLICKTHROUGH`, `SDL_IME_SHOW_UI`) set before SDL gets initialized,
+    /// see `CtxHandler::set_hint`
+    pub fn hint(mut self, name: &str, value: &str) -> Self {
+        self.hints.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    fn apply_hints(&self) {
+        for (name, value) in self.hints.iter() {
+            if !CtxHandler::set_hint(name, value) {
+                eprintln!("Couldn't set SDL hint \'{}\' to \'{}\'", name, value);
+            }
+        }
+    }
+
+    /// Fails if the machine can't run the renderer, e.g. without a GPU supporting Vulkan
+    pub fn build(self) -> Result<Engine, GraphicsInitError> {
+        self.apply_hints();
+
+        let ctx_handler =
+            CtxHandler::with_config(&self.window, self.graphics, &self.audio, self.target_fps)?;
+
+        Ok(Engine::from_ctx_handler(ctx_handler))
+    }
+
+    /// Build the Engine on an SDL context owned by the host application, see `CtxHandler::from_sdl`
+    /// The hints are set after the host created the context: only the ones read by the subsystems the engine opens apply
+    pub fn build_with_sdl(self, sdl: Sdl) -> Result<Engine, GraphicsInitError> {
+        self.apply_hints();
+
+        let ctx_handler = CtxHandler::from_sdl_with_config(
+            sdl,
+            &self.window,
+            self.graphics,
+            &self.audio,
+            self.target_fps,
+        )?;
+
+        Ok(Engine::from_ctx_handler(ctx_handler))
+    }
+}
+
 impl Engine {
-    /// Engine init process
-    pub fn new() -> Self {
-        let ctx_handler = CtxHandler::new();
+    /// Engine init process, with the default options (see `EngineBuilder` to change them)
+    /// The error explains why the renderer couldn't start, to show it to the player
+    pub fn new() -> Result<Self, GraphicsInitError> {
+        EngineBuilder::default().build()
+    }
+
+    /// Engine init process on an SDL context owned by a host application (editors, launchers)
+    pub fn from_sdl(sdl: Sdl) -> Result<Self, GraphicsInitError> {
+        EngineBuilder::default().build_with_sdl(sdl)
+    }
 
+    fn from_ctx_handler(ctx_handler: CtxHandler) -> Self {
         Self { ctx_handler }
     }
 
+    /// Time spent creating the pipelines at startup, see `GraphicsHandler::get_pipeline_warmup`
+    pub fn get_pipeline_warmup(&self) -> Duration {
+        self.ctx_handler.video.gl_handler.get_pipeline_warmup()
+    }
+
+    /// Window, renderer, audio and input of the engine, e.g. to add objects with their own `Draw` implementation
+    pub fn get_ctx_handler(&mut self) -> &mut CtxHandler {
+        &mut self.ctx_handler
+    }
+
     /// Main function to run the program
     pub fn run(&mut self) {
         if self
@@ -42,6 +211,9 @@ impl Engine {
 
         let _rect = self.ctx_handler.video.new_rectangle((100.0, 100.0).into(), (0.0, 0.0, 1.0, 1.0).into(), (200.0, 200.0).into(), 2);
 
+        // sprites added with S to stress the renderer, B switches batching to compare the render times
+        let mut crowd = Vec::new();
+
         let mut i = 0.0;
         'mainloop: loop {
             self.ctx_handler.check_events();
@@ -51,24 +223,62 @@ impl Engine {
 
             i += 2.0;
             {
-                self.ctx_handler.video.gl_handler.camera_scale.y = 1.0 - (i / 1000.0);
+                self.ctx_handler
+                    .video
+                    .set_camera_scale((1.0, 1.0 - (i / 1000.0)).into());
+
+                // move the sprite with the arrow keys
+                let speed = 200.0 * self.ctx_handler.time().delta;
+                let ctx_handler = &self.ctx_handler;
+                python.update(|sprite| {
+                    if ctx_handler.is_key_pressed(Keycode::Left) {
+                        sprite.global_position.x -= speed;
+                    }
+                    if ctx_handler.is_key_pressed(Keycode::Right) {
+                        sprite.global_position.x += speed;
+                    }
+                    if ctx_handler.is_key_pressed(Keycode::Up) {
+                        sprite.global_position.y -= speed;
+                    }
+                    if ctx_handler.is_key_pressed(Keycode::Down) {
+                        sprite.global_position.y += speed;
+                    }
+                    sprite.set_opacity(1.0 - (i / 255.0));
+                });
+            }
 
-                let mut sprite = python.get_mut();
-                sprite.global_position.x = i;
-                sprite.color = cgmath::Vector4::new(1.0, 1.0, 1.0, 1.0 - (i / 255.0));
+            if self.ctx_handler.key_just_pressed(Keycode::S) {
+                for n in 0..1000 {
+                    let sprite = self.ctx_handler.video.new_sprite("assets/rust.png", 0);
+                    sprite.update(|sprite| {
+                        sprite.scale = (0.1, 0.1).into();
+                        sprite.global_position = ((n % 40) as f32 * 40.0 - 800.0, (n / 40) as f32 * 40.0 - 500.0).into();
+                    });
+                    crowd.push(sprite);
+                }
+            }
+            if self.ctx_handler.key_just_pressed(Keycode::B) {
+                let batching = self.ctx_handler.video.get_batching();
+                self.ctx_handler.video.set_batching(!batching);
             }
 
+            self.ctx_handler.assets.update(&self.ctx_handler.video.gl_handler);
+            self.ctx_handler.video.update_bodies(self.ctx_handler.time().delta);
             self.ctx_handler.video.update();
+            self.ctx_handler.audio.update(self.ctx_handler.time().unscaled_delta);
 
             self.ctx_handler.wait();
 
-            println!("{}", self.ctx_handler.get_current_framerate());
+            let stats = self.ctx_handler.video.draw_stats();
+            println!(
+                "{} fps, {} sprites in {} draw calls, rendered in {:?}",
+                self.ctx_handler.get_current_framerate(),
+                stats.objects,
+                stats.draw_calls,
+                stats.render_time
+            );
         }
-    }
-}
 
-impl Default for Engine {
-    fn default() -> Self {
-        Self::new()
+        self.ctx_handler.shutdown();
     }
 }
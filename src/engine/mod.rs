@@ -1,4 +1,8 @@
 mod main_engine;
 mod ctx;
+pub mod spatial;
 
-pub use main_engine::Engine;
+pub use ctx::vulkan::{DevicePreference, GraphicsHandler, GraphicsInitError, IndexBuffer, SwapchainHandler, Vertex};
+pub use ctx::draw_objects::draw;
+pub use ctx::{CtxHandler, Draw, DrawFlags, DrawObject, GraphicObject, VideoHandler};
+pub use main_engine::{Engine, EngineBuilder};
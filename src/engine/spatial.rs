@@ -0,0 +1,244 @@
+//! Uniform grid bucketing objects by their bounding boxes, for collision and proximity queries
+
+// standard imports
+use std::collections::{HashMap, HashSet};
+use std::hash::Hash;
+
+// other imports
+use cgmath::Vector2;
+
+/// Axis aligned bounding box
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Aabb {
+    pub min: Vector2<f32>,
+    pub max: Vector2<f32>,
+}
+
+impl Aabb {
+    pub fn new(min: Vector2<f32>, max: Vector2<f32>) -> Self {
+        Self { min, max }
+    }
+
+    /// Box of size `scale` centered on `position`, like the objects drawn by the engine
+    pub fn from_center(position: Vector2<f32>, scale: Vector2<f32>) -> Self {
+        let half = scale / 2.0;
+        Self::new(position - half, position + half)
+    }
+
+    pub fn intersects(&self, other: &Aabb) -> bool {
+        self.min.x <= other.max.x
+            && self.max.x >= other.min.x
+            && self.min.y <= other.max.y
+            && self.max.y >= other.min.y
+    }
+
+    /// Box grown by `amount` in every direction
+    pub fn expand(&self, amount: f32) -> Aabb {
+        let amount = Vector2::new(amount, amount);
+        Aabb::new(self.min - amount, self.max + amount)
+    }
+
+    /// Shortest distance between the borders of two boxes, 0 when they overlap
+    pub fn distance(&self, other: &Aabb) -> f32 {
+        let dx = (other.min.x - self.max.x).max(self.min.x - other.max.x).max(0.0);
+        let dy = (other.min.y - self.max.y).max(self.min.y - other.max.y).max(0.0);
+
+        (dx * dx + dy * dy).sqrt()
+    }
+}
+
+/// Grid of square cells holding the ids of the objects overlapping them
+pub struct SpatialGrid<Id: Copy + Eq + Hash> {
+    cell_size: f32,
+    cells: HashMap<(i32, i32), Vec<Id>>,
+    objects: HashMap<Id, Aabb>,
+}
+
+impl<Id: Copy + Eq + Hash> SpatialGrid<Id> {
+    /// Cells should be about the size of the common objects: too small and big objects fill many cells,
+    /// too big and queries check many objects
+    pub fn new(cell_size: f32) -> Self {
+        assert!(cell_size > 0.0, "The cells of a SpatialGrid must have a positive size");
+
+        Self {
+            cell_size,
+            cells: HashMap::new(),
+            objects: HashMap::new(),
+        }
+    }
+
+    pub fn get_cell_size(&self) -> f32 {
+        self.cell_size
+    }
+
+    pub fn len(&self) -> usize {
+        self.objects.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.objects.is_empty()
+    }
+
+    /// Remove all the objects, to rebuild the grid from scratch
+    pub fn clear(&mut self) {
+        self.cells.clear();
+        self.objects.clear();
+    }
+
+    /// Add an object, or move it if the id is already in the grid
+    pub fn insert(&mut self, id: Id, bounds: Aabb) {
+        if let Some(old_bounds) = self.objects.get(&id).copied() {
+            // nothing to move when the object stays in the same cells
+            if self.cell_range(&old_bounds) == self.cell_range(&bounds) {
+                self.objects.insert(id, bounds);
+                return;
+            }
+            self.remove(id);
+        }
+
+        for cell in self.cells_of(&bounds) {
+            self.cells.entry(cell).or_insert_with(Vec::new).push(id);
+        }
+        self.objects.insert(id, bounds);
+    }
+
+    pub fn remove(&mut self, id: Id) -> Option<Aabb> {
+        let bounds = self.objects.remove(&id)?;
+
+        for cell in self.cells_of(&bounds) {
+            if let Some(ids) = self.cells.get_mut(&cell) {
+                ids.retain(|&i| i != id);
+                if ids.is_empty() {
+                    self.cells.remove(&cell);
+                }
+            }
+        }
+
+        Some(bounds)
+    }
+
+    pub fn get_bounds(&self, id: Id) -> Option<Aabb> {
+        self.objects.get(&id).copied()
+    }
+
+    /// Ids of all the objects overlapping a region
+    pub fn query(&self, region: Aabb) -> Vec<Id> {
+        let mut seen = HashSet::new();
+        let mut found = Vec::new();
+
+        for cell in self.cells_of(&region) {
+            for &id in self.cells.get(&cell).into_iter().flatten() {
+                if seen.insert(id) && self.objects[&id].intersects(&region) {
+                    found.push(id);
+                }
+            }
+        }
+
+        found
+    }
+
+    /// Ids of the objects within `radius` of the borders of an object (the object itself excluded)
+    pub fn neighbors(&self, id: Id, radius: f32) -> Vec<Id> {
+        let bounds = match self.objects.get(&id) {
+            Some(bounds) => *bounds,
+            None => return Vec::new(),
+        };
+
+        self.query(bounds.expand(radius))
+            .into_iter()
+            .filter(|&other| other != id && self.objects[&other].distance(&bounds) <= radius)
+            .collect()
+    }
+
+    /// First and last cell (inclusive) covered by a box
+    fn cell_range(&self, bounds: &Aabb) -> ((i32, i32), (i32, i32)) {
+        let to_cell = |v: f32| (v / self.cell_size).floor() as i32;
+
+        (
+            (to_cell(bounds.min.x), to_cell(bounds.min.y)),
+            (to_cell(bounds.max.x), to_cell(bounds.max.y)),
+        )
+    }
+
+    fn cells_of(&self, bounds: &Aabb) -> impl Iterator<Item = (i32, i32)> {
+        let ((min_x, min_y), (max_x, max_y)) = self.cell_range(bounds);
+
+        (min_x..=max_x).flat_map(move |x| (min_y..=max_y).map(move |y| (x, y)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn aabb(min_x: f32, min_y: f32, max_x: f32, max_y: f32) -> Aabb {
+        Aabb::new(Vector2::new(min_x, min_y), Vector2::new(max_x, max_y))
+    }
+
+    fn sorted(mut ids: Vec<u32>) -> Vec<u32> {
+        ids.sort_unstable();
+        ids
+    }
+
+    #[test]
+    fn query_finds_boxes_spanning_cells_and_negative_coordinates() {
+        let mut grid = SpatialGrid::new(10.0);
+        // covers cells -2..=1 on both axes
+        grid.insert(1, aabb(-15.0, -15.0, 15.0, 15.0));
+        grid.insert(2, aabb(-25.0, -25.0, -21.0, -21.0));
+        grid.insert(3, aabb(30.0, 30.0, 35.0, 35.0));
+
+        assert_eq!(grid.query(aabb(12.0, 12.0, 13.0, 13.0)), vec![1]);
+        assert_eq!(grid.query(aabb(-14.0, 5.0, -13.0, 6.0)), vec![1]);
+        assert_eq!(sorted(grid.query(aabb(-30.0, -30.0, -14.0, -14.0))), vec![1, 2]);
+        // same cell as 2, but not overlapping it
+        assert!(grid.query(aabb(-29.0, -29.0, -28.0, -28.0)).is_empty());
+    }
+
+    #[test]
+    fn insert_moves_an_object_without_stale_cells() {
+        let mut grid = SpatialGrid::new(10.0);
+        grid.insert(1, aabb(1.0, 1.0, 2.0, 2.0));
+        grid.insert(1, aabb(-19.0, 41.0, -18.0, 42.0));
+
+        assert_eq!(grid.len(), 1);
+        assert!(grid.query(aabb(0.0, 0.0, 5.0, 5.0)).is_empty());
+        assert_eq!(grid.query(aabb(-20.0, 40.0, -10.0, 50.0)), vec![1]);
+        assert_eq!(grid.cells.len(), 1);
+        assert_eq!(grid.cells[&(-2, 4)], vec![1]);
+    }
+
+    #[test]
+    fn remove_drops_empty_cells() {
+        let mut grid = SpatialGrid::new(10.0);
+        grid.insert(1, aabb(-5.0, -5.0, 5.0, 5.0));
+        grid.insert(2, aabb(1.0, 1.0, 2.0, 2.0));
+
+        assert_eq!(grid.remove(1), Some(aabb(-5.0, -5.0, 5.0, 5.0)));
+        assert_eq!(grid.remove(1), None);
+        // only the cell still holding 2 is left
+        assert_eq!(grid.cells.len(), 1);
+        assert_eq!(grid.cells[&(0, 0)], vec![2]);
+
+        grid.remove(2);
+        assert!(grid.cells.is_empty());
+        assert!(grid.is_empty());
+    }
+
+    #[test]
+    fn neighbors_exclude_self_and_objects_beyond_the_radius() {
+        let mut grid = SpatialGrid::new(10.0);
+        grid.insert(1, aabb(0.0, 0.0, 2.0, 2.0));
+        // 3 units to the right of 1
+        grid.insert(2, aabb(5.0, 0.0, 6.0, 2.0));
+        // 3 units right and 4 units down: 5 units away from the corner of 1
+        grid.insert(3, aabb(5.0, 6.0, 6.0, 7.0));
+
+        assert_eq!(grid.neighbors(1, 1.0), Vec::<u32>::new());
+        assert_eq!(grid.neighbors(1, 3.0), vec![2]);
+        // 3 is in the expanded box with a radius of 4.5, but farther than that from 1
+        assert_eq!(grid.neighbors(1, 4.5), vec![2]);
+        assert_eq!(sorted(grid.neighbors(1, 5.0)), vec![2, 3]);
+        assert!(grid.neighbors(4, 5.0).is_empty());
+    }
+}
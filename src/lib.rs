@@ -1,3 +1,3 @@
 #[allow(dead_code)]
 pub mod engine;
-pub use engine::Engine;
+pub use engine::{Engine, EngineBuilder, GraphicsInitError};
@@ -1,6 +1,13 @@
 use pholidota::Engine;
 
 fn main() {
-    let mut main_engine = Engine::new(); // create the Engine instance
-    main_engine.run();                   // run the engine main function
+    // create the Engine instance, the renderer can't start on every machine
+    let mut main_engine = match Engine::new() {
+        Ok(engine) => engine,
+        Err(e) => {
+            eprintln!("Couldn't start the engine: {}", e);
+            std::process::exit(1);
+        }
+    };
+    main_engine.run(); // run the engine main function
 }